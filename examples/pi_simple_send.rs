@@ -31,17 +31,13 @@ fn main() {
     let mut lora = sx127x_lora::LoRa::new(spi, cs, reset, FREQUENCY, Delay)
         .expect("Failed to communicate with radio module!");
 
-    lora.set_tx_power(17, 1); //Using PA_BOOST. See your board for correct pin.
+    lora.set_tx_power(17); //Using PA_BOOST by default. See your board for correct pin.
 
     let message = "Hello, world!";
-    let mut buffer = [0; 255];
-    for (i, c) in message.chars().enumerate() {
-        buffer[i] = c as u8;
-    }
 
-    let transmit = lora.transmit_payload(buffer, message.len());
-    match transmit {
-        Ok(packet_size) => println!("Sent packet with size: {}", packet_size),
-        Err(()) => println!("Error"),
+    // Any byte slice of 1 to 255 bytes, e.g. a heapless::Vec of any capacity
+    match lora.transmit_payload(message.as_bytes()) {
+        Ok(()) => println!("Sent packet with size: {}", message.len()),
+        Err(_) => println!("Error"),
     }
 }
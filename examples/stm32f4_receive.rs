@@ -63,7 +63,7 @@ fn main() -> ! {
                 hprintln!(
                     "New Packet with size {} and RSSI: {}",
                     size,
-                    lora.get_packet_rssi()
+                    lora.get_last_packet_rssi()
                 )
                 .unwrap();
                 let buffer = lora.read_packet(); // Received buffer. NOTE: 255 bytes are always returned
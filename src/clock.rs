@@ -0,0 +1,49 @@
+//! Time sources for the blocking functions with a deadline.
+
+use embedded_hal::blocking::delay::DelayMs;
+
+/// A monotonic millisecond clock, e.g. the tick counter of an application. Blocking functions
+/// taking a `Clock` compare `now_ms` against a deadline instead of counting delays, so the time
+/// spent on SPI transfers between polls is accounted for.
+pub trait Clock {
+    /// Returns the current time in milliseconds. Must never go backwards.
+    fn now_ms(&mut self) -> u64;
+
+    /// Waits about `ms` milliseconds between two polls of the radio. The default implementation
+    /// returns immediately, polling the radio as fast as possible.
+    fn wait_ms(&mut self, ms: u8) {
+        let _ = ms;
+    }
+}
+
+/// Adapts a `DelayMs` into a `Clock` whose time is the sum of the delays waited through it. This is
+/// how the functions taking a `timeout_ms` and a delay are implemented.
+pub struct DelayClock<'a> {
+    delay: &'a mut dyn DelayMs<u8>,
+    elapsed_ms: u64,
+}
+
+impl<'a> DelayClock<'a> {
+    pub fn new(delay: &'a mut dyn DelayMs<u8>) -> Self {
+        DelayClock {
+            delay,
+            elapsed_ms: 0,
+        }
+    }
+}
+
+impl Clock for DelayClock<'_> {
+    fn now_ms(&mut self) -> u64 {
+        self.elapsed_ms
+    }
+
+    fn wait_ms(&mut self, ms: u8) {
+        self.delay.delay_ms(ms);
+        self.elapsed_ms += u64::from(ms);
+    }
+}
+
+/// Returns the deadline `timeout_ms` from now, negative timeouts expiring immediately.
+pub(crate) fn deadline(clock: &mut impl Clock, timeout_ms: Option<i32>) -> Option<u64> {
+    timeout_ms.map(|timeout_ms| clock.now_ms() + timeout_ms.max(0) as u64)
+}
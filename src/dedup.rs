@@ -0,0 +1,78 @@
+//! Filtering of duplicate packets, e.g. the retransmissions of a flooding mesh.
+//!
+//! `DedupFilter` remembers the IDs of the last `N` packets in a fixed size ring buffer owned by the
+//! application, without allocating. The ID of a packet is taken from its payload by a function of
+//! the application, e.g. `leading_u16` for an ID in the first two bytes. See
+//! `LoRa::receive_dedup_into`.
+
+/// Returns the first two bytes of `packet` as a big endian ID, or `None` if it is shorter.
+pub fn leading_u16(packet: &[u8]) -> Option<u32> {
+    match packet {
+        [high, low, ..] => Some(u32::from(u16::from_be_bytes([*high, *low]))),
+        _ => None,
+    }
+}
+
+/// Ring buffer of the IDs of the last `N` packets. Once full, each new ID replaces the oldest one.
+pub struct DedupFilter<const N: usize> {
+    ids: [u32; N],
+    next: usize,
+    len: usize,
+    key: fn(&[u8]) -> Option<u32>,
+}
+
+impl<const N: usize> DedupFilter<N> {
+    /// Creates a filter taking the ID of a packet from its payload with `key`. Packets for which
+    /// `key` returns `None` are never considered duplicates.
+    pub const fn new(key: fn(&[u8]) -> Option<u32>) -> Self {
+        DedupFilter {
+            ids: [0; N],
+            next: 0,
+            len: 0,
+            key,
+        }
+    }
+
+    /// Returns true if the ID of `packet` was seen among the last `N` packets, remembering it
+    /// otherwise.
+    pub fn is_duplicate(&mut self, packet: &[u8]) -> bool {
+        match (self.key)(packet) {
+            Some(id) => !self.insert(id),
+            None => false,
+        }
+    }
+
+    /// Remembers `id`, dropping the oldest ID if the buffer is full. Returns false if it was
+    /// already there, in which case nothing changes.
+    pub fn insert(&mut self, id: u32) -> bool {
+        if N == 0 {
+            return true;
+        }
+        if self.contains(id) {
+            return false;
+        }
+        self.ids[self.next] = id;
+        self.next = (self.next + 1) % N;
+        self.len = (self.len + 1).min(N);
+        true
+    }
+
+    /// Returns true if `id` is among the last `N` IDs.
+    pub fn contains(&self, id: u32) -> bool {
+        self.ids[..self.len].contains(&id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Forgets all IDs.
+    pub fn clear(&mut self) {
+        self.next = 0;
+        self.len = 0;
+    }
+}
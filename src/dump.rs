@@ -0,0 +1,35 @@
+//! Register dumps for field debugging, see `LoRa::dump_registers`.
+
+use core::fmt::Write;
+
+/// Length of a register dump, registers 0x00 to 0x45 of the LoRa page.
+pub const REGISTER_DUMP_LEN: usize = 0x46;
+
+/// Registers left out of a dump, and left at `0` in it, because reading them has side effects.
+/// Reading RegFifo (0x00) advances RegFifoAddrPtr, which would corrupt a packet being read or
+/// written. No other register of the page has read side effects, but the status registers, e.g.
+/// RegRssiValue or RegModemStat, change all the time.
+pub const SKIPPED_REGISTERS: [u8; 1] = [0x00];
+
+/// Writes `dump` as lines of 16 registers in hex, each line starting with the address of its first
+/// register, skipped registers being shown as `--`. E.g.
+///
+/// ```text
+/// 00: -- 81 00 00 00 00 e4 c0 00 4f 09 2b 23 00 80 00
+/// ...
+/// ```
+pub fn write_register_dump(out: &mut impl Write, dump: &[u8; REGISTER_DUMP_LEN]) -> core::fmt::Result {
+    for (line, registers) in dump.chunks(16).enumerate() {
+        write!(out, "{:02x}:", line * 16)?;
+        for (offset, value) in registers.iter().enumerate() {
+            let addr = (line * 16 + offset) as u8;
+            if SKIPPED_REGISTERS.contains(&addr) {
+                out.write_str(" --")?;
+            } else {
+                write!(out, " {:02x}", value)?;
+            }
+        }
+        out.write_char('\n')?;
+    }
+    Ok(())
+}
@@ -0,0 +1,36 @@
+//! Conversions between frequencies in Hz and the Frf register, in synthesizer steps of
+//! FSTEP = FXOSC / 2^19 (4.1.4. Frequency Settings). All frequency math of the driver goes
+//! through here.
+
+/// Largest value of the 24 bit Frf register (RegFrfMsb, RegFrfMid, RegFrfLsb).
+pub const FRF_MAX: u32 = 0xff_ffff;
+
+/// Returns the Frf value of `hz` with a crystal of `fxosc` Hz, rounded down, i.e. the frequency
+/// actually programmed is the closest multiple of FSTEP at or below `hz`. The result is only valid
+/// up to `FRF_MAX`, larger frequencies saturating at `u32::MAX`.
+pub const fn frf_from_hz(hz: u64, fxosc: u32) -> u32 {
+    let frf = ((hz as u128) << 19) / fxosc as u128;
+    if frf > u32::MAX as u128 {
+        u32::MAX
+    } else {
+        frf as u32
+    }
+}
+
+/// Returns the frequency in Hz programmed by `frf` with a crystal of `fxosc` Hz, rounded down.
+pub const fn hz_from_frf(frf: u32, fxosc: u32) -> u64 {
+    (frf as u64 * fxosc as u64) >> 19
+}
+
+/// Returns the frequency error in Hz of a packet from the 20 bit signed FreqError value of RegFei,
+/// received with a bandwidth of `bw` Hz and a crystal of `fxosc` Hz:
+/// `FreqError * 2^24 / FXOSC * BW / 500 kHz` (4.1.5. Frequency Error Indication).
+pub const fn frequency_error_hz(freq_error: i32, bw: i64, fxosc: u32) -> i64 {
+    freq_error as i64 * (1 << 24) * bw / (fxosc as i64 * 500_000)
+}
+
+/// Returns the frequency in Hz of `steps` synthesizer steps with a crystal of `fxosc` Hz, rounded
+/// towards zero, e.g. the FSK AFC correction of RegAfc.
+pub const fn hz_from_fsteps(steps: i32, fxosc: u32) -> i64 {
+    steps as i64 * fxosc as i64 / (1 << 19)
+}
@@ -0,0 +1,23 @@
+//! Settings for talking to radios driven by other libraries.
+//!
+//! Two LoRa radios only hear each other if all of the settings below match. The frequency must
+//! match as well, within a fraction of the bandwidth.
+//!
+//! | Setting          | Function                 | arduino-LoRa default | RadioHead RH_RF95 default |
+//! |------------------|--------------------------|----------------------|---------------------------|
+//! | Spreading factor | `set_spreading_factor`   | 7                    | 7                         |
+//! | Bandwidth        | `set_signal_bandwidth`   | 125 kHz              | 125 kHz                   |
+//! | Coding rate      | `set_coding_rate_4`      | 4/5                  | 4/5                       |
+//! | Sync word        | `set_sync_word`          | `0x12`               | `0x12`                    |
+//! | Header mode      | `explicit_header`        | explicit             | explicit                  |
+//! | Preamble length  | `set_preamble_length`    | 8                    | 8                         |
+//! | IQ inversion     | `set_invert_iq`          | off                  | off                       |
+//! | Framing          |                          | payload only         | 4 byte header, see `radiohead` |
+//!
+//! The CRC doesn't need to match in explicit header mode, the header telling the receiver whether
+//! one follows the payload. arduino-LoRa sends without CRC unless `enableCrc` is called, and
+//! drops packets with a wrong CRC only if it was.
+//!
+//! `ModemPreset::ArduinoLoRa` applies the arduino-LoRa column with the CRC on. Payloads sent with
+//! `transmit_payload` are then received by `LoRa.parsePacket` byte for byte, and the other way
+//! around with `read_packet`.
@@ -1,7 +1,7 @@
-#![allow(unused_assignments)]
 #![no_std]
 #![crate_type = "lib"]
 #![crate_name = "sx127x_lora"]
+#![allow(clippy::type_complexity)]
 
 //! # sx127x_lora
 //!  A platform-agnostic driver for Semtech SX1276/77/78/79 based boards. It supports any device that
@@ -12,7 +12,7 @@
 //! # Examples
 //! ## Raspberry Pi Basic Send
 //! Utilizes a Raspberry Pi to send a message. The example utilizes the `linux_embedded_hal` crate.
-//! ```no_run
+//! ```ignore
 //! #![feature(extern_crate_item_prelude)]
 //! extern crate sx127x_lora;
 //! extern crate linux_embedded_hal as hal;
@@ -48,18 +48,14 @@
 //!         spi, cs, reset,  FREQUENCY, Delay)
 //!         .expect("Failed to communicate with radio module!");
 //!
-//!     lora.set_tx_power(17,1); //Using PA_BOOST. See your board for correct pin.
+//!     lora.set_tx_power(17); //Using PA_BOOST by default. See your board for correct pin.
 //!
 //!     let message = "Hello, world!";
-//!     let mut buffer = [0;255];
-//!     for (i,c) in message.chars().enumerate() {
-//!         buffer[i] = c as u8;
-//!     }
 //!
-//!     let transmit = lora.transmit_payload(buffer,message.len());
-//!     match transmit {
-//!         Ok(packet_size) => println!("Sent packet with size: {}", packet_size),
-//!         Err(()) => println!("Error"),
+//!     // Any byte slice of 1 to 255 bytes, e.g. a heapless::Vec of any capacity
+//!     match lora.transmit_payload(message.as_bytes()) {
+//!         Ok(()) => println!("Sent packet with size: {}", message.len()),
+//!         Err(_) => println!("Error"),
 //!     }
 //! }
 //! ```
@@ -67,7 +63,7 @@
 //! Utilizes a STM32F429 to receive data using the blocking `poll_irq(timeout)` function. It prints
 //! the received packet back out over semihosting. The example utilizes the `stm32f429_hal`, `cortex_m`,
 //! and `panic_semihosting` crates.
-//! ```no_run
+//! ```ignore
 //! #![no_std]
 //! #![no_main]
 //!
@@ -145,16 +141,38 @@
 //! support is available in `embedded-hal`, then this will be added. It is possible to implement this function on a
 //! device-to-device basis by retrieving a packet with the `read_packet()` function.
 
+use core::ops::ControlFlow;
+
 use bit_field::BitField;
-use embedded_hal::blocking::delay::DelayMs;
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
 use embedded_hal::blocking::spi::{Transfer, Write};
-use embedded_hal::digital::v2::OutputPin;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
 use embedded_hal::spi::{Mode, Phase, Polarity};
-use heapless;
+use embedded_hal::timer::CountDown;
 use bitflags::bitflags;
 
+pub mod clock;
+pub mod dedup;
+pub mod dump;
+pub mod frf;
+pub mod interop;
+pub mod link_stats;
+pub mod radiohead;
 pub mod register;
+pub mod stream;
+pub mod verify;
+#[cfg(test)]
+mod mock;
+#[cfg(test)]
+mod tests;
+use self::clock::{deadline, Clock, DelayClock};
+use self::dedup::DedupFilter;
+use self::dump::{REGISTER_DUMP_LEN, SKIPPED_REGISTERS};
+use self::frf::{frequency_error_hz, frf_from_hz, hz_from_frf, hz_from_fsteps, FRF_MAX};
+use self::link_stats::{LinkSample, LinkStats};
+use self::radiohead::{RadioHead, RhHeader, RH_MAX_MESSAGE_LEN};
 use self::register::*;
+use self::verify::{ConfigShadow, RegisterMismatch};
 
 /// Provides the necessary SPI mode configuration for the radio
 pub const MODE: Mode = Mode {
@@ -162,16 +180,231 @@ pub const MODE: Mode = Mode {
     polarity: Polarity::IdleHigh,
 };
 
-/*pub struct LoRaBuilder {}
+/// Builds a `LoRa` instance with non-default construction options. `LoRa::new` is equivalent to
+/// `LoRaBuilder::new(spi, cs, reset, frequency).build(delay)`.
+pub struct LoRaBuilder<SPI, CS, RESET>
+{
+    spi: SPI,
+    cs: CS,
+    reset: RESET,
+    frequency_hz: u64,
+    reset_delay_ms: u8,
+    pa_output: PaOutput,
+    fxosc_hz: u32,
+    spi_chunk_len: usize,
+    init: InitMode,
+    version_check: VersionCheck,
+}
+
+/// What `LoRaBuilder::build` accepts as the value of RegVersion.
+#[derive(Clone, Copy)]
+enum VersionCheck {
+    /// A supported chip, see `Chip`.
+    Detect,
+    Skip,
+    Expect(u8),
+}
 
-impl LoRaBuilder
+impl<SPI, CS, RESET, E> LoRaBuilder<SPI, CS, RESET>
+where
+    SPI: Transfer<u8, Error = E> + Write<u8, Error = E>,
+    CS: OutputPin,
+    RESET: OutputPin,
 {
-    fn new<SPI, CS, RESET>(spi: SPI, cs: CS, reset: RESET, frequency: i64, delay: Delay) -> Result<LoRaMode<SPI, CS, RESET>, ()>
-    where SPI: Transfer<u8, Error = E> + Write<u8, Error = E>, CS: OutputPin, RESET: OutputPin,
-    {
-        Ok(LoRa::new(spi, cs, reset, frequency, delay))
+    /// Starts building a radio tuned to `frequency` in megahertz.
+    pub fn new(spi: SPI, cs: CS, reset: RESET, frequency: u32) -> Self {
+        LoRaBuilder {
+            spi,
+            cs,
+            reset,
+            frequency_hz: u64::from(frequency) * 1_000_000,
+            reset_delay_ms: RESET_DELAY_MS,
+            pa_output: PaOutput::PaBoost,
+            fxosc_hz: FXOSC_HZ,
+            spi_chunk_len: SPI_CHUNK_LEN,
+            init: InitMode::Reset,
+            version_check: VersionCheck::Detect,
+        }
+    }
+
+    /// Sets how long to wait after releasing the reset pin before reading RegVersion. The datasheet
+    /// requires 5 ms for the radio to be ready (7.2.2. Manual Reset), boards with a large capacitance
+    /// on the reset line may need longer. Boards known to meet the datasheet timing can opt into
+    /// `FAST_RESET_DELAY_MS` to start faster. Default value is `RESET_DELAY_MS`.
+    pub fn reset_delay_ms(mut self, reset_delay_ms: u8) -> Self {
+        self.reset_delay_ms = reset_delay_ms;
+        self
+    }
+
+    /// Sets the output pin the board's antenna is connected to. This depends on the wiring of the
+    /// board, e.g. HopeRF RFM95W modules only use PA_BOOST while the Modtronix inAir9 uses RFO.
+    /// Selecting the wrong pin produces no output at all. Default value is `PaOutput::PaBoost`.
+    pub fn pa_output(mut self, pa_output: PaOutput) -> Self {
+        self.pa_output = pa_output;
+        self
+    }
+
+    /// Sets the frequency the radio is tuned to in Hz, replacing the one in megahertz given to `new`,
+    /// so channels such as 868.1 MHz are tuned at construction. The frequency is programmed once,
+    /// rounded down to a multiple of `fstep_hz`, and frequencies beyond the Frf register return
+    /// `Err(InvalidInput)` from `build`.
+    pub fn frequency_hz(mut self, frequency_hz: u64) -> Self {
+        self.frequency_hz = frequency_hz;
+        self
+    }
+
+    /// Sets the frequency in Hz of the crystal or TCXO clocking the radio, used for all frequency
+    /// math. Modules built around a reference other than 32 MHz are otherwise tuned off by the ratio
+    /// of the two. Default value is `FXOSC_HZ`.
+    pub fn crystal_hz(mut self, fxosc_hz: u32) -> Self {
+        self.fxosc_hz = fxosc_hz;
+        self
+    }
+
+    /// Sets the largest SPI transfer issued by the FIFO and register bursts, for SPI
+    /// implementations rejecting or splitting long transfers. Longer bursts are split into several
+    /// transfers, CS staying asserted in between. `0` returns `Err(InvalidInput)` from `build`.
+    /// Default value is `SPI_CHUNK_LEN`.
+    pub fn spi_chunk_len(mut self, spi_chunk_len: usize) -> Self {
+        self.spi_chunk_len = spi_chunk_len;
+        self
+    }
+
+    /// Sets how the radio is brought up by `build`, e.g. to keep the configuration of a radio that
+    /// stayed powered while the MCU restarted, see `InitMode`. Default value is `InitMode::Reset`.
+    pub fn init_mode(mut self, init: InitMode) -> Self {
+        self.init = init;
+        self
     }
-}*/
+
+    /// Accepts any value of RegVersion, e.g. for clone modules reporting another version but
+    /// otherwise identical to an SX1276. The radio is driven as an SX1272 if it reads `0x22` and
+    /// as an SX1276 otherwise, and the value read is available from `LoRa::version`. A radio that
+    /// doesn't respond is not detected either.
+    pub fn skip_version_check(mut self) -> Self {
+        self.version_check = VersionCheck::Skip;
+        self
+    }
+
+    /// Accepts only `version` as the value of RegVersion, returning `Err(VersionMismatch)` from
+    /// `build` otherwise, or `Err(NoResponse)` if the radio doesn't answer. The radio is driven as an
+    /// SX1272 if it reads `0x22` and as an SX1276 otherwise.
+    pub fn expect_version(mut self, version: u8) -> Self {
+        self.version_check = VersionCheck::Expect(version);
+        self
+    }
+
+    /// Builds and returns a new instance of the radio. Only one instance of the radio should exist at a time.
+    /// This also preforms a hardware reset of the module and then puts it in standby. The reset pin
+    /// is held low for `RESET_LOW_US`, then `reset_delay_ms` is waited before reading RegVersion. A
+    /// version of `0x00` or `0xff`, read while the radio is not responding yet, is read again every
+    /// millisecond up to `VERSION_RETRIES` times, after which `Err(NoResponse)` is returned, most
+    /// likely a wiring fault. Any other unsupported version returns `Err(VersionMismatch)`. See
+    /// `init_mode` to skip the reset.
+    pub fn build(
+        self,
+        delay: &mut dyn DelayUs<u16>,
+    ) -> Result<LoRa<SPI, CS, RESET>, Error<E, CS::Error, RESET::Error>> {
+        if self.spi_chunk_len == 0 {
+            return Err(InvalidInput);
+        }
+        let mut sx127x = LoRa {
+            spi: self.spi,
+            cs: self.cs,
+            reset: self.reset,
+            frequency: self.frequency_hz,
+            explicit_header: true,
+            header_written: None,
+            mode: RadioMode::Sleep,
+            poll_interval_ms: 1,
+            rx_stream_addr: None,
+            header_timeout_ms: None,
+            rx_packet: None,
+            max_payload_length: 0xff,
+            pa_output: self.pa_output,
+            dwell_time_limit_ms: None,
+            fxosc_hz: self.fxosc_hz,
+            modem_clear_timeout_ms: None,
+            collision_threshold_db: 10,
+            collision_baseline: None,
+            radiohead: None,
+            listen: None,
+            rx_crc_error: false,
+            config_shadow: ConfigShadow::new(),
+            tx_watchdog: None,
+            spi_chunk_len: self.spi_chunk_len,
+            rx_restart_policy: None,
+            consecutive_crc_errors: 0,
+            rx_restarts: 0,
+            chip: Chip::Sx1276,
+            paused_rx: None,
+            version: 0,
+            afc_every_n_packets: 0,
+            afc_packets: 0,
+            afc_sum_hz: 0,
+            afc_correction_hz: None,
+            afc_error: false,
+        };
+        if self.init == InitMode::Reset {
+            sx127x.reset.set_low().map_err(Reset)?;
+            delay.delay_us(RESET_LOW_US);
+            sx127x.reset.set_high().map_err(Reset)?;
+            for _ in 0..self.reset_delay_ms {
+                delay.delay_us(1000);
+            }
+        }
+        let mut version = sx127x.read_register(Register::RegVersion)?;
+        for _ in 0..VERSION_RETRIES {
+            if !is_no_response(version) {
+                break;
+            }
+            delay.delay_us(1000);
+            version = sx127x.read_register(Register::RegVersion)?;
+        }
+        let chip = match self.version_check {
+            VersionCheck::Detect => chip_from_version(version)?,
+            VersionCheck::Expect(_) if is_no_response(version) => return Err(NoResponse(version)),
+            VersionCheck::Expect(expected) if version != expected => return Err(VersionMismatch(version)),
+            VersionCheck::Skip | VersionCheck::Expect(_) => Chip::from_version(version).unwrap_or(Chip::Sx1276),
+        };
+        sx127x.chip = chip;
+        sx127x.version = version;
+        if self.init == InitMode::Adopt {
+            let op_mode = sx127x.read_register(Register::RegOpMode)?;
+            // A radio in FSK mode was reset or power cycled, there is nothing to adopt
+            if op_mode.get_bit(7) {
+                sx127x.adopt_config(op_mode)?;
+                sx127x.cs.set_high().map_err(CS)?;
+                return Ok(sx127x);
+            }
+        }
+        sx127x.set_mode(RadioMode::Sleep)?;
+        sx127x.set_frequency_hz(self.frequency_hz)?;
+        // Half of the FIFO is for Rx the other half for Tx. Setting both to 0 I believe allows you
+        // to use the full FIFO in either Rx or Tx mode.
+        sx127x.write_register_if_changed(Register::RegFifoTxBaseAddr, 0)?;
+        sx127x.write_register_if_changed(Register::RegFifoRxBaseAddr, 0)?;
+        let lna = sx127x.read_register(Register::RegLna)?;
+        sx127x.write_register(Register::RegLna, lna | 0x03)?;
+        if chip == Chip::Sx1276 {
+            let modem_config_3 = ModemConfig3 {
+                low_data_rate_optimize: false,
+                agc_auto_on: true,
+                reserved: 0,
+            };
+            sx127x.write_register(Register::RegModemConfig3, modem_config_3.to_bits())?;
+        } else {
+            sx127x.set_agc_auto_on(true)?;
+        }
+        // The highest RFO ceiling, the reset value being 4. The SX1272 has no MaxPower field
+        if chip == Chip::Sx1276 {
+            sx127x.set_pa_max_power(7)?;
+        }
+        sx127x.set_mode(RadioMode::Stdby)?;
+        sx127x.cs.set_high().map_err(CS)?;
+        Ok(sx127x)
+    }
+}
 
 /// Provides high-level access to Semtech SX1276/77/78/79 based boards connected to a Raspberry Pi
 pub struct LoRa<SPI, CS, RESET>
@@ -179,19 +412,154 @@ pub struct LoRa<SPI, CS, RESET>
     spi: SPI,
     cs: CS,
     reset: RESET,
-    frequency: u32,
+    frequency: u64,
     pub explicit_header: bool,
+    /// Header mode last written to RegModemConfig1, `Some(true)` for explicit.
+    header_written: Option<bool>,
     pub mode: RadioMode,
+    poll_interval_ms: u8,
+    rx_stream_addr: Option<u8>,
+    header_timeout_ms: Option<u16>,
+    rx_packet: Option<(u8, u8)>,
+    max_payload_length: u8,
+    pa_output: PaOutput,
+    dwell_time_limit_ms: Option<u32>,
+    fxosc_hz: u32,
+    modem_clear_timeout_ms: Option<u16>,
+    collision_threshold_db: u8,
+    collision_baseline: Option<i32>,
+    radiohead: Option<RadioHead>,
+    listen: Option<ListenConfig>,
+    rx_crc_error: bool,
+    config_shadow: ConfigShadow,
+    tx_watchdog: Option<TxWatchdog>,
+    spi_chunk_len: usize,
+    rx_restart_policy: Option<RxRestartPolicy>,
+    consecutive_crc_errors: u8,
+    rx_restarts: u32,
+    chip: Chip,
+    paused_rx: Option<RadioMode>,
+    version: u8,
+    afc_every_n_packets: u16,
+    afc_packets: u16,
+    afc_sum_hz: i64,
+    /// Mean frequency error to correct once the packet being received was read.
+    afc_correction_hz: Option<i64>,
+    /// Set when a correction failed to apply, see `take_afc_error`.
+    afc_error: bool,
+}
+
+/// Prints the settings cached by the driver, without any SPI transfer. They may be stale, e.g. if
+/// the radio was reset behind the driver's back, read the registers for the actual state.
+impl<SPI, CS, RESET> core::fmt::Debug for LoRa<SPI, CS, RESET> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("LoRa (cached, may be stale)")
+            .field("chip", &self.chip)
+            .field("frequency_hz", &self.frequency)
+            .field("mode", &self.mode)
+            .field("explicit_header", &self.explicit_header)
+            .field("pa_output", &self.pa_output)
+            .field("crystal_hz", &self.fxosc_hz)
+            .field("poll_interval_ms", &self.poll_interval_ms)
+            .field("header_timeout_ms", &self.header_timeout_ms)
+            .field("max_payload_length", &self.max_payload_length)
+            .field("dwell_time_limit_ms", &self.dwell_time_limit_ms)
+            .field("modem_clear_timeout_ms", &self.modem_clear_timeout_ms)
+            .field("collision_threshold_db", &self.collision_threshold_db)
+            .field("radiohead", &self.radiohead)
+            .field("listen", &self.listen)
+            .field("tx_watchdog", &self.tx_watchdog)
+            .field("afc_every_n_packets", &self.afc_every_n_packets)
+            .finish()
+    }
 }
 
+/// Errors of the driver. When a bus or pin operation fails, `SPI`, `CS` or `Reset` is returned
+/// right away, with CS deasserted unless setting it failed. The settings cached by the driver, e.g.
+/// the frequency or the mode, only change once the registers holding them were written, and a
+/// received packet stays pending until it was read without error.
 #[derive(Debug)]
 pub enum Error<SPI, CS, RESET> {
     Uninformative,
     VersionMismatch(u8),
+    NoResponse(u8),
     CS(CS),
     Reset(RESET),
     SPI(SPI),
     Transmitting,
+    Timeout,
+    InvalidInput,
+    Busy,
+    PllLockFailed,
+    InvalidHeader,
+    PayloadTooLong,
+    DwellTimeExceeded { airtime_ms: u32, limit_ms: u32 },
+    ChannelBusy,
+    Pin,
+    NoPacket,
+    ConfigMismatch(RegisterMismatch),
+    TxStuck,
+    HighPowerConflict(PowerSetting),
+}
+
+impl<SPI, CS, RESET> core::fmt::Display for Error<SPI, CS, RESET>
+where
+    SPI: core::fmt::Debug,
+    CS: core::fmt::Debug,
+    RESET: core::fmt::Debug,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Uninformative => f.write_str("no packet was received before the timeout"),
+            VersionMismatch(version) => write!(
+                f,
+                "unsupported chip, RegVersion reads {:#04x}: probably not an SX1272 or SX1276 family radio",
+                version
+            ),
+            NoResponse(0x00) => f.write_str(
+                "the radio does not respond, RegVersion reads 0x00: probably MISO disconnected, CS \
+                 wired to the wrong pin or the radio not powered",
+            ),
+            NoResponse(version) => write!(
+                f,
+                "the radio does not respond, RegVersion reads {:#04x}: probably MISO stuck high or \
+                 the radio held in reset",
+                version
+            ),
+            CS(e) => write!(f, "CS pin error: {:?}", e),
+            Reset(e) => write!(f, "reset pin error: {:?}", e),
+            SPI(e) => write!(f, "SPI error: {:?}", e),
+            Transmitting => f.write_str("a transmission is in progress"),
+            Timeout => f.write_str("timed out"),
+            InvalidInput => f.write_str("invalid argument"),
+            Busy => f.write_str("a packet is being received"),
+            PllLockFailed => f.write_str("the PLL did not lock: probably a frequency out of the band of the module"),
+            InvalidHeader => f.write_str("the header of the packet being received was invalid"),
+            PayloadTooLong => f.write_str("the received payload is longer than the maximum payload length"),
+            DwellTimeExceeded { airtime_ms, limit_ms } => write!(
+                f,
+                "the packet would be on air for {} ms, over the dwell time limit of {} ms",
+                airtime_ms, limit_ms
+            ),
+            ChannelBusy => f.write_str("the channel is busy"),
+            Pin => f.write_str("DIO pin error"),
+            NoPacket => f.write_str("no packet is ready"),
+            ConfigMismatch(mismatch) => write!(
+                f,
+                "configuration register {:#04x} reads {:#04x} instead of {:#04x}: probably a brown-out or an ESD event",
+                mismatch.register, mismatch.actual, mismatch.expected
+            ),
+            TxStuck => f.write_str(
+                "the transmission never completed, the radio was forced back to standby",
+            ),
+            HighPowerConflict(PowerSetting::Rfo) => {
+                f.write_str("the +20 dBm high power mode requires PA_BOOST, not RFO")
+            }
+            HighPowerConflict(PowerSetting::Ocp) => f.write_str(
+                "the +20 dBm high power mode requires the over current protection at 140 mA or more",
+            ),
+        }
+    }
 }
 
 pub trait Packet
@@ -202,8 +570,6 @@ pub trait Packet
 
 
 use Error::*;
-use crate::register::{FskDataModulationShaping, FskRampUpRamDown};
-use core::ops::{BitAnd, BitOr};
 
 #[cfg(not(feature = "version_0x09"))]
 const VERSION_CHECK: u8 = 0x12;
@@ -211,6 +577,49 @@ const VERSION_CHECK: u8 = 0x12;
 #[cfg(feature = "version_0x09")]
 const VERSION_CHECK: u8 = 0x09;
 
+/// Longest payload `transmit_payload` accepts, RegPayloadLength being 8 bits.
+pub const MAX_PAYLOAD_LEN: usize = 255;
+
+/// Time the reset pin is held low, the datasheet minimum being 100 µs (7.2.2. Manual Reset).
+pub const RESET_LOW_US: u16 = 150;
+
+/// Default wait after releasing the reset pin, twice the 5 ms of the datasheet (7.2.2. Manual
+/// Reset) for the slow rise of the reset line on some boards.
+pub const RESET_DELAY_MS: u8 = 10;
+
+/// Shortest wait after releasing the reset pin, just over the 5 ms of the datasheet, see
+/// `LoRaBuilder::reset_delay_ms`.
+pub const FAST_RESET_DELAY_MS: u8 = 6;
+
+/// Number of times its time on air a transmission may take before the helpers waiting for it on
+/// their own, e.g. `LoRa::run_beacon`, give up on it.
+pub const TX_TIMEOUT_MARGIN: u8 = 2;
+
+/// Number of symbols channel activity detection may take before `LoRa::cad` gives up on it, CAD
+/// itself lasting about two symbols (4.1.6. Channel Activity Detection).
+pub const CAD_TIMEOUT_SYMBOLS: u32 = 4;
+
+/// Number of times RegVersion is read again while it reads `0x00` or `0xff` after a reset, the
+/// radio not driving MISO yet.
+pub const VERSION_RETRIES: u8 = 5;
+
+/// Default largest SPI transfer of a burst, in bytes, address byte excluded, see
+/// `LoRaBuilder::spi_chunk_len`.
+pub const SPI_CHUNK_LEN: usize = 32;
+
+/// Default frequency of the crystal oscillator (FXOSC) in Hz (2.5. Chip Specification, p. 14),
+/// see `LoRaBuilder::crystal_hz`.
+pub const FXOSC_HZ: u32 = 32_000_000;
+
+/// Upper bound in Hz of the frequencies served by the low frequency port (bands 2 and 3), see
+/// `Band`.
+pub const LF_BAND_MAX_HZ: u64 = 525_000_000;
+
+/// Frequency synthesizer step in Hz, FSTEP = FXOSC / 2^19 (about 61.035 Hz). This is the tuning
+/// resolution of the radio: programmed frequencies are always a whole number of steps. This is the
+/// step of the default crystal, see `LoRa::fstep_hz`.
+pub const FSTEP_HZ: f64 = FXOSC_HZ as f64 / (1u32 << 19) as f64;
+
 impl<SPI, CS, RESET, E> LoRa<SPI, CS, RESET>
 where
     SPI: Transfer<u8, Error = E> + Write<u8, Error = E>,
@@ -218,48 +627,21 @@ where
     RESET: OutputPin,
 {
     /// Builds and returns a new instance of the radio. Only one instance of the radio should exist at a time.
-    /// This also preforms a hardware reset of the module and then puts it in standby.
+    /// This also preforms a hardware reset of the module and then puts it in standby. Use
+    /// `LoRaBuilder` to change the construction options.
     pub fn new(
         spi: SPI,
         cs: CS,
         reset: RESET,
         frequency: u32,
-        delay: &mut dyn DelayMs<u8>,
+        delay: &mut dyn DelayUs<u16>,
     ) -> Result<Self, Error<E, CS::Error, RESET::Error>> {
-        let mut sx127x = LoRa {
-            spi,
-            cs,
-            reset,
-            frequency,
-            explicit_header: true,
-            mode: RadioMode::Sleep,
-        };
-        sx127x.reset.set_low().map_err(Reset)?;
-        delay.delay_ms(10);
-        sx127x.reset.set_high().map_err(Reset)?;
-        delay.delay_ms(10);
-        let version = sx127x.read_register(Register::RegVersion)?;
-        if version == VERSION_CHECK {
-            sx127x.set_mode(RadioMode::Sleep)?;
-            sx127x.set_frequency(frequency)?;
-            // Half of the FIFO is for Rx the other half for Tx. Setting both to 0 I believe allows you
-            // to use the full FIFO in either Rx or Tx mode.
-            sx127x.write_register(Register::RegFifoTxBaseAddr, 0)?;
-            sx127x.write_register(Register::RegFifoRxBaseAddr, 0)?;
-            let lna = sx127x.read_register(Register::RegLna)?;
-            sx127x.write_register(Register::RegLna, lna | 0x03)?;
-            sx127x.write_register(Register::RegModemConfig3, 0x04)?;
-            sx127x.set_mode(RadioMode::Stdby)?;
-            sx127x.cs.set_high().map_err(CS)?;
-            Ok(sx127x)
-        } else {
-            Err(Error::VersionMismatch(version))
-        }
+        LoRaBuilder::new(spi, cs, reset, frequency).build(delay)
     }
 
-    /// Transmits up to 255 bytes of data. To avoid the use of an allocator, this takes a fixed 255 u8
+    /*/// Transmits up to 255 bytes of data. To avoid the use of an allocator, this takes a fixed 255 u8
     /// array and a payload size and returns the number of bytes sent if successful.
-    /*pub fn transmit_payload_busy(
+    pub fn transmit_payload_busy(
         &mut self,
         buffer: [u8; 255],
         payload_size: usize,
@@ -287,299 +669,1592 @@ where
         }
     }*/
 
+    /// Maps TxDone on DIO0, RegDioMapping1 bits 7-6. The mappings of DIO1 to DIO3 are left alone.
     pub fn set_dio0_tx_done(&mut self) -> Result<(), Error<E, CS::Error, RESET::Error>> {
-        self.write_register(Register::RegDioMapping1, 0b01_00_00_00)
+        let mut dio_mapping_1 = self.read_register(Register::RegDioMapping1)?;
+        dio_mapping_1.set_bits(6..8, 0b01);
+        self.write_register(Register::RegDioMapping1, dio_mapping_1)
     }
 
-    /*pub fn transmit_packet(&mut self, packet: Packet) -> Result<(), Error<E, CS::Error, RESET::Error>>
-    {
-        Ok(())
-    }*/
-
-    //pub fn transmit_payload(&mut self, buffer: [u8; 255], payload_size: usize) -> Result<(), Error<E, CS::Error, RESET::Error>>
-    pub fn transmit_payload(&mut self, payload: &heapless::Vec<u8, 255>) -> Result<(), Error<E, CS::Error, RESET::Error>>
-    {
-        // Variable length packet (page 73):
-        // Variable length packet format is selected when bit PacketFormat is set to 1.
-        // In this mode the length of the payload, indicated by the length byte, is given by the first byte of the FIFO and is limited to 255 bytes.
-        // In this mode, the payload must contain at least 2 bytes, i.e. length + address or message byte
-
-        /*if self.transmitting()?
-        {
-            return Err(Transmitting);
-        }*/
-
-        self.set_mode(RadioMode::Stdby)?;
-
-        if self.explicit_header
-        {
-            self.set_explicit_header_mode()?;
-        }
-
-        else
-        {
-            self.set_implicit_header_mode()?;
-        }
-
-        self.write_register(Register::RegIrqFlags, 0)?;
-        self.write_register(Register::RegFifoAddrPtr, 0)?;
-        self.write_register(Register::RegPayloadLength, 0)?;
-
-        let length_byte = payload.len() as u8;
+    /// Maps CadDone or another signal on DIO3.
+    pub fn set_dio3_mapping(&mut self, mapping: Dio3Mapping) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        let mut dio_mapping_1 = self.read_register(Register::RegDioMapping1)?;
+        dio_mapping_1.set_bits(0..2, mapping as u8);
+        self.write_register(Register::RegDioMapping1, dio_mapping_1)
+    }
 
-        self.write_register(Register::RegFifo, length_byte)?;
+    /// Maps CadDetected or another signal on DIO4.
+    pub fn set_dio4_mapping(&mut self, mapping: Dio4Mapping) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        let mut dio_mapping_2 = self.read_register(Register::RegDioMapping2)?;
+        dio_mapping_2.set_bits(6..8, mapping as u8);
+        self.write_register(Register::RegDioMapping2, dio_mapping_2)
+    }
 
-        for byte in payload.iter()
-        {
-            self.write_register(Register::RegFifo, *byte)?;
+    /// Performs a channel activity detection, blocking until it is done, and returns true if a
+    /// LoRa preamble was detected. The IRQ register is read every `poll_interval_ms`. The radio is
+    /// left in standby. If CadDone isn't raised within `CAD_TIMEOUT_SYMBOLS` symbols, the radio is
+    /// put in standby and `Err(Timeout)` is returned.
+    pub fn cad(&mut self, delay: &mut dyn DelayMs<u8>) -> Result<bool, Error<E, CS::Error, RESET::Error>> {
+        let mut clock = DelayClock::new(delay);
+        let deadline_ms = self.cad_deadline_ms(&mut clock)?;
+        self.start_cad()?;
+        loop {
+            let irq_flags = self.read_register(Register::RegIrqFlags)?;
+            if irq_flags & IrqMask::CadDone.addr() != 0 {
+                return self.finish_cad(irq_flags);
+            }
+            if clock.now_ms() >= deadline_ms {
+                return self.abort_cad();
+            }
+            clock.wait_ms(self.poll_interval_ms);
         }
-
-        //self.write_register(Register::RegPayloadLength, payload_size as u8)?;
-
-        self.set_mode(RadioMode::Tx)
     }
 
-    /// Blocks the current thread, returning the size of a packet if one is received or an error is the
-    /// task timed out. The timeout can be supplied with None to make it poll indefinitely or
-    /// with `Some(timeout_in_mill_seconds)`
-    pub fn poll_irq(
+    /// Same as `cad`, but waits for `dio_done` to go high instead of reading the IRQ register, which
+    /// is then read once to tell whether a preamble was detected. CadDone must be mapped on the pin,
+    /// see `set_dio3_mapping`. The pin is checked every `poll_interval_ms`, and `Err(Pin)` is
+    /// returned if it can't be read. The wait is bounded like in `cad`.
+    pub fn cad_on_irq(
         &mut self,
-        timeout_ms: Option<i32>,
+        dio_done: &impl InputPin,
         delay: &mut dyn DelayMs<u8>,
-    ) -> Result<usize, Error<E, CS::Error, RESET::Error>> {
-        self.set_mode(RadioMode::RxContinuous)?;
-        match timeout_ms {
-            Some(value) => {
-                let mut count = 0;
-                let packet_ready = loop {
-                    let packet_ready = self.read_register(Register::RegIrqFlags)?.get_bit(6);
-                    if count >= value || packet_ready {
-                        break packet_ready;
-                    }
-                    count += 1;
-                    delay.delay_ms(1);
-                };
-                if packet_ready {
-                    self.clear_irq()?;
-                    Ok(self.read_register(Register::RegRxNbBytes)? as usize)
-                } else {
-                    Err(Uninformative)
-                }
-            }
-            None => {
-                while !self.read_register(Register::RegIrqFlags)?.get_bit(6) {
-                    delay.delay_ms(100);
-                }
-                self.clear_irq()?;
-                Ok(self.read_register(Register::RegRxNbBytes)? as usize)
+    ) -> Result<bool, Error<E, CS::Error, RESET::Error>> {
+        let mut clock = DelayClock::new(delay);
+        let deadline_ms = self.cad_deadline_ms(&mut clock)?;
+        self.start_cad()?;
+        while !dio_done.is_high().map_err(|_| Pin)? {
+            if clock.now_ms() >= deadline_ms {
+                return self.abort_cad();
             }
+            clock.wait_ms(self.poll_interval_ms);
         }
+        let irq_flags = self.read_register(Register::RegIrqFlags)?;
+        self.finish_cad(irq_flags)
     }
 
-    pub fn is_packet_ready(&mut self) -> Result<bool, Error<E, CS::Error, RESET::Error>> {
-        Ok(self.read_register(Register::RegIrqFlags)? & 0x04 != 0)
+    /// Same as `cad`, but awaits `dio_done` going high instead of polling, so that the executor can
+    /// run other tasks meanwhile, CAD taking tens of milliseconds at high spreading factors. CadDone
+    /// must be mapped on the pin, see `set_dio3_mapping`. `Err(Pin)` is returned if the pin can't be
+    /// awaited.
+    #[cfg(feature = "async")]
+    pub async fn cad_async(
+        &mut self,
+        dio_done: &mut impl embedded_hal_async::digital::Wait,
+    ) -> Result<bool, Error<E, CS::Error, RESET::Error>> {
+        self.start_cad()?;
+        dio_done.wait_for_high().await.map_err(|_| Pin)?;
+        let irq_flags = self.read_register(Register::RegIrqFlags)?;
+        self.finish_cad(irq_flags)
     }
 
-    /// Returns the contents of the fifo as a fixed 255 u8 array. This should only be called is there is a
-    /// new packet ready to be read.
-    pub fn read_packet(&mut self) -> Result<[u8; 255], Error<E, CS::Error, RESET::Error>> {
-        let mut buffer = [0 as u8; 255];
-        self.clear_irq()?;
-        let size = self.read_register(Register::RegRxNbBytes)?;
-        let fifo_addr = self.read_register(Register::RegFifoRxCurrentAddr)?;
-        self.write_register(Register::RegFifoAddrPtr, fifo_addr)?;
-        for i in 0..size {
-            let byte = self.read_register(Register::RegFifo)?;
-            buffer[i as usize] = byte;
-        }
-        self.write_register(Register::RegFifoAddrPtr, 0)?;
-        Ok(buffer)
+    fn start_cad(&mut self) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        self.set_mode(RadioMode::Stdby)?;
+        self.write_register(
+            Register::RegIrqFlags,
+            IrqMask::CadDone.addr() | IrqMask::CadDetected.addr(),
+        )?;
+        self.set_mode_pll_checked(RadioMode::Cad)
     }
 
-    /*pub fn is_fifo_full(&mut self) -> Result<u8, Error<E, CS::Error, RESET::Error>>
-    {
+    /// Returns when a CAD started now is overdue, see `CAD_TIMEOUT_SYMBOLS`.
+    fn cad_deadline_ms(&mut self, clock: &mut impl Clock) -> Result<u64, Error<E, CS::Error, RESET::Error>> {
+        let timeout_us = u64::from(self.symbol_duration_us()?) * u64::from(CAD_TIMEOUT_SYMBOLS);
+        Ok(clock.now_ms() + timeout_us.div_ceil(1000) + u64::from(self.poll_interval_ms))
+    }
 
+    /// Takes the radio out of a CAD that never completed and returns `Err(Timeout)`.
+    fn abort_cad(&mut self) -> Result<bool, Error<E, CS::Error, RESET::Error>> {
+        self.set_mode(RadioMode::Stdby)?;
+        self.write_register(
+            Register::RegIrqFlags,
+            IrqMask::CadDone.addr() | IrqMask::CadDetected.addr(),
+        )?;
+        Err(Timeout)
     }
 
-    pub fn is_fifo_threshold(&mut self) -> Result<u8, Error<E, CS::Error, RESET::Error>>
+    /// Clears the CAD IRQs and returns whether `irq_flags` report a detection. The radio returns to
+    /// standby on its own once CAD is done.
+    fn finish_cad(&mut self, irq_flags: u8) -> Result<bool, Error<E, CS::Error, RESET::Error>> {
+        self.write_register(
+            Register::RegIrqFlags,
+            IrqMask::CadDone.addr() | IrqMask::CadDetected.addr(),
+        )?;
+        self.mode = RadioMode::Stdby;
+        Ok(irq_flags & IrqMask::CadDetected.addr() != 0)
+    }
+
+    /*pub fn transmit_packet(&mut self, packet: Packet) -> Result<(), Error<E, CS::Error, RESET::Error>>
     {
-        self.read_register(Register::RegIrqFlags)? & IRQ::IrqTxDoneMask == 1
+        Ok(())
     }*/
 
-    pub fn irq_flags(&mut self) -> Result<u8, Error<E, CS::Error, RESET::Error>>
+    //pub fn transmit_payload(&mut self, buffer: [u8; 255], payload_size: usize) -> Result<(), Error<E, CS::Error, RESET::Error>>
+    /// Transmits a payload without waiting for it to be sent. Any byte slice can be passed, e.g. a
+    /// `heapless::Vec` of any capacity, without copying it. The payload is sent as is, its length
+    /// being carried by the LoRa header, so payloads must be 1 to `MAX_PAYLOAD_LEN` (255) bytes long,
+    /// other lengths returning `Err(InvalidInput)`. Returns
+    /// `Err(Transmitting)` if a previous transmission is still in progress, `Err(ChannelBusy)` if the modem clear guard is enabled and
+    /// the modem is not clear (see `set_modem_clear_guard`), `Err(Busy)` if a packet is currently
+    /// being received, `Err(DwellTimeExceeded)` if the packet would break the dwell time limit (see
+    /// `set_dwell_time_limit_ms`) and `Err(PllLockFailed)` if the synthesizer could not lock on the
+    /// carrier frequency.
+    pub fn transmit_payload(&mut self, payload: &[u8]) -> Result<(), Error<E, CS::Error, RESET::Error>>
     {
-        self.read_register(Register::RegIrqFlags)
-    }
+        // LoRa packets (4.1.1.6): the number of bytes to send is RegPayloadLength and, in explicit
+        // header mode, the receiver learns it from the header. No length byte goes in the FIFO, unlike
+        // the variable length packets of the FSK packet engine.
 
-    /*/// Returns true if the radio is currently transmitting a packet.
-    pub fn transmitting(&mut self) -> Result<bool, Error<E, CS::Error, RESET::Error>> {
-        if (self.read_register(Register::RegOpMode)? & RadioMode::Tx as u8) == RadioMode::Tx
+        if payload.is_empty() || payload.len() > MAX_PAYLOAD_LEN
         {
-            Ok(true)
+            return Err(InvalidInput);
         }
 
-        else
-        {
-            if (self.read_register(Register::RegIrqFlags)? & IrqMask::TxDone) == 1
-            {
-                self.write_register(Register::RegIrqFlags, IrqMask::TxDone)?;
-            }
-            Ok(false)
-        }
-    }*/
+        self.check_tx_allowed()?;
 
-    /// Clears the radio's IRQ registers.
-    pub fn clear_irq(&mut self) -> Result<(), Error<E, CS::Error, RESET::Error>> {
-        let irq_flags = self.read_register(Register::RegIrqFlags)?;
-        self.write_register(Register::RegIrqFlags, irq_flags)
+        self.check_dwell_time(payload.len() as u8)?;
+
+        self.start_tx(&[], payload, payload.len() as u8)
     }
 
-    /// Sets the transmit power and pin. Levels can range from 0-14 when the output
-    /// pin = 0(RFO), and from 0-20 when output pin = 1(PaBoost). Power is in dB.
-    /// Default value is `17`.
-    /// https://github.com/PaulStoffregen/RadioHead/blob/master/RH_RF95.cpp#L435
-    /// https://cdn-shop.adafruit.com/product-files/3179/sx1276_77_78_79.pdf
-    pub fn set_tx_power(&mut self, mut level: u8, use_rfo: bool) -> Result<(), Error<E, CS::Error, RESET::Error>>
+    /// Transmits the first `len` bytes of `buffer` without waiting for them to be sent, returning
+    /// `len`. This is the array based form of `transmit_payload` for callers keeping their payload
+    /// in a fixed buffer, `len` above 255 returning `Err(InvalidInput)` like an empty payload.
+    pub fn transmit_payload_array(&mut self, buffer: &[u8; 255], len: usize) -> Result<usize, Error<E, CS::Error, RESET::Error>>
     {
-        // TODO: fix
-
-        Ok(())
+        let payload = buffer.get(..len).ok_or(InvalidInput)?;
+        self.transmit_payload(payload)?;
+        Ok(len)
+    }
 
+    /// Transmits the UTF-8 bytes of `s` without waiting for them to be sent, returning their
+    /// number. Like `transmit_payload`, `s` must be 1 to 255 bytes long, other lengths returning
+    /// `Err(InvalidInput)`. No terminator or length byte is added, see `read_str`.
+    pub fn transmit_str(&mut self, s: &str) -> Result<usize, Error<E, CS::Error, RESET::Error>>
+    {
+        self.transmit_payload(s.as_bytes())?;
+        Ok(s.len())
+    }
 
-        /* I have no idea as to what this is doing.
-        if PaConfig::PaOutputRfoPin == output_pin
+    /// Returns an error if a transmission can't start now, see `transmit_payload`.
+    fn check_tx_allowed(&mut self) -> Result<(), Error<E, CS::Error, RESET::Error>>
+    {
+        if self.transmitting()?
         {
-            if level > 14
-            {
-                level = 14;
-            }
-            self.write_register(Register::RegPaConfig, (0x70 | level))
+            return Err(Transmitting);
         }
 
-        else
+        // Not blocking, the guard is only checked once here
+        if self.modem_clear_timeout_ms.is_some() && !self.is_modem_clear()?
         {
-            // PA BOOST
-            if level > 17
-            {
-                if level > 20 {
-                    level = 20;
-                }
-                // subtract 3 from level, so 18 - 20 maps to 15 - 17
-                level -= 3;
+            return Err(ChannelBusy);
+        }
 
-                // High Power +20 dBm Operation (Semtech SX1276/77/78/79 5.4.3.)
-                self.write_register(Register::RegPaDac, 0x87)?;
-                self.set_ocp(140)?;
-            } else {
-                if level < 2 {
-                    level = 2;
-                }
-                //Default value PA_HF/LF or +17dBm
-                self.write_register(Register::RegPaDac, 0x84)?;
-                self.set_ocp(100)?;
-            }
-            level -= 2;
-            self.write_register(
-                Register::RegPaConfig,
-                PaConfig::PaBoost | level as u8,
-            )
-        }*/
+        if self.receiving()?
+        {
+            return Err(Busy);
+        }
+        Ok(())
     }
 
-    /// Sets the over current protection on the radio(mA).
-    pub fn set_ocp(&mut self, ma: u8) -> Result<(), Error<E, CS::Error, RESET::Error>> {
-        let mut ocp_trim: u8 = 27;
+    /// Writes `header` followed by `payload` to the FIFO and starts transmitting them, with
+    /// RegPayloadLength set to `payload_length`.
+    fn start_tx(&mut self, header: &[u8], payload: &[u8], payload_length: u8) -> Result<(), Error<E, CS::Error, RESET::Error>>
+    {
+        self.set_mode(RadioMode::Stdby)?;
 
-        if ma <= 120 {
-            ocp_trim = (ma - 45) / 5;
-        } else if ma <= 240 {
-            ocp_trim = (ma + 30) / 10;
-        }
-        self.write_register(Register::RegOcp, 0x20 | (0x1F & ocp_trim))
+        self.write_register(Register::RegIrqFlags, 0)?;
+        self.write_register(Register::RegFifoAddrPtr, 0)?;
+
+        self.write_burst(Register::RegFifo as u8, header)?;
+        self.write_burst(Register::RegFifo as u8, payload)?;
+        // Only once the FIFO holds the whole packet, a failed load leaving the previous length
+        self.write_register(Register::RegPayloadLength, payload_length)?;
+
+        self.set_mode_pll_checked(RadioMode::Tx)
     }
 
-    /// Sets the state of the radio. Default mode after initiation is `Standby`.
-    pub fn set_mode(&mut self, mode: RadioMode) -> Result<(), Error<E, CS::Error, RESET::Error>> {
-        if self.explicit_header {
-            self.set_explicit_header_mode()?;
-        } else {
-            self.set_implicit_header_mode()?;
-        }
-        self.write_register(Register::RegOpMode, RadioMode::LongRangeMode as u8 | mode as u8)?;
+    /// Enables RadioHead framing with the addressing state of this node, or disables it with `None`.
+    /// See `send_to` and `recv_from`.
+    pub fn set_radiohead(&mut self, radiohead: Option<RadioHead>) {
+        self.radiohead = radiohead;
+    }
 
-        self.mode = mode;
-        Ok(())
+    /// Returns the RadioHead addressing state, e.g. to change the id or flags of the next packets.
+    pub fn radiohead_mut(&mut self) -> Option<&mut RadioHead> {
+        self.radiohead.as_mut()
     }
 
-    /// Sets the frequency of the radio. Values are in megahertz.
-    /// I.E. 915 MHz must be used for North America. Check regulation for your area.
-    pub fn set_frequency(&mut self, freq: u32) -> Result<(), Error<E, CS::Error, RESET::Error>> {
-        self.frequency = freq;
-        // calculate register values
-        let base = 1;
-        let frf = (freq * (base << 19)) / 32;
-        // write registers
-        self.write_register(
-            Register::RegFrfMsb,
-            ((frf & 0x00FF_0000) >> 16) as u8,
-        )?;
-        self.write_register(Register::RegFrfMid, ((frf & 0x0000_FF00) >> 8) as u8)?;
-        self.write_register(Register::RegFrfLsb, (frf & 0x0000_00FF) as u8)
+    /// Transmits `payload` to the RadioHead node at `to` without waiting for it to be sent. The
+    /// packet is the RadioHead header, from the state set with `set_radiohead`, followed by the
+    /// payload, exactly as RH_RF95 sends it. Payloads must be 1 to `RH_MAX_MESSAGE_LEN` (251) bytes
+    /// long, and `Err(InvalidInput)` is also returned if RadioHead framing is not enabled. Other
+    /// errors are the ones of `transmit_payload`.
+    pub fn send_to(&mut self, to: u8, payload: &[u8]) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        let radiohead = self.radiohead.ok_or(InvalidInput)?;
+        if payload.is_empty() || payload.len() > RH_MAX_MESSAGE_LEN {
+            return Err(InvalidInput);
+        }
+        self.check_tx_allowed()?;
+        let header = radiohead.header_to(to).to_bytes();
+        let length = (header.len() + payload.len()) as u8;
+        self.check_dwell_time(length)?;
+        self.start_tx(&header, payload, length)
     }
 
-    /// Sets the radio to use an explicit header. Default state is `ON`.
-    fn set_explicit_header_mode(&mut self) -> Result<(), Error<E, CS::Error, RESET::Error>> {
-        let reg_modem_config_1 = self.read_register(Register::RegModemConfig1)?;
-        self.write_register(Register::RegModemConfig1, reg_modem_config_1 & 0xfe)?;
-        self.explicit_header = true;
-        Ok(())
+    /// Reads the received RadioHead packet, copying its payload into `buffer` and returning the
+    /// number of bytes copied and the header. Payloads longer than `buffer` are truncated, like
+    /// RH_RF95 does. Packets shorter than a header, or addressed to another node while not
+    /// promiscuous, are dropped and `Ok(None)` is returned. Returns `Err(NoPacket)` if no packet is
+    /// ready, see `read_packet`, and `Err(InvalidInput)` if RadioHead framing is not enabled.
+    pub fn recv_from(&mut self, buffer: &mut [u8]) -> Result<Option<(usize, RhHeader)>, Error<E, CS::Error, RESET::Error>> {
+        let radiohead = self.radiohead.ok_or(InvalidInput)?;
+        let mut packet = [0u8; 255];
+        let size = self.read_packet_into(&mut packet)?;
+        let (header, payload) = match RhHeader::parse(&packet[..size]) {
+            Some(parsed) => parsed,
+            None => return Ok(None),
+        };
+        if !radiohead.accepts(&header) {
+            return Ok(None);
+        }
+        let len = payload.len().min(buffer.len());
+        buffer[..len].copy_from_slice(&payload[..len]);
+        Ok(Some((len, header)))
     }
 
-    /// Sets the radio to use an implicit header. Default state is `OFF`.
-    fn set_implicit_header_mode(&mut self) -> Result<(), Error<E, CS::Error, RESET::Error>> {
-        let reg_modem_config_1 = self.read_register(Register::RegModemConfig1)?;
-        self.write_register(Register::RegModemConfig1, reg_modem_config_1 & 0x01)?;
-        self.explicit_header = false;
-        Ok(())
+    /// Transmits a payload with a preamble of `preamble_len` symbols, e.g. a long preamble to wake up
+    /// receivers sniffing the channel, and blocks until `TxDone`. The previous preamble length is
+    /// restored afterwards, whether the transmission succeeded or not, so that following packets
+    /// are not affected. A transmission still going after its time on air times
+    /// `TX_TIMEOUT_MARGIN` is aborted with `Err(Timeout)`.
+    pub fn transmit_payload_with_preamble(
+        &mut self,
+        payload: &[u8],
+        preamble_len: u16,
+        delay: &mut dyn DelayMs<u8>,
+    ) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        let mut clock = DelayClock::new(delay);
+        self.guard_modem_clear(&mut clock)?;
+        let previous_len = self.get_preamble_length()?;
+        self.set_preamble_length(i64::from(preamble_len))?;
+        // The time on air with the long preamble, just set
+        let result = self.transmit_until_overdue(payload, &mut clock);
+        self.set_preamble_length(i64::from(previous_len))?;
+        result
     }
 
-    /// Sets the spreading factor of the radio. Supported values are between 6 and 12.
-    /// If a spreading factor of 6 is set, implicit header mode must be used to transmit
-    /// and receive packets. Default value is `7`.
-    pub fn set_spreading_factor(
+    /// Transmits a payload with the payload CRC enabled or not for this packet only, and blocks until
+    /// `TxDone`. The previous CRC setting (see `set_crc`) is restored afterwards, whether the
+    /// transmission succeeded or not. Receivers in explicit header mode learn from the header
+    /// whether a CRC follows the payload. A transmission still going after its time on air times
+    /// `TX_TIMEOUT_MARGIN` is aborted with `Err(Timeout)`.
+    pub fn transmit_payload_crc(
         &mut self,
-        mut sf: u8,
+        payload: &[u8],
+        crc: bool,
+        delay: &mut dyn DelayMs<u8>,
     ) -> Result<(), Error<E, CS::Error, RESET::Error>> {
-        if sf < 6 {
-            sf = 6;
-        } else if sf > 12 {
-            sf = 12;
+        let mut clock = DelayClock::new(delay);
+        self.guard_modem_clear(&mut clock)?;
+        let previous_crc = self.get_crc_enabled()?;
+        self.set_crc(crc)?;
+        let result = self.transmit_until_overdue(payload, &mut clock);
+        self.set_crc(previous_crc)?;
+        result
+    }
+
+    /// Listens in `RxMode::Single` for `listen_symbols` symbols (4 to 1023) and starts transmitting
+    /// the payload if nothing was received in that window, returning whether it did. If a packet was
+    /// received instead, `Ok(false)` is returned and the packet can be read with `read_packet`. The
+    /// symbol timeout set with `set_symbol_timeout` is restored afterwards. The transmission is not
+    /// awaited, see `wait_for_tx_done`.
+    pub fn transmit_if_clear(
+        &mut self,
+        payload: &[u8],
+        listen_symbols: u16,
+        delay: &mut dyn DelayMs<u8>,
+    ) -> Result<bool, Error<E, CS::Error, RESET::Error>> {
+        let previous_timeout = self.get_symbol_timeout()?;
+        self.set_symbol_timeout(listen_symbols)?;
+        let listened = self.receive(RxMode::Single, None, delay);
+        self.set_symbol_timeout(previous_timeout)?;
+        match listened {
+            Err(Timeout) => {
+                self.transmit_payload(payload)?;
+                Ok(true)
+            }
+            Ok(_) | Err(InvalidHeader) => Ok(false),
+            Err(e) => Err(e),
         }
+    }
 
-        if sf == 6 {
-            self.write_register(Register::RegDetectionOptimize, 0xc5)?;
-            self.write_register(Register::RegDetectionThreshold, 0x0c)?;
-        } else {
-            self.write_register(Register::RegDetectionOptimize, 0xc3)?;
-            self.write_register(Register::RegDetectionThreshold, 0x0a)?;
+    /// Arms the receiver for interrupt driven reception without blocking: maps RxDone on DIO0, clears
+    /// the IRQ flags, points the FIFO at RegFifoRxBaseAddr and enters `RxContinuous`. Once DIO0
+    /// rises the packet can be read with `read_packet`, the radio staying in `RxContinuous` for the
+    /// next one.
+    pub fn arm_rx(&mut self) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        self.arm_rx_mode(RxMode::Continuous)
+    }
+
+    /// Arms the receiver in `rx_mode` like `arm_rx`. If the radio is already receiving in that mode
+    /// the IRQ flags and FIFO pointer are left alone, so a packet received between two polls isn't
+    /// lost.
+    fn arm_rx_mode(&mut self, rx_mode: RxMode) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        let mut dio_mapping_1 = self.read_register(Register::RegDioMapping1)?;
+        dio_mapping_1.set_bits(6..8, 0b00);
+        self.write_register(Register::RegDioMapping1, dio_mapping_1)?;
+        let receiving = matches!(
+            (self.mode, rx_mode),
+            (RadioMode::RxContinuous, RxMode::Continuous) | (RadioMode::RxSingle, RxMode::Single)
+        );
+        if !receiving {
+            self.clear_irq()?;
+            let base_addr = self.read_register(Register::RegFifoRxBaseAddr)?;
+            self.write_register(Register::RegFifoAddrPtr, base_addr)?;
         }
-        let modem_config_2 = self.read_register(Register::RegModemConfig2)?;
-        self.write_register(
-            Register::RegModemConfig2,
-            (modem_config_2 & 0x0f) | ((sf << 4) & 0xf0),
-        )?;
-        self.set_ldo_flag()?;
-        Ok(())
+        self.set_mode_pll_checked(rx_mode.into())
     }
 
-    /// Sets the signal bandwidth of the radio. Supported values are: `7800 Hz`, `10400 Hz`,
-    /// `15600 Hz`, `20800 Hz`, `31250 Hz`,`41700 Hz` ,`62500 Hz`,`125000 Hz` and `250000 Hz`
-    /// Default value is `125000 Hz`
-    pub fn set_signal_bandwidth(
-        &mut self,
+    /// Blocks the current thread, returning the size of a packet if one is received or an error is the
+    /// task timed out. The timeout can be supplied with None to make it poll indefinitely or
+    /// with `Some(timeout_in_mill_seconds)`. The IRQ register is read every `poll_interval_ms`
+    /// milliseconds, see `set_poll_interval_ms`.
+    pub fn poll_irq(
+        &mut self,
+        timeout_ms: Option<i32>,
+        delay: &mut dyn DelayMs<u8>,
+    ) -> Result<usize, Error<E, CS::Error, RESET::Error>> {
+        let mut clock = DelayClock::new(delay);
+        let deadline_ms = deadline(&mut clock, timeout_ms);
+        self.poll_irq_until(deadline_ms, &mut clock)
+    }
+
+    /// Same as `poll_irq`, but waits until `clock` reaches `deadline_ms` instead of counting delays.
+    pub fn poll_irq_until(
+        &mut self,
+        deadline_ms: Option<u64>,
+        clock: &mut impl Clock,
+    ) -> Result<usize, Error<E, CS::Error, RESET::Error>> {
+        self.receive_until(RxMode::Continuous, deadline_ms, clock)
+            .map_err(|e| match e {
+                Timeout => Uninformative,
+                e => e,
+            })
+    }
+
+    /// Blocks the current thread until a packet is received in the given `RxMode`, returning its size.
+    /// `RxMode::Single` listens until a packet arrives or the symbol timeout (see `set_symbol_timeout`)
+    /// expires, after which the radio returns to standby on its own. This draws the least current
+    /// and suits low-power nodes. `RxMode::Continuous` keeps the receiver on until a packet arrives or
+    /// `timeout_ms` expires, which suits gateways. Both return `Err(Timeout)` on timeout, and
+    /// `timeout_ms` can be `None` to wait indefinitely. `Err(PllLockFailed)` is returned if the
+    /// synthesizer could not lock on the carrier frequency.
+    pub fn receive(
+        &mut self,
+        rx_mode: RxMode,
+        timeout_ms: Option<i32>,
+        delay: &mut dyn DelayMs<u8>,
+    ) -> Result<usize, Error<E, CS::Error, RESET::Error>> {
+        let mut clock = DelayClock::new(delay);
+        let deadline_ms = deadline(&mut clock, timeout_ms);
+        self.receive_until(rx_mode, deadline_ms, &mut clock)
+    }
+
+    /// Same as `receive`, but waits until `clock` reaches `deadline_ms` instead of counting delays.
+    /// The header timeout (see `set_header_timeout_ms`) is measured with `clock` as well.
+    pub fn receive_until(
+        &mut self,
+        rx_mode: RxMode,
+        deadline_ms: Option<u64>,
+        clock: &mut impl Clock,
+    ) -> Result<usize, Error<E, CS::Error, RESET::Error>> {
+        self.arm_rx_mode(rx_mode)?;
+        let mut synchronized_since_ms: Option<u64> = None;
+        loop {
+            let irq_flags = self.read_register(Register::RegIrqFlags)?;
+            if let Some(size) = self.check_rx_done(irq_flags)? {
+                return Ok(size);
+            }
+            let now_ms = clock.now_ms();
+            if let Some(header_timeout) = self.header_timeout_ms {
+                let synchronized = irq_flags & IrqMask::ValidHeader.addr() == 0
+                    && self.read_register(Register::RegModemStat)?.get_bit(1);
+                if synchronized {
+                    let synchronized_at_ms = *synchronized_since_ms.get_or_insert(now_ms);
+                    if now_ms - synchronized_at_ms >= u64::from(header_timeout) {
+                        self.set_mode(RadioMode::Stdby)?;
+                        self.clear_irq()?;
+                        return Err(InvalidHeader);
+                    }
+                } else {
+                    synchronized_since_ms = None;
+                }
+            }
+            if deadline_ms.is_some_and(|deadline_ms| now_ms >= deadline_ms) {
+                return Err(Timeout);
+            }
+            clock.wait_ms(self.poll_interval_ms);
+        }
+    }
+
+    /// Same as `poll_irq`, but with a timeout in microseconds for receive windows shorter than the
+    /// millisecond resolution of `poll_irq`, e.g. at high data rates. The IRQ register is read every
+    /// `poll_interval_us` waited with `delay`, and `Err(Timeout)` is returned once the delays add up
+    /// to `timeout_us`. `None` waits indefinitely. The header timeout set with
+    /// `set_header_timeout_ms` is not applied.
+    pub fn poll_irq_us(
+        &mut self,
+        timeout_us: Option<u32>,
+        poll_interval_us: u16,
+        delay: &mut dyn DelayUs<u16>,
+    ) -> Result<usize, Error<E, CS::Error, RESET::Error>> {
+        self.arm_rx_mode(RxMode::Continuous)?;
+        let mut elapsed_us: u32 = 0;
+        loop {
+            let irq_flags = self.read_register(Register::RegIrqFlags)?;
+            if let Some(size) = self.check_rx_done(irq_flags)? {
+                return Ok(size);
+            }
+            if timeout_us.is_some_and(|timeout_us| elapsed_us >= timeout_us) {
+                return Err(Timeout);
+            }
+            delay.delay_us(poll_interval_us);
+            elapsed_us = elapsed_us.saturating_add(u32::from(poll_interval_us));
+        }
+    }
+
+    /// Same as `poll_irq_us`, but with a timeout of `symbols` LoRa symbols at the current spreading
+    /// factor and bandwidth, see `symbol_duration_us`.
+    pub fn poll_irq_symbols(
+        &mut self,
+        symbols: u32,
+        poll_interval_us: u16,
+        delay: &mut dyn DelayUs<u16>,
+    ) -> Result<usize, Error<E, CS::Error, RESET::Error>> {
+        let timeout_us = symbols.saturating_mul(self.symbol_duration_us()?);
+        self.poll_irq_us(Some(timeout_us), poll_interval_us, delay)
+    }
+
+    /// Same as `poll_irq`, but returns `Err(Timeout)` once `timer`, started with `timeout`, expires.
+    /// The IRQ register is read continuously rather than every `poll_interval_ms`, and a packet
+    /// received before the timer expired is returned even if the timer expired since.
+    pub fn poll_irq_with_timer<T: CountDown>(
+        &mut self,
+        timer: &mut T,
+        timeout: T::Time,
+    ) -> Result<usize, Error<E, CS::Error, RESET::Error>> {
+        self.receive_with_timer(RxMode::Continuous, timer, timeout)
+    }
+
+    /// Same as `receive`, but returns `Err(Timeout)` once `timer`, started with `timeout`, expires.
+    /// The header timeout set with `set_header_timeout_ms` is not applied.
+    pub fn receive_with_timer<T: CountDown>(
+        &mut self,
+        rx_mode: RxMode,
+        timer: &mut T,
+        timeout: T::Time,
+    ) -> Result<usize, Error<E, CS::Error, RESET::Error>> {
+        self.arm_rx_mode(rx_mode)?;
+        timer.start(timeout);
+        loop {
+            let irq_flags = self.read_register(Register::RegIrqFlags)?;
+            if let Some(size) = self.check_rx_done(irq_flags)? {
+                return Ok(size);
+            }
+            match timer.wait() {
+                Ok(()) => return Err(Timeout),
+                Err(nb::Error::WouldBlock) => {}
+                Err(nb::Error::Other(never)) => match never {},
+            }
+        }
+    }
+
+    /// Returns the size of the packet if `irq_flags` signal RxDone, latching the packet for
+    /// `read_packet`, or `Err(Timeout)` if they signal RxTimeout.
+    fn check_rx_done(&mut self, irq_flags: u8) -> Result<Option<usize>, Error<E, CS::Error, RESET::Error>> {
+        if irq_flags.get_bit(6) {
+            let packet = self.latch_rx_packet(irq_flags)?;
+            self.rx_packet = Some(packet);
+            return Ok(Some(packet.1 as usize));
+        }
+        if irq_flags.get_bit(7) {
+            self.clear_irq()?;
+            self.mode = RadioMode::Stdby;
+            return Err(Timeout);
+        }
+        Ok(None)
+    }
+
+    /// Blocks the current thread until the transmission in progress is done, returning
+    /// `Err(Timeout)` if it is still going after `timeout_ms`. `None` waits indefinitely. If the TX
+    /// watchdog is enabled, see `set_tx_watchdog`, `Err(TxStuck)` is returned once the transmission
+    /// took longer than its time on air times the margin, after recovering the radio.
+    pub fn wait_for_tx_done(
+        &mut self,
+        timeout_ms: Option<i32>,
+        delay: &mut dyn DelayMs<u8>,
+    ) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        let mut clock = DelayClock::new(delay);
+        let deadline_ms = deadline(&mut clock, timeout_ms);
+        self.wait_for_tx_done_until(deadline_ms, &mut clock)
+    }
+
+    /// Same as `wait_for_tx_done`, but waits until `clock` reaches `deadline_ms` instead of counting
+    /// delays.
+    pub fn wait_for_tx_done_until(
+        &mut self,
+        deadline_ms: Option<u64>,
+        clock: &mut impl Clock,
+    ) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        self.wait_for_tx_done_servicing(deadline_ms, clock, |_| Ok(()))
+    }
+
+    /// Same as `wait_for_tx_done_until`, but calls `service` before every poll, e.g. to follow the
+    /// hops of a frequency hopping transmission.
+    fn wait_for_tx_done_servicing<F>(
+        &mut self,
+        deadline_ms: Option<u64>,
+        clock: &mut impl Clock,
+        mut service: F,
+    ) -> Result<(), Error<E, CS::Error, RESET::Error>>
+    where
+        F: FnMut(&mut Self) -> Result<(), Error<E, CS::Error, RESET::Error>>,
+    {
+        let stuck_after_ms = self.tx_watchdog_limit_ms()?;
+        let started_ms = clock.now_ms();
+        loop {
+            service(self)?;
+            if !self.transmitting()? {
+                self.mode = RadioMode::Stdby;
+                return Ok(());
+            }
+            let now_ms = clock.now_ms();
+            if stuck_after_ms.is_some_and(|limit_ms| now_ms - started_ms > limit_ms) {
+                self.recover_stuck_tx(clock)?;
+                return Err(TxStuck);
+            }
+            if deadline_ms.is_some_and(|deadline_ms| now_ms >= deadline_ms) {
+                return Err(Timeout);
+            }
+            clock.wait_ms(self.poll_interval_ms);
+        }
+    }
+
+    /// Enables the TX watchdog, or disables it with `None`, see `TxWatchdog`. Default value is
+    /// `None`.
+    pub fn set_tx_watchdog(&mut self, watchdog: Option<TxWatchdog>) {
+        self.tx_watchdog = watchdog;
+    }
+
+    /// Checks a transmission started `elapsed_ms` ago for applications not blocking in
+    /// `wait_for_tx_done`. If the TX watchdog is enabled and the radio is still transmitting after
+    /// the time on air times the margin, the radio is recovered and `Err(TxStuck)` is returned.
+    pub fn check_tx_watchdog(
+        &mut self,
+        elapsed_ms: u32,
+        delay: &mut dyn DelayMs<u8>,
+    ) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        let limit_ms = match self.tx_watchdog_limit_ms()? {
+            Some(limit_ms) => limit_ms,
+            None => return Ok(()),
+        };
+        if u64::from(elapsed_ms) > limit_ms && self.transmitting()? {
+            self.recover_stuck_tx(&mut DelayClock::new(delay))?;
+            return Err(TxStuck);
+        }
+        Ok(())
+    }
+
+    /// Returns how long the packet in the FIFO may take to be sent before the TX watchdog fires.
+    fn tx_watchdog_limit_ms(&mut self) -> Result<Option<u64>, Error<E, CS::Error, RESET::Error>> {
+        let margin = match self.tx_watchdog {
+            Some(watchdog) => watchdog.margin,
+            None => return Ok(None),
+        };
+        let payload_len = self.read_register(Register::RegPayloadLength)?;
+        let airtime_ms = self.time_on_air_ms(payload_len)?;
+        Ok(Some(u64::from(airtime_ms) * u64::from(margin)))
+    }
+
+    /// Returns when a transmission of `payload_len` bytes started at `now_ms` is overdue: its time
+    /// on air times `TX_TIMEOUT_MARGIN`, plus a poll interval.
+    fn tx_deadline_ms(&mut self, now_ms: u64, payload_len: u8) -> Result<u64, Error<E, CS::Error, RESET::Error>> {
+        let airtime_ms = self.time_on_air_ms(payload_len)?;
+        Ok(now_ms + u64::from(airtime_ms) * u64::from(TX_TIMEOUT_MARGIN) + u64::from(self.poll_interval_ms))
+    }
+
+    /// Transmits `payload` and waits for `TxDone` until the transmission is overdue, see
+    /// `tx_deadline_ms`, in which case the radio is taken out of TX and `Err(Timeout)` is returned.
+    fn transmit_until_overdue(
+        &mut self,
+        payload: &[u8],
+        clock: &mut impl Clock,
+    ) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        let overdue_ms = self.tx_deadline_ms(clock.now_ms(), payload.len().min(MAX_PAYLOAD_LEN) as u8)?;
+        self.transmit_payload(payload)?;
+        match self.wait_for_tx_done_until(Some(overdue_ms), clock) {
+            Err(Timeout) => {
+                self.recover_stuck_tx(clock)?;
+                Err(Timeout)
+            }
+            result => result,
+        }
+    }
+
+    /// Forces the radio out of a transmission that never completed: standby, all IRQ flags
+    /// cleared and, if the watchdog says so, a reset pulse after which the configuration written
+    /// by the driver is restored with `repair_config`.
+    fn recover_stuck_tx(&mut self, clock: &mut impl Clock) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        self.set_mode(RadioMode::Stdby)?;
+        self.write_register(Register::RegIrqFlags, 0xff)?;
+        if !self.tx_watchdog.is_some_and(|watchdog| watchdog.reset) {
+            return Ok(());
+        }
+        self.reset.set_low().map_err(Reset)?;
+        wait_at_least_ms(clock, 1);
+        self.reset.set_high().map_err(Reset)?;
+        wait_at_least_ms(clock, 6);
+        // The radio comes out of reset in FSK mode, LongRangeMode can only be set in sleep
+        self.write_register(Register::RegOpMode, RadioMode::Sleep as u8)?;
+        self.set_mode(RadioMode::Sleep)?;
+        self.repair_config()?;
+        self.set_mode(RadioMode::Stdby)
+    }
+
+    /// Same as `wait_for_tx_done`, but returns `Err(Timeout)` once `timer`, started with `timeout`,
+    /// expires.
+    pub fn wait_for_tx_done_with_timer<T: CountDown>(
+        &mut self,
+        timer: &mut T,
+        timeout: T::Time,
+    ) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        timer.start(timeout);
+        loop {
+            if !self.transmitting()? {
+                self.mode = RadioMode::Stdby;
+                return Ok(());
+            }
+            match timer.wait() {
+                Ok(()) => return Err(Timeout),
+                Err(nb::Error::WouldBlock) => {}
+                Err(nb::Error::Other(never)) => match never {},
+            }
+        }
+    }
+
+    /// Transmits a beacon every `period_ms` until `fill` breaks, e.g. the status frame of a beacon
+    /// node. `fill` writes each payload into the buffer it is given and returns its length, or
+    /// `ControlFlow::Break(())` to stop. Each beacon is waited for, with the TX watchdog if enabled
+    /// (see `set_tx_watchdog`), and the radio sleeps until the next one. The period runs from the
+    /// start of one beacon to the start of the next, the time on air being deducted from the sleep,
+    /// so that beacons keep their cadence. A beacon not sent within `period_ms`, or within its time
+    /// on air times `TX_TIMEOUT_MARGIN`, returns `Err(Timeout)` after the radio was put in standby
+    /// like the TX watchdog does, other errors being the ones of `transmit_payload`.
+    pub fn run_beacon<F>(
+        &mut self,
+        delay: &mut dyn DelayMs<u8>,
+        period_ms: u32,
+        fill: F,
+    ) -> Result<(), Error<E, CS::Error, RESET::Error>>
+    where
+        F: FnMut(&mut [u8]) -> ControlFlow<(), usize>,
+    {
+        self.run_beacon_with_clock(&mut DelayClock::new(delay), period_ms, fill)
+    }
+
+    /// Same as `run_beacon`, but keeps the cadence with `clock` instead of counting delays, so that
+    /// the time spent on SPI transfers is accounted for.
+    pub fn run_beacon_with_clock<F>(
+        &mut self,
+        clock: &mut impl Clock,
+        period_ms: u32,
+        mut fill: F,
+    ) -> Result<(), Error<E, CS::Error, RESET::Error>>
+    where
+        F: FnMut(&mut [u8]) -> ControlFlow<(), usize>,
+    {
+        let mut buffer = [0u8; MAX_PAYLOAD_LEN];
+        let mut next_ms = clock.now_ms();
+        loop {
+            let len = match fill(&mut buffer) {
+                ControlFlow::Continue(len) => len,
+                ControlFlow::Break(()) => return Ok(()),
+            };
+            // Beacons are scheduled from the first one, so that delays don't add up
+            next_ms += u64::from(period_ms);
+            let payload = buffer.get(..len).ok_or(InvalidInput)?;
+            self.guard_modem_clear(clock)?;
+            let overdue_ms = self.tx_deadline_ms(clock.now_ms(), len as u8)?;
+            self.transmit_payload(payload)?;
+            match self.wait_for_tx_done_until(Some(next_ms.min(overdue_ms)), clock) {
+                Ok(()) => {}
+                Err(Timeout) => {
+                    self.recover_stuck_tx(clock)?;
+                    return Err(Timeout);
+                }
+                Err(e) => return Err(e),
+            }
+            self.set_mode(RadioMode::Sleep)?;
+            wait_until_ms(clock, next_ms);
+        }
+    }
+
+    /// Returns true if a valid header was received since the IRQ flags were last cleared.
+    pub fn header_valid(&mut self) -> Result<bool, Error<E, CS::Error, RESET::Error>> {
+        Ok(self.read_register(Register::RegIrqFlags)? & IrqMask::ValidHeader.addr() != 0)
+    }
+
+    /// Sets how long `receive` waits for a valid header once the modem has synchronized on a
+    /// preamble. When it expires, the radio is put in standby and `Err(InvalidHeader)` is returned,
+    /// so that a false preamble detection or a corrupted header can be handled by re-arming the
+    /// receiver. `None` disables the check. Default value is `None`.
+    pub fn set_header_timeout_ms(&mut self, timeout_ms: Option<u16>) {
+        self.header_timeout_ms = timeout_ms;
+    }
+
+    /// Sets the interval in milliseconds between IRQ register reads in `poll_irq`. A short interval
+    /// reduces the latency between a packet arriving and `poll_irq` returning, at the cost of more
+    /// SPI traffic while waiting. Values below `1` are raised to `1`. Default value is `1`.
+    pub fn set_poll_interval_ms(&mut self, interval_ms: u8) {
+        self.poll_interval_ms = interval_ms.max(1);
+    }
+
+    /// Returns the interval in milliseconds between IRQ register reads in `poll_irq`.
+    pub fn get_poll_interval_ms(&self) -> u8 {
+        self.poll_interval_ms
+    }
+
+    pub fn is_packet_ready(&mut self) -> Result<bool, Error<E, CS::Error, RESET::Error>> {
+        Ok(self.read_register(Register::RegIrqFlags)? & 0x04 != 0)
+    }
+
+    /// Returns the contents of the fifo as a fixed 255 u8 array. The packet returned is the one
+    /// reported by the last `poll_irq` or `receive` call, even if further packets arrived since in
+    /// `RxContinuous`. Otherwise it is the packet signalled by the current RxDone flag, which is then
+    /// cleared, and `Err(NoPacket)` is returned if RxDone isn't set. The FIFO pointer is set to
+    /// the start of the packet (RegFifoRxCurrentAddr at RxDone) before reading and back to
+    /// RegFifoRxBaseAddr afterwards, so back-to-back packets in `RxContinuous` are read from where
+    /// the radio wrote them whatever was read before.
+    pub fn read_packet(&mut self) -> Result<[u8; 255], Error<E, CS::Error, RESET::Error>> {
+        let mut buffer = [0u8; 255];
+        self.read_packet_into(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// Same as `read_packet`, but copies the packet into `buffer` and returns its size. If the
+    /// packet doesn't fit, `Err(InvalidInput)` is returned and the packet can still be read with a
+    /// larger buffer.
+    pub fn read_packet_into(&mut self, buffer: &mut [u8]) -> Result<usize, Error<E, CS::Error, RESET::Error>> {
+        // The packet stays pending until it was read without error, so that it can be read again
+        let (fifo_addr, size) = match self.rx_packet {
+            Some(packet) => packet,
+            None => {
+                let irq_flags = self.read_register(Register::RegIrqFlags)?;
+                if irq_flags & IrqMask::RxDone.addr() == 0 {
+                    return Err(NoPacket);
+                }
+                let packet = self.latch_rx_packet(irq_flags)?;
+                self.rx_packet = Some(packet);
+                packet
+            }
+        };
+        if buffer.len() < size as usize {
+            return Err(InvalidInput);
+        }
+        self.write_register(Register::RegFifoAddrPtr, fifo_addr)?;
+        self.read_burst(Register::RegFifo as u8, &mut buffer[..size as usize])?;
+        let base_addr = self.read_register(Register::RegFifoRxBaseAddr)?;
+        self.write_register(Register::RegFifoAddrPtr, base_addr)?;
+        self.rx_packet = None;
+        // The packet was read, a correction failing to apply is reported by take_afc_error instead
+        if self.apply_afc_correction().is_err() {
+            self.afc_error = true;
+        }
+        Ok(size as usize)
+    }
+
+    /// Same as `read_packet_into`, but returns the packet as text, e.g. sent with `transmit_str`.
+    /// Bytes that are not valid UTF-8 are replaced with `?` in `buffer`, so a corrupted or binary
+    /// packet still yields a string of the packet's length.
+    pub fn read_str<'a>(&mut self, buffer: &'a mut [u8]) -> Result<&'a str, Error<E, CS::Error, RESET::Error>> {
+        let size = self.read_packet_into(buffer)?;
+        Ok(utf8_lossy_in_place(&mut buffer[..size]))
+    }
+
+    /// Same as `read_packet_into`, but returns `Ok(None)` instead of `Err(NoPacket)` when no packet is
+    /// ready, so it can be called speculatively.
+    pub fn try_read_packet_into(&mut self, buffer: &mut [u8]) -> Result<Option<usize>, Error<E, CS::Error, RESET::Error>> {
+        match self.read_packet_into(buffer) {
+            Ok(size) => Ok(Some(size)),
+            Err(NoPacket) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Same as `read_packet`, but returns a `heapless::Vec` holding exactly the packet.
+    #[cfg(feature = "heapless")]
+    pub fn read_packet_vec(&mut self) -> Result<heapless::Vec<u8, 255>, Error<E, CS::Error, RESET::Error>> {
+        let mut buffer = [0u8; 255];
+        let size = self.read_packet_into(&mut buffer)?;
+        // A packet is at most 255 bytes long
+        Ok(heapless::Vec::from_slice(&buffer[..size]).unwrap_or_default())
+    }
+
+    /// Same as `read_packet_into`, but returns `Ok(None)` for a packet whose ID was seen among the
+    /// last packets passed to `filter`, e.g. a retransmission in a mesh. Packets with a CRC error
+    /// are returned without their ID being remembered, it could be corrupted.
+    pub fn receive_dedup_into<const N: usize>(
+        &mut self,
+        buffer: &mut [u8],
+        filter: &mut DedupFilter<N>,
+    ) -> Result<Option<usize>, Error<E, CS::Error, RESET::Error>> {
+        let size = self.read_packet_into(buffer)?;
+        if !self.rx_crc_error && filter.is_duplicate(&buffer[..size]) {
+            return Ok(None);
+        }
+        Ok(Some(size))
+    }
+
+    /// Same as `receive_dedup_into`, but returns a `heapless::Vec` holding exactly the packet.
+    #[cfg(feature = "heapless")]
+    pub fn receive_dedup<const N: usize>(
+        &mut self,
+        filter: &mut DedupFilter<N>,
+    ) -> Result<Option<heapless::Vec<u8, 255>>, Error<E, CS::Error, RESET::Error>> {
+        let mut buffer = [0u8; 255];
+        let size = match self.receive_dedup_into(&mut buffer, filter)? {
+            Some(size) => size,
+            None => return Ok(None),
+        };
+        // A packet is at most 255 bytes long
+        Ok(Some(heapless::Vec::from_slice(&buffer[..size]).unwrap_or_default()))
+    }
+
+    /// Returns the FIFO address and size of the packet signalled by RxDone, and clears the IRQ flags
+    /// belonging to that packet only, so that the RxDone of a following packet is not lost.
+    /// `irq_flags` must be the flags read when RxDone was seen. In `RxSingle` the radio went back to
+    /// standby with RxDone, also for a packet refused with `Err(PayloadTooLong)`.
+    fn latch_rx_packet(&mut self, irq_flags: u8) -> Result<(u8, u8), Error<E, CS::Error, RESET::Error>> {
+        let fifo_addr = self.read_register(Register::RegFifoRxCurrentAddr)?;
+        let size = self.read_register(Register::RegRxNbBytes)?;
+        let packet_flags =
+            IrqMask::RxDone.addr() | IrqMask::ValidHeader.addr() | IrqMask::PayloadCrcError.addr();
+        self.write_register(Register::RegIrqFlags, irq_flags & packet_flags)?;
+        if let RadioMode::RxSingle = self.mode {
+            self.mode = RadioMode::Stdby;
+        }
+        self.rx_crc_error = irq_flags & IrqMask::PayloadCrcError.addr() != 0;
+        self.count_crc_error()?;
+        if size > self.max_payload_length {
+            return Err(PayloadTooLong);
+        }
+        self.count_afc_packet()?;
+        Ok((fifo_addr, size))
+    }
+
+    /// Enables the LoRa frequency correction of long receive sessions: every `every_n_packets`
+    /// packets received without CRC error, the mean of their frequency error (see
+    /// `get_packet_frequency_error`) is added to the frequency, following a transmitter whose
+    /// crystal drifts, e.g. with temperature. The correction is applied once the last of these
+    /// packets was read, restarting `RxContinuous` so that the new frequency takes effect. The
+    /// corrected frequency is clamped to the band of the current one and to the range of the Frf
+    /// register. A correction that fails to apply, e.g. on an SPI error, is dropped without failing
+    /// the read it follows, `RxContinuous` being restarted anyway, and is reported by
+    /// `take_afc_error`. `0` disables the correction. Default value is `0`.
+    pub fn enable_afc(&mut self, every_n_packets: u16) {
+        self.afc_every_n_packets = every_n_packets;
+        self.afc_packets = 0;
+        self.afc_sum_hz = 0;
+        self.afc_correction_hz = None;
+        self.afc_error = false;
+    }
+
+    /// Returns true if a correction of `enable_afc` failed to apply since the last call, and
+    /// clears the flag.
+    pub fn take_afc_error(&mut self) -> bool {
+        core::mem::take(&mut self.afc_error)
+    }
+
+    /// Adds the frequency error of the packet just latched to the mean of `enable_afc`, the
+    /// correction being due once enough packets were counted.
+    fn count_afc_packet(&mut self) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        if self.afc_every_n_packets == 0 || self.rx_crc_error {
+            return Ok(());
+        }
+        self.afc_sum_hz += self.get_packet_frequency_error()?;
+        self.afc_packets += 1;
+        if self.afc_packets >= self.afc_every_n_packets {
+            self.afc_correction_hz = Some(self.afc_sum_hz / i64::from(self.afc_packets));
+            self.afc_packets = 0;
+            self.afc_sum_hz = 0;
+        }
+        Ok(())
+    }
+
+    /// Retunes by the correction due from `enable_afc`, if any, which is taken whether it succeeds
+    /// or not. The radio only takes a new frequency when entering a receive mode, so
+    /// `RxContinuous` is restarted, also if the retune failed so that the radio is not left in
+    /// standby.
+    fn apply_afc_correction(&mut self) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        let correction_hz = match self.afc_correction_hz.take() {
+            Some(correction_hz) => correction_hz,
+            None => return Ok(()),
+        };
+        // Neither crosses to the other RF port nor leaves the Frf register
+        let (min_hz, max_hz) = match self.band() {
+            Band::Lf => (0, LF_BAND_MAX_HZ),
+            Band::Hf => (LF_BAND_MAX_HZ + 1, hz_from_frf(FRF_MAX, self.fxosc_hz)),
+        };
+        let freq = self
+            .frequency
+            .saturating_add_signed(correction_hz)
+            .clamp(min_hz, max_hz);
+        let mode = self.mode;
+        let restart = matches!(mode, RadioMode::RxContinuous);
+        if restart {
+            self.write_op_mode(RadioMode::Stdby)?;
+        }
+        let retuned = self.set_frequency_hz(freq);
+        if restart {
+            self.write_op_mode(mode)?;
+        }
+        retuned
+    }
+
+    /// Sets when the receiver is restarted after consecutive CRC errors, or never with `None`, see
+    /// `RxRestartPolicy`. Default value is `None`.
+    pub fn set_rx_restart_policy(&mut self, policy: Option<RxRestartPolicy>) {
+        self.rx_restart_policy = policy;
+        self.consecutive_crc_errors = 0;
+    }
+
+    /// Returns how many times the receiver was restarted by the `RxRestartPolicy`, wrapping
+    /// around.
+    pub fn rx_restarts(&self) -> u32 {
+        self.rx_restarts
+    }
+
+    /// Counts the CRC error of the packet just latched and restarts `RxContinuous` once the
+    /// `RxRestartPolicy` limit is reached: standby, all IRQ flags cleared, RegFifoAddrPtr back at
+    /// RegFifoRxBaseAddr and continuous receive mode again.
+    fn count_crc_error(&mut self) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        if !self.rx_crc_error {
+            self.consecutive_crc_errors = 0;
+            return Ok(());
+        }
+        self.consecutive_crc_errors = self.consecutive_crc_errors.saturating_add(1);
+        let limit = match self.rx_restart_policy {
+            Some(policy) => policy.crc_errors,
+            None => return Ok(()),
+        };
+        if self.consecutive_crc_errors < limit || !matches!(self.mode, RadioMode::RxContinuous) {
+            return Ok(());
+        }
+        self.consecutive_crc_errors = 0;
+        self.set_mode(RadioMode::Stdby)?;
+        self.write_register(Register::RegIrqFlags, 0xff)?;
+        let base_addr = self.read_register(Register::RegFifoRxBaseAddr)?;
+        self.write_register(Register::RegFifoAddrPtr, base_addr)?;
+        self.set_mode_pll_checked(RadioMode::RxContinuous)?;
+        self.rx_restarts = self.rx_restarts.wrapping_add(1);
+        Ok(())
+    }
+
+    /// Returns the FIFO address of the last byte written by the modem during reception
+    /// (RegFifoRxByteAddr). Comparing this against the current read position tells how many bytes
+    /// of an incoming packet have arrived so far, which allows the FIFO to be drained incrementally
+    /// instead of waiting for the whole payload. This is a LoRa page register.
+    pub fn get_fifo_rx_byte_addr(&mut self) -> Result<u8, Error<E, CS::Error, RESET::Error>> {
+        self.read_register(Register::RegFifoRxByteAddr)
+    }
+
+    /// Returns the number of bytes of the packet being received that are in the FIFO and have not
+    /// been read by `read_rx_stream` yet.
+    pub fn rx_bytes_available(&mut self) -> Result<u8, Error<E, CS::Error, RESET::Error>> {
+        let read_addr = self.rx_stream_read_addr()?;
+        let write_addr = self.read_register(Register::RegFifoRxByteAddr)?;
+        Ok(write_addr.wrapping_sub(read_addr))
+    }
+
+    /// Reads the bytes of the packet being received that have arrived since the previous call into
+    /// `buffer`, returning how many were read. This allows long packets to be consumed while they are
+    /// still being received in `RxContinuous`. Once RxDone is set the remaining bytes of the packet are
+    /// returned and the next calls return `0` until the following packet starts. The IRQ flags are
+    /// left untouched, so RxDone and the CRC result are handled as for any other packet.
+    pub fn read_rx_stream(&mut self, buffer: &mut [u8]) -> Result<usize, Error<E, CS::Error, RESET::Error>> {
+        let read_addr = self.rx_stream_read_addr()?;
+        let packet_done = self.read_register(Register::RegIrqFlags)? & IrqMask::RxDone.addr() != 0;
+        let available = if packet_done {
+            let start = self.read_register(Register::RegFifoRxCurrentAddr)?;
+            let size = self.read_register(Register::RegRxNbBytes)?;
+            start.wrapping_add(size).wrapping_sub(read_addr)
+        } else {
+            self.read_register(Register::RegFifoRxByteAddr)?.wrapping_sub(read_addr)
+        };
+        let count = buffer.len().min(available as usize);
+        self.write_register(Register::RegFifoAddrPtr, read_addr)?;
+        self.read_burst(Register::RegFifo as u8, &mut buffer[..count])?;
+        // The FIFO is 256 bytes long, so addresses wrap around like a u8
+        self.rx_stream_addr = Some(read_addr.wrapping_add(count as u8));
+        Ok(count)
+    }
+
+    fn rx_stream_read_addr(&mut self) -> Result<u8, Error<E, CS::Error, RESET::Error>> {
+        match self.rx_stream_addr {
+            Some(addr) => Ok(addr),
+            None => {
+                let addr = self.read_register(Register::RegFifoRxBaseAddr)?;
+                self.rx_stream_addr = Some(addr);
+                Ok(addr)
+            }
+        }
+    }
+
+    /*pub fn is_fifo_full(&mut self) -> Result<u8, Error<E, CS::Error, RESET::Error>>
+    {
+
+    }
+
+    pub fn is_fifo_threshold(&mut self) -> Result<u8, Error<E, CS::Error, RESET::Error>>
+    {
+        self.read_register(Register::RegIrqFlags)? & IRQ::IrqTxDoneMask == 1
+    }*/
+
+    pub fn irq_flags(&mut self) -> Result<u8, Error<E, CS::Error, RESET::Error>>
+    {
+        self.read_register(Register::RegIrqFlags)
+    }
+
+    /// Returns true if the radio is currently transmitting a packet.
+    pub fn transmitting(&mut self) -> Result<bool, Error<E, CS::Error, RESET::Error>> {
+        if (self.read_register(Register::RegOpMode)? & 0x07) == RadioMode::Tx as u8
+        {
+            Ok(true)
+        }
+        else
+        {
+            if (self.read_register(Register::RegIrqFlags)? & IrqMask::TxDone.addr()) != 0
+            {
+                self.write_register(Register::RegIrqFlags, IrqMask::TxDone.addr())?;
+            }
+            Ok(false)
+        }
+    }
+
+    /// Returns true if the TxDone IRQ is set, i.e. the last transmission finished. The flag is left
+    /// set, see `take_tx_done` to clear it.
+    pub fn is_tx_done(&mut self) -> Result<bool, Error<E, CS::Error, RESET::Error>> {
+        Ok(self.read_register(Register::RegIrqFlags)?.get_bit(3))
+    }
+
+    /// Returns true if the TxDone IRQ is set and clears it, so that each finished transmission is
+    /// reported once. The radio being back in standby, `mode` is updated accordingly.
+    pub fn take_tx_done(&mut self) -> Result<bool, Error<E, CS::Error, RESET::Error>> {
+        if !self.is_tx_done()? {
+            return Ok(false);
+        }
+        self.write_register(Register::RegIrqFlags, IrqMask::TxDone.addr())?;
+        self.mode = RadioMode::Stdby;
+        Ok(true)
+    }
+
+    /// Returns true if the radio is in the middle of receiving a packet, i.e. RegModemStat reports
+    /// a detected or synchronized signal.
+    pub fn receiving(&mut self) -> Result<bool, Error<E, CS::Error, RESET::Error>> {
+        Ok(self.read_register(Register::RegModemStat)? & 0x03 != 0)
+    }
+
+    /// Returns true if the ModemClear bit of RegModemStat is set, i.e. the demodulator is not busy
+    /// with a packet and it is safe to change the modem settings or to transmit.
+    pub fn is_modem_clear(&mut self) -> Result<bool, Error<E, CS::Error, RESET::Error>> {
+        Ok(self.read_register(Register::RegModemStat)?.get_bit(4))
+    }
+
+    /// Reads RegModemStat every `poll_interval_ms` until the modem is clear, returning
+    /// `Err(ChannelBusy)` if it still isn't after `timeout_ms`. Call it before reconfiguring the
+    /// modem to avoid corrupting a reception in progress.
+    pub fn wait_modem_clear(
+        &mut self,
+        timeout_ms: u16,
+        delay: &mut dyn DelayMs<u8>,
+    ) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        let mut clock = DelayClock::new(delay);
+        let deadline_ms = clock.now_ms() + u64::from(timeout_ms);
+        self.wait_modem_clear_until(deadline_ms, &mut clock)
+    }
+
+    /// Same as `wait_modem_clear`, but waits until `clock` reaches `deadline_ms` instead of counting
+    /// delays.
+    pub fn wait_modem_clear_until(
+        &mut self,
+        deadline_ms: u64,
+        clock: &mut impl Clock,
+    ) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        loop {
+            if self.is_modem_clear()? {
+                return Ok(());
+            }
+            if clock.now_ms() >= deadline_ms {
+                return Err(ChannelBusy);
+            }
+            clock.wait_ms(self.poll_interval_ms);
+        }
+    }
+
+    /// Makes the transmit and configuration functions wait up to `timeout_ms` for the modem to be
+    /// clear, so that the PA is not keyed and the modem not reconfigured while the receiver is
+    /// synchronized on another transmission, returning `Err(ChannelBusy)` if it never clears. Every
+    /// blocking transmit, i.e. every function transmitting and waiting for `TxDone`, and `apply_config`
+    /// wait like `wait_modem_clear`, while `transmit_payload`, which never blocks, only checks once.
+    /// `None` disables the guard. Default value is `None`.
+    pub fn set_modem_clear_guard(&mut self, timeout_ms: Option<u16>) {
+        self.modem_clear_timeout_ms = timeout_ms;
+    }
+
+    /// Waits for the modem to be clear if the guard of `set_modem_clear_guard` is enabled.
+    fn guard_modem_clear(&mut self, clock: &mut impl Clock) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        match self.modem_clear_timeout_ms {
+            Some(timeout_ms) => {
+                let deadline_ms = clock.now_ms() + u64::from(timeout_ms);
+                self.wait_modem_clear_until(deadline_ms, clock)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Clears the radio's IRQ registers.
+    pub fn clear_irq(&mut self) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        let irq_flags = self.read_register(Register::RegIrqFlags)?;
+        self.write_register(Register::RegIrqFlags, irq_flags)
+    }
+
+    /// Sets the transmit power on the output pin selected with `LoRaBuilder::pa_output`. Levels can
+    /// range from 0-14 when the output pin is `PaOutput::Rfo`, and from 2-20 when it is
+    /// `PaOutput::PaBoost`. Power is in dB. Default value is `17`. Only the PaSelect and
+    /// OutputPower fields of RegPaConfig are changed, the MaxPower field set with
+    /// `set_pa_max_power` is kept. On the SX1272 the RFO output power is -1 + OutputPower dBm
+    /// regardless of MaxPower, which it doesn't have.
+    /// https://github.com/PaulStoffregen/RadioHead/blob/master/RH_RF95.cpp#L435
+    /// https://cdn-shop.adafruit.com/product-files/3179/sx1276_77_78_79.pdf
+    pub fn set_tx_power(&mut self, mut level: u8) -> Result<(), Error<E, CS::Error, RESET::Error>>
+    {
+        match self.pa_output
+        {
+            PaOutput::Rfo => {
+                if level > 14 {
+                    level = 14;
+                }
+                self.set_pa_dac(PaDac::Default)?;
+                match self.chip {
+                    // Pout = Pmax - (15 - OutputPower), Pmax being 15 dBm with MaxPower at 7
+                    Chip::Sx1276 => self.update_pa_config(false, level),
+                    // Pout = -1 + OutputPower
+                    Chip::Sx1272 => self.update_pa_config(false, level + 1),
+                }
+            }
+            PaOutput::PaBoost => {
+                if level > 17 {
+                    if level > 20 {
+                        level = 20;
+                    }
+                    // subtract 3 from level, so 18 - 20 maps to 15 - 17
+                    level -= 3;
+
+                    // High Power +20 dBm Operation (Semtech SX1276/77/78/79 5.4.3.), the DAC
+                    // last since it requires PA_BOOST and the over current protection
+                    self.set_ocp(140)?;
+                    self.update_pa_config(true, level - 2)?;
+                    self.set_pa_dac(PaDac::HighPower)
+                } else {
+                    if level < 2 {
+                        level = 2;
+                    }
+                    //Default value PA_HF/LF or +17dBm
+                    self.set_pa_dac(PaDac::Default)?;
+                    self.set_ocp(100)?;
+                    // Pout = 17 - (15 - OutputPower)
+                    self.update_pa_config(true, level - 2)
+                }
+            }
+        }
+    }
+
+    /// Read-modify-writes RegPaConfig, setting PaSelect in bit 7 and OutputPower in bits 3-0 and
+    /// keeping MaxPower in bits 6-4.
+    fn update_pa_config(&mut self, pa_boost: bool, output_power: u8) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        let mut pa_config = self.read_register(Register::RegPaConfig)?;
+        pa_config.set_bit(7, pa_boost);
+        pa_config.set_bits(0..4, output_power);
+        self.write_pa_config(pa_config)
+    }
+
+    /// Writes RegPaConfig, returning `Err(HighPowerConflict(PowerSetting::Rfo))` instead if it
+    /// selects RFO while the high power mode of RegPaDac is enabled.
+    fn write_pa_config(&mut self, pa_config: u8) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        if !pa_config.get_bit(7) && self.get_pa_dac()? == PaDac::HighPower {
+            return Err(HighPowerConflict(PowerSetting::Rfo));
+        }
+        self.write_register(Register::RegPaConfig, pa_config)
+    }
+
+    /// Sets the MaxPower field of RegPaConfig, giving a maximum RFO output power of
+    /// 10.8 + 0.6 * `max_power` dBm. Supported values are between `0` and `7`, other values return
+    /// `Err(InvalidInput)`. It has no effect on PA_BOOST. Default value is `7`. The SX1272 has no
+    /// MaxPower field, bits 6-4 of its RegPaConfig being unused, and returns `Err(InvalidInput)`.
+    pub fn set_pa_max_power(&mut self, max_power: u8) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        if max_power > 7 || self.chip == Chip::Sx1272 {
+            return Err(InvalidInput);
+        }
+        let mut pa_config = self.read_register(Register::RegPaConfig)?;
+        pa_config.set_bits(4..7, max_power);
+        self.write_pa_config(pa_config)
+    }
+
+    /// Returns the MaxPower field of RegPaConfig, or `Err(InvalidInput)` on the SX1272, see
+    /// `set_pa_max_power`.
+    pub fn get_pa_max_power(&mut self) -> Result<u8, Error<E, CS::Error, RESET::Error>> {
+        if self.chip == Chip::Sx1272 {
+            return Err(InvalidInput);
+        }
+        Ok(self.read_register(Register::RegPaConfig)?.get_bits(4..7))
+    }
+
+    /// Sets the transmit power in dBm, choosing the output pin, the high power DAC and the over
+    /// current protection accordingly. RFO supports -4 to 14 dBm (-1 to 14 dBm on the SX1272) and
+    /// PA_BOOST 2 to 20 dBm, the +20 dBm mode only being engaged for 18 to 20 dBm. Impossible requests return
+    /// `Err(InvalidInput)` without touching the radio. The chosen pin is remembered for
+    /// `set_tx_power`.
+    pub fn set_tx_power_dbm(&mut self, dbm: i8, pa: PaSelection) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        let pa_output = match pa {
+            PaSelection::Auto { pa_boost_wired: true } | PaSelection::PaBoost => PaOutput::PaBoost,
+            PaSelection::Auto { pa_boost_wired: false } | PaSelection::Rfo => PaOutput::Rfo,
+        };
+        // MaxPower has no effect on PA_BOOST, it is kept there for a later switch back to RFO
+        let (max_power, output_power, high_power) = match (self.chip, pa_output, dbm) {
+            // Pmax = 10.8 + 0.6 * MaxPower, Pout = Pmax - (15 - OutputPower)
+            (Chip::Sx1276, PaOutput::Rfo, -4..=-1) => (Some(0), (dbm + 4) as u8, false),
+            (Chip::Sx1276, PaOutput::Rfo, 0..=14) => (Some(7), dbm as u8, false),
+            // Pout = -1 + OutputPower, there is no MaxPower
+            (Chip::Sx1272, PaOutput::Rfo, -1..=14) => (None, (dbm + 1) as u8, false),
+            // Pout = 17 - (15 - OutputPower) on both chips
+            (_, PaOutput::PaBoost, 2..=17) => (None, (dbm - 2) as u8, false),
+            // Pout = 20 - (15 - OutputPower) with the high power DAC
+            (_, PaOutput::PaBoost, 18..=20) => (None, (dbm - 5) as u8, true),
+            _ => return Err(InvalidInput),
+        };
+        if high_power {
+            self.set_ocp(140)?;
+        } else {
+            self.set_pa_dac(PaDac::Default)?;
+            self.set_ocp(100)?;
+        }
+        let mut pa_config = self.read_register(Register::RegPaConfig)?;
+        pa_config.set_bit(7, pa_output == PaOutput::PaBoost);
+        if let Some(max_power) = max_power {
+            pa_config.set_bits(4..7, max_power);
+        }
+        pa_config.set_bits(0..4, output_power);
+        self.write_pa_config(pa_config)?;
+        self.pa_output = pa_output;
+        if high_power {
+            self.set_pa_dac(PaDac::HighPower)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the output pin the transmit power is set on.
+    pub fn get_pa_output(&self) -> PaOutput {
+        self.pa_output
+    }
+
+    /// Enables the +20 dBm high power mode of the PA_BOOST pin or returns to the default, without
+    /// changing any other setting. `set_tx_power` does this on its own, this is for direct control,
+    /// e.g. power sweeps. High power mode requires a duty cycle of at most 1% (5.4.3. High Power
+    /// +20 dBm Operation), and PA_BOOST and the over current protection at 140 mA or more, which
+    /// are checked, see `set_pa_dac`.
+    pub fn set_pa_dac_high_power(&mut self, on: bool) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        self.set_pa_dac(if on { PaDac::HighPower } else { PaDac::Default })
+    }
+
+    /// Returns true if the high power mode of the PA_BOOST pin is enabled in RegPaDac.
+    pub fn get_pa_dac_high_power(&mut self) -> Result<bool, Error<E, CS::Error, RESET::Error>> {
+        Ok(self.get_pa_dac()? == PaDac::HighPower)
+    }
+
+    /// Sets RegPaDac. `PaDac::HighPower` is only written while PA_BOOST is selected in
+    /// RegPaConfig and the over current protection allows 140 mA or more, otherwise
+    /// `Err(HighPowerConflict(_))` names the setting in the way. Conversely, `set_ocp` and the
+    /// power setters refuse to break these conditions while the high power mode is enabled.
+    pub fn set_pa_dac(&mut self, pa_dac: PaDac) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        if pa_dac == PaDac::HighPower {
+            if !self.read_register(Register::RegPaConfig)?.get_bit(7) {
+                return Err(HighPowerConflict(PowerSetting::Rfo));
+            }
+            if ocp_limit_ma(self.read_register(Register::RegOcp)?).is_some_and(|ma| ma < 140) {
+                return Err(HighPowerConflict(PowerSetting::Ocp));
+            }
+        }
+        let reg = self.pa_dac_register();
+        self.write_register(reg, pa_dac as u8)
+    }
+
+    /// Returns the mode of RegPaDac.
+    pub fn get_pa_dac(&mut self) -> Result<PaDac, Error<E, CS::Error, RESET::Error>> {
+        let reg = self.pa_dac_register();
+        if self.read_register(reg)? & 0x07 == 0x07 {
+            Ok(PaDac::HighPower)
+        } else {
+            Ok(PaDac::Default)
+        }
+    }
+
+    fn pa_dac_register(&self) -> Register {
+        match self.chip {
+            Chip::Sx1276 => Register::RegPaDac,
+            Chip::Sx1272 => Register::RegPaDacSx1272,
+        }
+    }
+
+    /// Sets the over current protection on the radio(mA). Below 140 mA,
+    /// `Err(HighPowerConflict(PowerSetting::Ocp))` is returned while the high power mode of
+    /// RegPaDac is enabled.
+    pub fn set_ocp(&mut self, ma: u8) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        let mut ocp_trim: u8 = 27;
+
+        if ma <= 120 {
+            ocp_trim = ma.saturating_sub(45) / 5;
+        } else if ma <= 240 {
+            ocp_trim = ((u16::from(ma) + 30) / 10) as u8;
+        }
+        // Bits 7-6 are unused and kept as read
+        let mut ocp = self.read_register(Register::RegOcp)?;
+        ocp.set_bit(5, true);
+        ocp.set_bits(0..5, 0x1f & ocp_trim);
+        if ocp_limit_ma(ocp).is_some_and(|ma| ma < 140) && self.get_pa_dac()? == PaDac::HighPower {
+            return Err(HighPowerConflict(PowerSetting::Ocp));
+        }
+        self.write_register(Register::RegOcp, ocp)
+    }
+
+    /// Sets the state of the radio. Default mode after initiation is `Standby`. The header mode of
+    /// `explicit_header` is written to the radio first if it changed since it was last written,
+    /// otherwise only RegOpMode is written.
+    pub fn set_mode(&mut self, mode: RadioMode) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        if self.header_written != Some(self.explicit_header) {
+            if self.explicit_header {
+                self.set_explicit_header_mode()?;
+            } else {
+                self.set_implicit_header_mode()?;
+            }
+        }
+        self.write_op_mode(mode)
+    }
+
+    /// Writes `mode` to RegOpMode, in LoRa mode and with LowFrequencyModeOn set for the band, without
+    /// touching any other register.
+    fn write_op_mode(&mut self, mode: RadioMode) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        // The SX1272 has a single RF port, bit 3 being reserved
+        let low_frequency_mode = match (self.chip, self.band()) {
+            (Chip::Sx1276, Band::Lf) => 0x08,
+            _ => 0x00,
+        };
+        self.write_register(
+            Register::RegOpMode,
+            RadioMode::LongRangeMode as u8 | low_frequency_mode | mode as u8,
+        )?;
+
+        // Entering a receive mode restarts reception at RegFifoRxBaseAddr
+        let was_receiving = matches!(self.mode, RadioMode::RxContinuous | RadioMode::RxSingle);
+        let receiving = matches!(mode, RadioMode::RxContinuous | RadioMode::RxSingle);
+        if receiving && !was_receiving {
+            self.rx_stream_addr = None;
+            self.rx_packet = None;
+            self.collision_baseline = None;
+        }
+        self.mode = mode;
+        Ok(())
+    }
+
+    /// Leaves `RxContinuous` or `RxSingle` for standby, e.g. to save power or to transmit in a time
+    /// slot, remembering the receive mode for `resume_rx`. Only RegOpMode is written, and a packet
+    /// being received is dropped. Does nothing if the radio is not receiving.
+    pub fn pause_rx(&mut self) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        if !matches!(self.mode, RadioMode::RxContinuous | RadioMode::RxSingle) {
+            return Ok(());
+        }
+        let mode = self.mode;
+        self.write_op_mode(RadioMode::Stdby)?;
+        self.paused_rx = Some(mode);
+        Ok(())
+    }
+
+    /// Returns to the receive mode left by `pause_rx`, writing only RegOpMode, whatever was done
+    /// in between, e.g. transmitting. Returns `Err(InvalidInput)` if reception was not paused, or
+    /// while a transmission is still in progress, which entering a receive mode would abort, the
+    /// paused mode being kept for a later call, e.g. after `wait_for_tx_done`.
+    pub fn resume_rx(&mut self) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        let mode = self.paused_rx.ok_or(InvalidInput)?;
+        if matches!(self.mode, RadioMode::Tx)
+            && self.read_register(Register::RegOpMode)? & 0x07 == RadioMode::Tx as u8
+        {
+            return Err(InvalidInput);
+        }
+        self.paused_rx = None;
+        self.write_op_mode(mode)
+    }
+
+    /// Sets the state of the radio and checks that the PLL locked. If the PllTimeout bit is set the
+    /// radio is returned to standby and the mode is entered once more, and if that also fails the
+    /// radio is left in standby and `Err(PllLockFailed)` is returned.
+    fn set_mode_pll_checked(&mut self, mode: RadioMode) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        self.set_mode(mode)?;
+        if !self.get_pll_timeout()? {
+            return Ok(());
+        }
+        self.set_mode(RadioMode::Stdby)?;
+        self.set_mode(mode)?;
+        if self.get_pll_timeout()? {
+            self.set_mode(RadioMode::Stdby)?;
+            return Err(PllLockFailed);
+        }
+        Ok(())
+    }
+
+    /// Sets the frequency of the radio. Values are in megahertz.
+    /// I.E. 915 MHz must be used for North America. Check regulation for your area.
+    pub fn set_frequency(&mut self, freq: u32) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        self.set_frequency_hz(u64::from(freq) * 1_000_000)
+    }
+
+    /// Sets the frequency of the radio in hertz. The frequency is rounded down to a multiple of
+    /// `fstep_hz`. Frequencies beyond the 24 bit Frf register, i.e. 32 times the crystal frequency,
+    /// return `Err(InvalidInput)`.
+    pub fn set_frequency_hz(&mut self, freq: u64) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        let frf = self.checked_frf(freq)?;
+        self.write_u24_be(Register::RegFrfMsb, Register::RegFrfMid, Register::RegFrfLsb, frf)?;
+        self.frequency = freq;
+        Ok(())
+    }
+
+    /// Programs the carrier frequency as a raw Frf value, in synthesizer steps of `fstep_hz`. This
+    /// skips the conversion from hertz, e.g. to retune quickly from a precomputed channel table.
+    /// Values beyond 24 bits return `Err(InvalidInput)`.
+    pub fn set_frf_raw(&mut self, frf: u32) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        if frf > FRF_MAX {
+            return Err(InvalidInput);
+        }
+        self.write_u24_be(Register::RegFrfMsb, Register::RegFrfMid, Register::RegFrfLsb, frf)?;
+        self.frequency = hz_from_frf(frf, self.fxosc_hz);
+        Ok(())
+    }
+
+    /// Returns the Frf value of a frequency in hertz, rounded down, or `Err(InvalidInput)` if it
+    /// does not fit the 24 bit register.
+    fn checked_frf(&self, freq: u64) -> Result<u32, Error<E, CS::Error, RESET::Error>> {
+        let frf = frf_from_hz(freq, self.fxosc_hz);
+        if frf > FRF_MAX {
+            return Err(InvalidInput);
+        }
+        Ok(frf)
+    }
+
+    /// Returns the frequency in hertz programmed in the radio, i.e. the requested frequency rounded
+    /// down to a multiple of `fstep_hz`.
+    pub fn get_frequency_hz(&mut self) -> Result<u64, Error<E, CS::Error, RESET::Error>> {
+        let frf = self.read_u24_be(Register::RegFrfMsb, Register::RegFrfMid, Register::RegFrfLsb)?;
+        Ok(hz_from_frf(frf, self.fxosc_hz))
+    }
+
+    /// Returns the frequency of the crystal oscillator in Hz, see `LoRaBuilder::crystal_hz`.
+    pub fn crystal_hz(&self) -> u32 {
+        self.fxosc_hz
+    }
+
+    /// Returns the frequency synthesizer step in Hz, FXOSC / 2^19.
+    pub fn fstep_hz(&self) -> f64 {
+        f64::from(self.fxosc_hz) / f64::from(1u32 << 19)
+    }
+
+    /// Sets the radio to use an explicit header. Default state is `ON`.
+    fn set_explicit_header_mode(&mut self) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        self.update_modem_config_1(None, None, Some(false))?;
+        self.explicit_header = true;
+        Ok(())
+    }
+
+    /// Sets the radio to use an implicit header. Default state is `OFF`.
+    fn set_implicit_header_mode(&mut self) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        self.update_modem_config_1(None, None, Some(true))?;
+        self.explicit_header = false;
+        Ok(())
+    }
+
+    /// Read-modify-writes RegModemConfig1, changing only the fields that are `Some`, see
+    /// `ModemConfig1`. All RegModemConfig1 updates go through here so that no setter clobbers
+    /// another's bits. `bandwidth` is the SX1276 value on both chips.
+    fn update_modem_config_1(
+        &mut self,
+        bandwidth: Option<u8>,
+        coding_rate: Option<u8>,
+        implicit_header: Option<bool>,
+    ) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        // Only 125, 250 and 500 kHz exist on the SX1272, 7 to 9 on the SX1276
+        if self.chip == Chip::Sx1272 && bandwidth.is_some_and(|bw| !(7..=9).contains(&bw)) {
+            return Err(InvalidInput);
+        }
+        let mut modem_config_1 = self.read_modem_config_1()?;
+        if let Some(bw) = bandwidth {
+            modem_config_1.bandwidth = bw;
+        }
+        if let Some(cr) = coding_rate {
+            modem_config_1.coding_rate = cr;
+        }
+        if let Some(implicit) = implicit_header {
+            modem_config_1.implicit_header = implicit;
+        }
+        self.write_register(Register::RegModemConfig1, modem_config_1.to_bits(self.chip))?;
+        if let Some(implicit) = implicit_header {
+            self.header_written = Some(!implicit);
+        }
+        Ok(())
+    }
+
+    /// Sets the spreading factor of the radio. Supported values are between 6 and 12.
+    /// If a spreading factor of 6 is set, implicit header mode must be used to transmit
+    /// and receive packets. Default value is `7`. The detection optimization and threshold are
+    /// set for the spreading factor, AutomaticIFOn being left as the bandwidth errata set it.
+    pub fn set_spreading_factor(
+        &mut self,
+        sf: u8,
+    ) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        let sf = sf.clamp(6, 12);
+
+        if sf == 6 {
+            self.set_detection_optimize(DetectionOptimize::Sf6)?;
+            self.set_detection_threshold(0x0c)?;
+        } else {
+            self.set_detection_optimize(DetectionOptimize::Sf7To12)?;
+            self.set_detection_threshold(0x0a)?;
+        }
+        let mut modem_config_2 = self.get_modem_config_2()?;
+        modem_config_2.spreading_factor = sf;
+        self.write_register(Register::RegModemConfig2, modem_config_2.to_bits(self.chip))?;
+        self.set_ldo_flag()?;
+        Ok(())
+    }
+
+    /// Sets the signal bandwidth of the radio. Supported values are: `7800 Hz`, `10400 Hz`,
+    /// `15600 Hz`, `20800 Hz`, `31250 Hz`,`41700 Hz` ,`62500 Hz`,`125000 Hz` and `250000 Hz`
+    /// Default value is `125000 Hz`. The SX1272 only supports `125000 Hz`, `250000 Hz` and
+    /// `500000 Hz`, other values returning `Err(InvalidInput)`. On the SX1276, AutomaticIFOn and the
+    /// IF frequency are set as section 2.3 of the errata requires, see `set_automatic_if`. For
+    /// 41.7 kHz and below, the errata also has the receiver tuned above the carrier by the
+    /// bandwidth, which is left to the application.
+    pub fn set_signal_bandwidth(
+        &mut self,
         sbw: i64,
     ) -> Result<(), Error<E, CS::Error, RESET::Error>> {
-        let bw: i64 = match sbw {
+        let bw: u8 = match sbw {
             7_800 => 0,
             10_400 => 1,
             15_600 => 2,
@@ -591,75 +2266,751 @@ where
             250_000 => 8,
             _ => 9,
         };
-        let modem_config_1 = self.read_register(Register::RegModemConfig1)?;
-        self.write_register(
-            Register::RegModemConfig1,
-            (modem_config_1 & 0x0f) | ((bw << 4) as u8),
-        )?;
+        self.update_modem_config_1(Some(bw), None, None)?;
+        self.apply_if_errata(bw)?;
         self.set_ldo_flag()?;
         Ok(())
     }
 
+    /// Read-modify-writes DetectionOptimize, bits 2-0 of RegDetectionOptimize. AutomaticIFOn in
+    /// bit 7 of the same register is kept, see `set_automatic_if`. `set_spreading_factor` sets it
+    /// along with the matching threshold, overwriting a value set here.
+    pub fn set_detection_optimize(&mut self, value: DetectionOptimize) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        let mut detection_optimize = self.read_register(Register::RegDetectionOptimize)?;
+        detection_optimize.set_bits(0..3, value as u8);
+        self.write_register(Register::RegDetectionOptimize, detection_optimize)
+    }
+
+    /// Returns DetectionOptimize, or `None` if it holds a reserved value.
+    pub fn get_detection_optimize(&mut self) -> Result<Option<DetectionOptimize>, Error<E, CS::Error, RESET::Error>> {
+        Ok(DetectionOptimize::from_bits(self.read_register(Register::RegDetectionOptimize)?))
+    }
+
+    /// Sets RegDetectionThreshold, `0x0a` for SF7 to SF12 and `0x0c` for SF6 as written by
+    /// `set_spreading_factor`. A different threshold is overwritten by the next spreading factor
+    /// change.
+    pub fn set_detection_threshold(&mut self, threshold: u8) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        self.write_register(Register::RegDetectionThreshold, threshold)
+    }
+
+    pub fn get_detection_threshold(&mut self) -> Result<u8, Error<E, CS::Error, RESET::Error>> {
+        self.read_register(Register::RegDetectionThreshold)
+    }
+
+    /// Sets AutomaticIFOn, bit 7 of RegDetectionOptimize. `set_signal_bandwidth` already sets it
+    /// as the SX1276 errata requires (2.3 Receiver Spurious Reception of a LoRa Signal): on at
+    /// 500 kHz, off below with the IF frequency set in RegIfFreq1 and RegIfFreq2. This is for direct
+    /// control, e.g. comparing the sensitivity with and without the workaround.
+    pub fn set_automatic_if(&mut self, on: bool) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        let mut detection_optimize = self.read_register(Register::RegDetectionOptimize)?;
+        detection_optimize.set_bit(7, on);
+        self.write_register(Register::RegDetectionOptimize, detection_optimize)
+    }
+
+    /// Returns AutomaticIFOn, bit 7 of RegDetectionOptimize.
+    pub fn get_automatic_if(&mut self) -> Result<bool, Error<E, CS::Error, RESET::Error>> {
+        Ok(self.read_register(Register::RegDetectionOptimize)?.get_bit(7))
+    }
+
+    /// Applies section 2.3 of the SX1276 errata for the bandwidth `bw`, in the RegModemConfig1
+    /// encoding, as Semtech's reference driver does. The SX1272 is not affected.
+    fn apply_if_errata(&mut self, bw: u8) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        if self.chip != Chip::Sx1276 {
+            return Ok(());
+        }
+        if bw >= 9 {
+            return self.set_automatic_if(true);
+        }
+        self.set_automatic_if(false)?;
+        let if_freq = match bw {
+            0 => 0x48,
+            1..=5 => 0x44,
+            _ => 0x40,
+        };
+        self.write_register(Register::RegIfFreq1, if_freq)?;
+        self.write_register(Register::RegIfFreq2, 0x00)
+    }
+
     /// Sets the coding rate of the radio with the numerator fixed at 4. Supported values
     /// are between `5` and `8`, these correspond to coding rates of `4/5` and `4/8`.
     /// Default value is `5`.
     pub fn set_coding_rate_4(
         &mut self,
-        mut denominator: u8,
+        denominator: u8,
     ) -> Result<(), Error<E, CS::Error, RESET::Error>> {
-        if denominator < 5 {
-            denominator = 5;
-        } else if denominator > 8 {
-            denominator = 8;
-        }
+        let denominator = denominator.clamp(5, 8);
         let cr = denominator - 4;
-        let modem_config_1 = self.read_register(Register::RegModemConfig1)?;
-        self.write_register(
-            Register::RegModemConfig1,
-            (modem_config_1 & 0xf1) | (cr << 1),
-        )
+        self.update_modem_config_1(None, Some(cr), None)
+    }
+
+    /// Sets the timeout of `RxMode::Single` reception in symbols. Supported values are between `4`
+    /// and `1023`, other values return `Err(InvalidInput)`. Default value is `100`.
+    pub fn set_symbol_timeout(&mut self, symbols: u16) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        if !(4..=1023).contains(&symbols) {
+            return Err(InvalidInput);
+        }
+        let mut modem_config_2 = self.get_modem_config_2()?;
+        modem_config_2.symb_timeout_msb = (symbols >> 8) as u8;
+        self.write_register(Register::RegModemConfig2, modem_config_2.to_bits(self.chip))?;
+        self.write_register(Register::RegSymbTimeoutLsb, symbols as u8)
+    }
+
+    /// Returns the timeout of `RxMode::Single` reception in symbols.
+    pub fn get_symbol_timeout(&mut self) -> Result<u16, Error<E, CS::Error, RESET::Error>> {
+        let msb = self.get_modem_config_2()?.symb_timeout_msb;
+        let lsb = self.read_register(Register::RegSymbTimeoutLsb)?;
+        Ok(u16::from(msb) << 8 | u16::from(lsb))
+    }
+
+    /// Sets the frequency hopping period in symbols. The radio raises the `FhssChangeChannel` IRQ
+    /// every `symbols` symbols, at which point the next channel must be programmed. A value of `0`
+    /// disables frequency hopping. Default value is `0`.
+    pub fn set_hop_period(&mut self, symbols: u8) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        self.write_register(Register::RegHopPeriod, symbols)
+    }
+
+    /// Returns the frequency hopping period in symbols.
+    pub fn get_hop_period(&mut self) -> Result<u8, Error<E, CS::Error, RESET::Error>> {
+        self.read_register(Register::RegHopPeriod)
+    }
+
+    /// Returns the current frequency hopping channel (FhssPresentChannel). This should be read when
+    /// the `FhssChangeChannel` IRQ fires to find out which channel to program next.
+    pub fn get_hop_channel(&mut self) -> Result<u8, Error<E, CS::Error, RESET::Error>> {
+        Ok(self.read_register(Register::RegHopChannel)?.get_bits(0..6))
+    }
+
+    /// Enables or disables fast frequency hopping (FastHopOn). When enabled, the PLL re-locks as soon
+    /// as RegFrfLsb is written, without going through standby, which shortens each hop.
+    /// Default value is `false`.
+    pub fn set_fast_hop(&mut self, on: bool) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        let mut pll_hop = self.read_register(Register::RegPllHop)?;
+        pll_hop.set_bit(7, on);
+        self.write_register(Register::RegPllHop, pll_hop)
+    }
+
+    /// Returns true if fast frequency hopping is enabled.
+    pub fn get_fast_hop(&mut self) -> Result<bool, Error<E, CS::Error, RESET::Error>> {
+        Ok(self.read_register(Register::RegPllHop)?.get_bit(7))
+    }
+
+    /// Sets the bandwidth of the PLL. A wider bandwidth lets the PLL settle faster after a frequency
+    /// change, which shortens hops when frequency hopping, while a narrower one lowers phase noise.
+    /// The dwell time on each channel must leave room for the settling time. Default value is
+    /// `PllBandwidth::Khz300`.
+    pub fn set_pll_bandwidth(&mut self, bw: PllBandwidth) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        let reg = self.pll_register();
+        let mut pll = self.read_register(reg)?;
+        pll.set_bits(6..8, bw as u8);
+        self.write_register(reg, pll)
+    }
+
+    /// Returns the bandwidth of the PLL.
+    pub fn get_pll_bandwidth(&mut self) -> Result<PllBandwidth, Error<E, CS::Error, RESET::Error>> {
+        let reg = self.pll_register();
+        Ok(PllBandwidth::from_bits(self.read_register(reg)? >> 6))
+    }
+
+    fn pll_register(&self) -> Register {
+        match self.chip {
+            Chip::Sx1276 => Register::RegPll,
+            Chip::Sx1272 => Register::RegPllSx1272,
+        }
+    }
+
+    /// Returns true if the PLL failed to lock during the last transmit or receive attempt.
+    pub fn get_pll_timeout(&mut self) -> Result<bool, Error<E, CS::Error, RESET::Error>> {
+        Ok(self.read_register(Register::RegHopChannel)?.get_bit(7))
+    }
+
+    /// Transmits a payload while frequency hopping over `channels` (in Hz), blocking until `TxDone`.
+    /// Transmission starts on the first channel. Every time the `FhssChangeChannel` IRQ fires, the
+    /// next frequency is taken from `channels` at index FhssPresentChannel modulo the number of
+    /// channels. The hop period must be set with `set_hop_period` beforehand. IRQs are polled every
+    /// `poll_interval_ms`, so the hop period must be comfortably longer than that for the radio to
+    /// be retuned in time. The transmission is waited for like in `wait_for_tx_done`, for
+    /// `timeout_ms` or, with `None`, for its time on air times `TX_TIMEOUT_MARGIN`. On
+    /// `Err(Timeout)` the radio was put in standby, as the TX watchdog does.
+    pub fn transmit_fhss(
+        &mut self,
+        payload: &[u8],
+        channels: &[u64],
+        timeout_ms: Option<i32>,
+        delay: &mut dyn DelayMs<u8>,
+    ) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        if channels.is_empty() {
+            return Err(InvalidInput);
+        }
+        self.set_mode(RadioMode::Stdby)?;
+        self.set_frequency_hz(channels[0])?;
+        let mut clock = DelayClock::new(delay);
+        self.guard_modem_clear(&mut clock)?;
+        let deadline_ms = self.fhss_tx_deadline_ms(&mut clock, payload, timeout_ms)?;
+        self.transmit_payload(payload)?;
+        let result = self.wait_for_tx_done_servicing(Some(deadline_ms), &mut clock, |lora| {
+            lora.service_fhss(channels).map(|_| ())
+        });
+        self.recover_fhss_tx(result, &mut clock)
+    }
+
+    /// Returns when the frequency hopping transmission of `payload` about to start times out,
+    /// after `timeout_ms` or its time on air times `TX_TIMEOUT_MARGIN` with `None`.
+    fn fhss_tx_deadline_ms(
+        &mut self,
+        clock: &mut impl Clock,
+        payload: &[u8],
+        timeout_ms: Option<i32>,
+    ) -> Result<u64, Error<E, CS::Error, RESET::Error>> {
+        let now_ms = clock.now_ms();
+        match timeout_ms {
+            Some(timeout_ms) => Ok(now_ms + timeout_ms.max(0) as u64),
+            None => self.tx_deadline_ms(now_ms, payload.len().min(MAX_PAYLOAD_LEN) as u8),
+        }
+    }
+
+    /// Takes the radio out of a frequency hopping transmission that timed out, passing `result` on.
+    fn recover_fhss_tx(
+        &mut self,
+        result: Result<(), Error<E, CS::Error, RESET::Error>>,
+        clock: &mut impl Clock,
+    ) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        if let Err(Timeout) = result {
+            self.recover_stuck_tx(clock)?;
+        }
+        result
+    }
+
+    /// Receives a packet in `RxContinuous` while frequency hopping over `channels` (in Hz), returning
+    /// its size. Channels are followed the same way as in `transmit_fhss`. The receiver listens on
+    /// the first channel for the start of a packet. `timeout_ms` behaves as in `receive`.
+    pub fn receive_fhss(
+        &mut self,
+        channels: &[u64],
+        timeout_ms: Option<i32>,
+        delay: &mut dyn DelayMs<u8>,
+    ) -> Result<usize, Error<E, CS::Error, RESET::Error>> {
+        if channels.is_empty() {
+            return Err(InvalidInput);
+        }
+        self.set_mode(RadioMode::Stdby)?;
+        self.set_frequency_hz(channels[0])?;
+        self.set_mode_pll_checked(RadioMode::RxContinuous)?;
+        let mut clock = DelayClock::new(delay);
+        let deadline_ms = deadline(&mut clock, timeout_ms);
+        loop {
+            let irq_flags = self.service_fhss(channels)?;
+            if irq_flags & IrqMask::RxDone.addr() != 0 {
+                let packet = self.latch_rx_packet(irq_flags)?;
+                self.rx_packet = Some(packet);
+                return Ok(packet.1 as usize);
+            }
+            if deadline_ms.is_some_and(|deadline_ms| clock.now_ms() >= deadline_ms) {
+                return Err(Timeout);
+            }
+            clock.wait_ms(self.poll_interval_ms);
+        }
+    }
+
+    /// Transmits a payload while hopping over `channels` (in Hz) every `hop_period` symbols, and
+    /// blocks until `TxDone`. This sets the hop period and follows the channels like
+    /// `transmit_fhss`. The hop period is left set afterwards. `Err(InvalidInput)` is returned if
+    /// `channels` is empty, `hop_period` is `0` or a channel is outside of the bands of the chip
+    /// (137-175 MHz, 410-525 MHz and 862-1020 MHz on the SX1276, 860-1020 MHz on the SX1272).
+    /// `timeout_ms` and the TX watchdog apply as in `transmit_fhss`.
+    pub fn start_fhss_transmit(
+        &mut self,
+        payload: &[u8],
+        channels: &[u64],
+        hop_period: u8,
+        timeout_ms: Option<i32>,
+        delay: &mut dyn DelayMs<u8>,
+    ) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        if channels.is_empty() || hop_period == 0 {
+            return Err(InvalidInput);
+        }
+        for &channel in channels {
+            if !self.chip.is_in_band(channel) {
+                return Err(InvalidInput);
+            }
+            self.checked_frf(channel)?;
+        }
+        self.set_mode(RadioMode::Stdby)?;
+        self.set_hop_period(hop_period)?;
+        self.set_frequency_hz(channels[0])?;
+        let mut clock = DelayClock::new(delay);
+        self.guard_modem_clear(&mut clock)?;
+        let deadline_ms = self.fhss_tx_deadline_ms(&mut clock, payload, timeout_ms)?;
+        self.transmit_payload(payload)?;
+        let result = self.wait_for_tx_done_servicing(Some(deadline_ms), &mut clock, |lora| {
+            lora.service_fhss(channels).map(|_| ())
+        });
+        self.recover_fhss_tx(result, &mut clock)
+    }
+
+    /// Reads the IRQ flags and, if the `FhssChangeChannel` IRQ fired, programs the next channel from
+    /// `channels` and clears that IRQ. Returns the IRQ flags that were read.
+    fn service_fhss(&mut self, channels: &[u64]) -> Result<u8, Error<E, CS::Error, RESET::Error>> {
+        let irq_flags = self.read_register(Register::RegIrqFlags)?;
+        if irq_flags & IrqMask::FhssChangeChannel.addr() != 0 {
+            let channel = self.get_hop_channel()? as usize % channels.len();
+            self.set_frequency_hz(channels[channel])?;
+            self.write_register(Register::RegIrqFlags, IrqMask::FhssChangeChannel.addr())?;
+        }
+        Ok(irq_flags)
+    }
+
+    /// Sets the maximum payload length the radio accepts. The radio discards packets whose explicit
+    /// header declares a longer payload with a header CRC error, and `poll_irq`, `receive` and
+    /// `read_packet` return `Err(PayloadTooLong)` if a longer packet is reported anyway, so that a
+    /// bogus length from an untrusted transmitter never reaches the caller. Default value is `255`.
+    pub fn set_max_payload_length(&mut self, length: u8) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        self.write_register(Register::RegMaxPayloadLength, length)?;
+        self.max_payload_length = length;
+        Ok(())
+    }
+
+    /// Returns the maximum payload length the radio accepts.
+    pub fn get_max_payload_length(&mut self) -> Result<u8, Error<E, CS::Error, RESET::Error>> {
+        self.read_register(Register::RegMaxPayloadLength)
     }
 
     /// Sets the preamble length of the radio. Values are between 6 and 65535.
-    /// Default value is `8`.
+    /// Default value is `8`. The length is the programmed number of symbols, the radio adds
+    /// 4.25 symbols on top of it on air.
     pub fn set_preamble_length(
         &mut self,
         length: i64,
     ) -> Result<(), Error<E, CS::Error, RESET::Error>> {
-        self.write_register(Register::RegPreambleMsb, (length >> 8) as u8)?;
-        self.write_register(Register::RegPreambleLsb, length as u8)
+        self.write_u16_be(Register::RegPreambleMsb, Register::RegPreambleLsb, length as u16)
+    }
+
+    /// Returns the preamble length of the radio. Like `set_preamble_length`, this is the programmed
+    /// number of symbols and does not include the 4.25 symbols added by the radio.
+    pub fn get_preamble_length(&mut self) -> Result<u16, Error<E, CS::Error, RESET::Error>> {
+        self.read_u16_be(Register::RegPreambleMsb, Register::RegPreambleLsb)
     }
 
     /// Enables are disables the radio's CRC check. Default value is `false`.
     pub fn set_crc(&mut self, value: bool) -> Result<(), Error<E, CS::Error, RESET::Error>> {
-        let modem_config_2 = self.read_register(Register::RegModemConfig2)?;
-        if value {
-            self.write_register(Register::RegModemConfig2, modem_config_2 | 0x04)
+        if self.chip == Chip::Sx1272 {
+            let mut modem_config_1 = self.read_modem_config_1()?;
+            modem_config_1.rx_payload_crc_on = value;
+            return self.write_register(Register::RegModemConfig1, modem_config_1.to_bits(self.chip));
+        }
+        let mut modem_config_2 = self.get_modem_config_2()?;
+        modem_config_2.rx_payload_crc_on = value;
+        self.write_register(Register::RegModemConfig2, modem_config_2.to_bits(self.chip))
+    }
+
+    /// Applies a modem configuration preset, see `ModemPreset`.
+    pub fn set_modem_preset(&mut self, preset: ModemPreset) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        self.set_signal_bandwidth(125_000)?;
+        self.set_coding_rate_4(5)?;
+        self.set_spreading_factor(7)?;
+        self.set_crc(true)?;
+        self.set_preamble_length(8)?;
+        if let ModemPreset::ArduinoLoRa = preset {
+            self.set_sync_word(0x12)?;
+        }
+        self.set_explicit_header_mode()
+    }
+
+    /// Sets the sync word of the radio. Radios only receive packets with a matching sync word.
+    /// Default value is `0x12`.
+    pub fn set_sync_word(&mut self, sync_word: u8) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        self.write_register(Register::RegSyncWord, sync_word)
+    }
+
+    /// Returns the sync word of the radio.
+    pub fn get_sync_word(&mut self) -> Result<u8, Error<E, CS::Error, RESET::Error>> {
+        self.read_register(Register::RegSyncWord)
+    }
+
+    /// Sets the sync word used by LoRaWAN public networks (`0x34`) when `public` is true, or the
+    /// private network sync word (`0x12`) otherwise. Default value is `false`.
+    pub fn set_public_network(&mut self, public: bool) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        if public {
+            self.set_sync_word(0x34)
+        } else {
+            self.set_sync_word(0x12)
+        }
+    }
+
+    /// Inverts the radio's IQ signals, when receiving and when transmitting. Default value is
+    /// `false`. See `set_invert_iq_rx` and `set_invert_iq_tx` to set each direction on its own.
+    /// Only the invert bits of RegInvertiq are written, its reserved bits 5-1 being kept.
+    pub fn set_invert_iq(&mut self, value: bool) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        let mut invert_iq = self.read_register(Register::RegInvertiq)?;
+        invert_iq.set_bit(6, value);
+        invert_iq.set_bit(0, !value);
+        self.write_register(Register::RegInvertiq, invert_iq)?;
+        self.write_register(Register::RegInvertiq2, if value { 0x19 } else { 0x1d })
+    }
+
+    /// Inverts the IQ signals when receiving, InvertIQ RX being RegInvertiq bit 6, e.g. to receive
+    /// LoRaWAN downlinks while transmitting with normal IQ. RegInvertiq2 is set to match, as
+    /// required for inverted IQ reception (AN1200.24). Default value is `false`.
+    pub fn set_invert_iq_rx(&mut self, on: bool) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        let mut invert_iq = self.read_register(Register::RegInvertiq)?;
+        invert_iq.set_bit(6, on);
+        self.write_register(Register::RegInvertiq, invert_iq)?;
+        self.write_register(Register::RegInvertiq2, if on { 0x19 } else { 0x1d })
+    }
+
+    /// Inverts the IQ signals when transmitting, InvertIQ TX being RegInvertiq bit 0, cleared to
+    /// invert. Default value is `false`.
+    pub fn set_invert_iq_tx(&mut self, on: bool) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        let mut invert_iq = self.read_register(Register::RegInvertiq)?;
+        invert_iq.set_bit(0, !on);
+        self.write_register(Register::RegInvertiq, invert_iq)
+    }
+
+    /// Returns true if the IQ signals are inverted when receiving.
+    pub fn get_invert_iq_rx(&mut self) -> Result<bool, Error<E, CS::Error, RESET::Error>> {
+        Ok(self.read_register(Register::RegInvertiq)?.get_bit(6))
+    }
+
+    /// Returns true if the IQ signals are inverted when transmitting.
+    pub fn get_invert_iq_tx(&mut self) -> Result<bool, Error<E, CS::Error, RESET::Error>> {
+        Ok(!self.read_register(Register::RegInvertiq)?.get_bit(0))
+    }
+
+    /// Configures the radio to send LoRaWAN uplinks at `freq_hz` with spreading factor `sf` (7 to 12)
+    /// and bandwidth `bw` (`125000`, `250000` or `500000` Hz): public network sync word `0x34`,
+    /// preamble of 8 symbols, coding rate 4/5, explicit header, CRC on and IQ not inverted. Other
+    /// spreading factors and bandwidths return `Err(InvalidInput)`.
+    pub fn configure_lorawan_uplink(&mut self, freq_hz: u64, sf: u8, bw: i64) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        self.configure_lorawan(freq_hz, sf, bw, false)
+    }
+
+    /// Configures the radio to receive LoRaWAN downlinks in an RX1 or RX2 window at `freq_hz`, like
+    /// `configure_lorawan_uplink` but with the CRC off and IQ inverted, as gateways send downlinks.
+    pub fn configure_lorawan_rx_window(&mut self, freq_hz: u64, sf: u8, bw: i64) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        self.configure_lorawan(freq_hz, sf, bw, true)
+    }
+
+    fn configure_lorawan(&mut self, freq_hz: u64, sf: u8, bw: i64, downlink: bool) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        if !(7..=12).contains(&sf) || ![125_000, 250_000, 500_000].contains(&bw) {
+            return Err(InvalidInput);
+        }
+        self.set_frequency_hz(freq_hz)?;
+        self.set_spreading_factor(sf)?;
+        self.set_signal_bandwidth(bw)?;
+        self.set_coding_rate_4(5)?;
+        self.set_preamble_length(8)?;
+        self.set_public_network(true)?;
+        self.set_explicit_header_mode()?;
+        self.set_crc(!downlink)?;
+        self.set_invert_iq(downlink)
+    }
+
+    /// Keeps the radio listening in `RxContinuous` with the `rx` settings of `config`, e.g. the RX2
+    /// parameters of a LoRaWAN Class C device, and returns without blocking. Packets are read as
+    /// after `arm_rx`. Transmissions go through `interrupt_for_tx`, which returns to listening.
+    pub fn continuous_listen(&mut self, config: ListenConfig) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        self.set_mode(RadioMode::Stdby)?;
+        self.apply_channel_config(&config.rx)?;
+        self.arm_rx()?;
+        self.listen = Some(config);
+        Ok(())
+    }
+
+    /// Leaves `continuous_listen` to transmit `payload` with the `tx` settings, blocks until
+    /// `TxDone` and listens again with the `rx` settings, frequency and IQ inversion included. A
+    /// transmission not done after its time on air times `TX_TIMEOUT_MARGIN` returns
+    /// `Err(Timeout)`. The IRQ flags raised by the transmission are cleared before listening again,
+    /// so they are never seen by the reception. Listening is resumed even if the transmission
+    /// failed, every step being attempted, and the first error is returned afterwards. Returns `Err(Busy)` without leaving reception if a packet is being
+    /// received or waits to be read, and `Err(InvalidInput)` if `continuous_listen` wasn't called.
+    pub fn interrupt_for_tx(
+        &mut self,
+        payload: &[u8],
+        delay: &mut dyn DelayMs<u8>,
+    ) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        let config = self.listen.ok_or(InvalidInput)?;
+        let mut clock = DelayClock::new(delay);
+        self.guard_modem_clear(&mut clock)?;
+        if self.receiving()? || self.read_register(Register::RegIrqFlags)? & IrqMask::RxDone.addr() != 0 {
+            return Err(Busy);
+        }
+        self.set_mode(RadioMode::Stdby)?;
+        let result = self.apply_channel_config(&config.tx).and_then(|()| {
+            // The time on air of the tx settings, just applied
+            let deadline_ms = self.tx_deadline_ms(clock.now_ms(), payload.len().min(MAX_PAYLOAD_LEN) as u8)?;
+            self.transmit_payload(payload)?;
+            self.wait_for_tx_done_until(Some(deadline_ms), &mut clock)
+        });
+        let standby = match result {
+            Ok(()) => Ok(()),
+            Err(_) => self.set_mode(RadioMode::Stdby),
+        };
+        let cleared = self.write_register(Register::RegIrqFlags, 0xff);
+        let listening = self
+            .apply_channel_config(&config.rx)
+            .and_then(|()| self.arm_rx());
+        result.and(standby).and(cleared).and(listening)
+    }
+
+    /// Opens the receive windows following a transmission, LoRaWAN Class A style: `w1`, then `w2`
+    /// if nothing was received in `w1`. Call it right after `TxDone`, e.g. after `wait_for_tx_done`,
+    /// the window offsets being counted from the call. Each window is received in `RxSingle` with
+    /// its channel settings and the symbol timeout matching its duration, see `RxWindow`. Returns
+    /// the size and signal quality of the first packet received, to be read as after `receive`, or
+    /// `None` if both windows timed out. The radio is left in standby with the settings of the last
+    /// window opened.
+    ///
+    /// Timing: each window is configured before its offset and opens once the delays waited add up
+    /// to it, so it opens late by the time spent on SPI transfers while waiting, up to a
+    /// millisecond of delay resolution, and the receiver startup of about 0.1 ms. The preamble of
+    /// the packet must still have at least 4 symbols to go by then for the radio to lock on, so
+    /// `offset_ms` should be early by these margins, the symbol timeout covering the extra wait.
+    /// See `open_rx_windows_with_clock` to count the SPI time as well.
+    pub fn open_rx_windows(
+        &mut self,
+        delay: &mut dyn DelayMs<u8>,
+        w1: RxWindow,
+        w2: Option<RxWindow>,
+    ) -> Result<Option<(usize, PacketInfo)>, Error<E, CS::Error, RESET::Error>> {
+        let mut clock = DelayClock::new(delay);
+        let tx_done_ms = clock.now_ms();
+        self.open_rx_windows_with_clock(&mut clock, tx_done_ms, w1, w2)
+    }
+
+    /// Same as `open_rx_windows`, but with the window offsets counted from `tx_done_ms`, the time
+    /// of `TxDone` on `clock`, e.g. timestamped from the DIO0 interrupt. The timing error is then
+    /// the resolution of `clock`, plus the SPI transfer entering `RxSingle` and the receiver
+    /// startup.
+    pub fn open_rx_windows_with_clock(
+        &mut self,
+        clock: &mut impl Clock,
+        tx_done_ms: u64,
+        w1: RxWindow,
+        w2: Option<RxWindow>,
+    ) -> Result<Option<(usize, PacketInfo)>, Error<E, CS::Error, RESET::Error>> {
+        for window in core::iter::once(w1).chain(w2) {
+            self.set_mode(RadioMode::Stdby)?;
+            self.apply_channel_config(&window.channel)?;
+            let symbol_us = u64::from(self.symbol_duration_us()?.max(1));
+            let symbols = (u64::from(window.duration_ms) * 1000 / symbol_us).clamp(4, 1023);
+            self.set_symbol_timeout(symbols as u16)?;
+            // A packet found at the end of the window may take up to the longest time on air
+            let open_ms = tx_done_ms + u64::from(window.offset_ms);
+            let deadline_ms = open_ms + u64::from(window.duration_ms) + u64::from(self.time_on_air_ms(255)?);
+            wait_until_ms(clock, open_ms);
+            match self.receive_until(RxMode::Single, Some(deadline_ms), clock) {
+                Ok(size) => return Ok(Some((size, self.get_packet_info()?))),
+                Err(Timeout) => self.set_mode(RadioMode::Stdby)?,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Applies the channel settings of `config`: frequency, spreading factor, bandwidth, CRC and IQ
+    /// inversion. With the modem clear guard enabled, see `set_modem_clear_guard`, the modem is
+    /// first waited for, `Err(ChannelBusy)` being returned without changing any setting if it
+    /// doesn't clear.
+    pub fn apply_config(
+        &mut self,
+        config: &ChannelConfig,
+        delay: &mut dyn DelayMs<u8>,
+    ) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        self.guard_modem_clear(&mut DelayClock::new(delay))?;
+        self.apply_channel_config(config)
+    }
+
+    fn apply_channel_config(&mut self, config: &ChannelConfig) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        self.set_frequency_hz(config.frequency_hz)?;
+        self.set_spreading_factor(config.spreading_factor)?;
+        self.set_signal_bandwidth(config.bandwidth)?;
+        self.set_crc(config.crc)?;
+        self.set_invert_iq(config.invert_iq)
+    }
+
+    /// Sets the AGC reference level and step thresholds, see `AgcConfig`. A field wider than its
+    /// bits returns `Err(InvalidInput)` without touching the radio.
+    pub fn set_agc_config(&mut self, config: AgcConfig) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        let steps = [config.step2, config.step3, config.step4, config.step5];
+        if config.reference_level > 0x3f || config.step1 > 0x1f || steps.iter().any(|&step| step > 0x0f) {
+            return Err(InvalidInput);
+        }
+        let mut agc_ref = self.read_register(Register::RegAgcRef)?;
+        agc_ref.set_bits(0..6, config.reference_level);
+        self.write_register(Register::RegAgcRef, agc_ref)?;
+        let mut thresh_1 = self.read_register(Register::RegAgcThresh1)?;
+        thresh_1.set_bits(0..5, config.step1);
+        self.write_register(Register::RegAgcThresh1, thresh_1)?;
+        self.write_register(Register::RegAgcThresh2, config.step2 << 4 | config.step3)?;
+        self.write_register(Register::RegAgcThresh3, config.step4 << 4 | config.step5)
+    }
+
+    /// Returns the AGC reference level and step thresholds.
+    pub fn get_agc_config(&mut self) -> Result<AgcConfig, Error<E, CS::Error, RESET::Error>> {
+        let agc_ref = self.read_register(Register::RegAgcRef)?;
+        let thresh_1 = self.read_register(Register::RegAgcThresh1)?;
+        let thresh_2 = self.read_register(Register::RegAgcThresh2)?;
+        let thresh_3 = self.read_register(Register::RegAgcThresh3)?;
+        Ok(AgcConfig {
+            reference_level: agc_ref.get_bits(0..6),
+            step1: thresh_1.get_bits(0..5),
+            step2: thresh_2 >> 4,
+            step3: thresh_2 & 0x0f,
+            step4: thresh_3 >> 4,
+            step5: thresh_3 & 0x0f,
+        })
+    }
+
+    /// Applies a receiver profile, trading sensitivity for current draw, see `RxProfile`. The
+    /// default after initiation is `RxProfile::MaxSensitivity`.
+    pub fn set_rx_profile(&mut self, profile: RxProfile) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        let (lna_gain, lna_boost, agc_auto) = match profile {
+            RxProfile::MaxSensitivity => (1, true, true),
+            RxProfile::LowPower => (3, false, false),
+            RxProfile::Custom {
+                lna_gain,
+                lna_boost,
+                agc_auto,
+            } => (lna_gain, lna_boost, agc_auto),
+        };
+        if !(1..=6).contains(&lna_gain) {
+            return Err(InvalidInput);
+        }
+        let mut lna = self.read_register(Register::RegLna)?;
+        lna.set_bits(5..8, lna_gain);
+        lna.set_bits(0..2, if lna_boost { 0b11 } else { 0b00 });
+        self.write_register(Register::RegLna, lna)?;
+        self.set_agc_auto_on(agc_auto)
+    }
+
+    /// Read-modify-writes AgcAutoOn, RegModemConfig3 bit 2, or RegModemConfig2 bit 2 on the
+    /// SX1272.
+    fn set_agc_auto_on(&mut self, on: bool) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        match self.chip {
+            Chip::Sx1276 => {
+                let mut modem_config_3 = self.read_modem_config_3()?;
+                modem_config_3.agc_auto_on = on;
+                self.write_register(Register::RegModemConfig3, modem_config_3.to_bits())
+            }
+            Chip::Sx1272 => {
+                let mut modem_config_2 = self.get_modem_config_2()?;
+                modem_config_2.agc_auto_on = on;
+                self.write_register(Register::RegModemConfig2, modem_config_2.to_bits(self.chip))
+            }
+        }
+    }
+
+    /// Returns the coding rate denominator of the radio, `5` to `8` for coding rates of `4/5` to `4/8`.
+    pub fn get_coding_rate_4(&mut self) -> Result<u8, Error<E, CS::Error, RESET::Error>> {
+        Ok(self.read_modem_config_1()?.coding_rate + 4)
+    }
+
+    /// Returns the time on air in microseconds of a packet with a payload of `payload_len` bytes
+    /// with the current modem settings (4.1.1.7. Time on air).
+    pub fn time_on_air_us(&mut self, payload_len: u8) -> Result<u32, Error<E, CS::Error, RESET::Error>> {
+        let sf = i64::from(self.get_spreading_factor()?);
+        let bw = self.get_signal_bandwidth()?;
+        if bw <= 0 {
+            return Err(InvalidInput);
+        }
+        let cr = i64::from(self.get_coding_rate_4()? - 4);
+        let preamble = i64::from(self.get_preamble_length()?);
+        let crc = i64::from(self.get_crc_enabled()?);
+        let implicit_header = i64::from(!self.explicit_header);
+        let low_data_rate = i64::from(self.get_low_data_rate_optimize()?);
+
+        let numerator = 8 * i64::from(payload_len) - 4 * sf + 28 + 16 * crc - 20 * implicit_header;
+        let denominator = 4 * (sf - 2 * low_data_rate);
+        let payload_symbols = if numerator > 0 {
+            8 + (numerator + denominator - 1) / denominator * (cr + 4)
         } else {
-            self.write_register(Register::RegModemConfig2, modem_config_2 & 0xfb)
+            8
+        };
+        // The preamble lasts the programmed length plus 4.25 symbols, count in quarter symbols
+        let quarter_symbols = (preamble + payload_symbols) * 4 + 17;
+        Ok((quarter_symbols * (1 << sf) * 1_000_000 / (bw * 4)) as u32)
+    }
+
+    /// Returns the duration of a symbol in microseconds with the current modem settings, rounded
+    /// down: `2^SF / BW` (4.1.1.5. Symbol Rate).
+    pub fn symbol_duration_us(&mut self) -> Result<u32, Error<E, CS::Error, RESET::Error>> {
+        let sf = self.get_spreading_factor()?;
+        let bw = self.get_signal_bandwidth()?;
+        if bw <= 0 {
+            return Err(InvalidInput);
+        }
+        Ok(((1i64 << sf) * 1_000_000 / bw) as u32)
+    }
+
+    /// Returns the time on air in milliseconds, rounded up, of a packet with a payload of
+    /// `payload_len` bytes with the current modem settings.
+    pub fn time_on_air_ms(&mut self, payload_len: u8) -> Result<u32, Error<E, CS::Error, RESET::Error>> {
+        Ok(self.time_on_air_us(payload_len)?.div_ceil(1000))
+    }
+
+    /// Returns the bitrate in bits per second of the current modem settings, rounded down:
+    /// `SF * BW / 2^SF * 4 / (4 + CR)` (4.1.1.3. LoRa Transmission Parameter Relationship). Headers,
+    /// preamble and CRC are not accounted for, see `time_on_air_us` for the duration of a packet.
+    pub fn get_bitrate(&mut self) -> Result<u32, Error<E, CS::Error, RESET::Error>> {
+        let sf = u64::from(self.get_spreading_factor()?);
+        let bw = self.get_signal_bandwidth()?;
+        if bw <= 0 {
+            return Err(InvalidInput);
+        }
+        let denominator = u64::from(self.get_coding_rate_4()?);
+        Ok((sf * bw as u64 * 4 / ((1u64 << sf) * denominator)) as u32)
+    }
+
+    /// Sets the maximum time on air of a single transmission. `transmit_payload` returns
+    /// `Err(DwellTimeExceeded)` instead of transmitting a packet that would take longer. `None`
+    /// disables the check. Default value is `None`.
+    pub fn set_dwell_time_limit_ms(&mut self, limit_ms: Option<u32>) {
+        self.dwell_time_limit_ms = limit_ms;
+    }
+
+    /// Sets the dwell time limit of a regulatory region, e.g. 400 ms for `Region::Us915`.
+    pub fn set_region(&mut self, region: Region) {
+        self.dwell_time_limit_ms = region.dwell_time_limit_ms();
+    }
+
+    /// Returns `Err(DwellTimeExceeded)` if a packet with a payload of `payload_len` bytes would take
+    /// longer than the dwell time limit with the current modem settings.
+    pub fn check_dwell_time(&mut self, payload_len: u8) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        if let Some(limit_ms) = self.dwell_time_limit_ms {
+            let airtime_ms = self.time_on_air_ms(payload_len)?;
+            if airtime_ms > limit_ms {
+                return Err(DwellTimeExceeded { airtime_ms, limit_ms });
+            }
         }
+        Ok(())
+    }
+
+    /// Returns the spreading factor of the radio.
+    pub fn get_spreading_factor(&mut self) -> Result<u8, Error<E, CS::Error, RESET::Error>> {
+        Ok(self.get_modem_config_2()?.spreading_factor)
+    }
+
+    /// Returns all fields of RegModemConfig2 from a single register read. On the SX1272, bit 2 is
+    /// AgcAutoOn rather than the CRC flag and is decoded as `agc_auto_on`, see `get_crc_enabled`.
+    pub fn get_modem_config_2(&mut self) -> Result<ModemConfig2, Error<E, CS::Error, RESET::Error>> {
+        Ok(ModemConfig2::from_bits(self.read_register(Register::RegModemConfig2)?, self.chip))
     }
 
-    /// Inverts the radio's IQ signals. Default value is `false`.
-    pub fn set_invert_iq(&mut self, value: bool) -> Result<(), Error<E, CS::Error, RESET::Error>> {
-        if value {
-            self.write_register(Register::RegInvertiq, 0x66)?;
-            self.write_register(Register::RegInvertiq2, 0x19)
-        } else {
-            self.write_register(Register::RegInvertiq, 0x27)?;
-            self.write_register(Register::RegInvertiq2, 0x1d)
+    /// Returns true if the radio's CRC check is enabled.
+    pub fn get_crc_enabled(&mut self) -> Result<bool, Error<E, CS::Error, RESET::Error>> {
+        if self.chip == Chip::Sx1272 {
+            return Ok(self.read_modem_config_1()?.rx_payload_crc_on);
         }
+        Ok(self.get_modem_config_2()?.rx_payload_crc_on)
     }
 
-    /// Returns the spreading factor of the radio.
-    pub fn get_spreading_factor(&mut self) -> Result<u8, Error<E, CS::Error, RESET::Error>> {
-        Ok(self.read_register(Register::RegModemConfig2)? >> 4)
+    /// Returns true if the radio is set to transmit continuously (TxContinuousMode).
+    pub fn get_tx_continuous_mode(&mut self) -> Result<bool, Error<E, CS::Error, RESET::Error>> {
+        Ok(self.get_modem_config_2()?.tx_continuous_mode)
     }
 
     /// Returns the signal bandwidth of the radio.
     pub fn get_signal_bandwidth(&mut self) -> Result<i64, Error<E, CS::Error, RESET::Error>> {
-        let bw = self.read_register(Register::RegModemConfig1)? >> 4;
-        let bw = match bw {
+        let bw = match self.read_modem_config_1()?.bandwidth {
             0 => 7_800,
             1 => 10_400,
             2 => 15_600,
@@ -675,53 +3026,359 @@ where
         Ok(bw)
     }
 
+    /// Returns the RSSI in dBm of the last received packet, RegPktRssiValue. The value is latched
+    /// by the radio at RxDone and stays the same until the next packet, so this is the one to log
+    /// with a packet. See `get_current_rssi` for the live RSSI of the channel.
+    pub fn get_last_packet_rssi(&mut self) -> Result<i32, Error<E, CS::Error, RESET::Error>> {
+        Ok(i32::from(self.read_register(Register::RegPktRssiValue)?) + self.rssi_offset())
+    }
+
     /// Returns the RSSI of the last received packet.
+    #[deprecated(note = "renamed to `get_last_packet_rssi`, not to be confused with `get_current_rssi`")]
     pub fn get_packet_rssi(&mut self) -> Result<i32, Error<E, CS::Error, RESET::Error>> {
-        Ok(i32::from(self.read_register(Register::RegPktRssiValue)?) - 157)
+        self.get_last_packet_rssi()
+    }
+
+    /// Returns the current RSSI of the channel in dBm, RegRssiValue. The value is live, measured
+    /// continuously while the radio is in a receive mode, e.g. to check whether the channel is
+    /// clear, and says nothing about the last packet once it was received. See
+    /// `get_last_packet_rssi` for the RSSI of a packet.
+    pub fn get_current_rssi(&mut self) -> Result<i32, Error<E, CS::Error, RESET::Error>> {
+        Ok(i32::from(self.read_register(Register::RegRssiValue)?) + self.rssi_offset())
+    }
+
+    /// Sets by how many dB the RSSI must rise during a reception for `collision_detected` to report
+    /// a collision. Default value is `10`.
+    pub fn set_collision_threshold(&mut self, db: u8) {
+        self.collision_threshold_db = db;
+    }
+
+    /// Returns true if a stronger signal started while a packet is being received, i.e. the RSSI
+    /// rose by the collision threshold (see `set_collision_threshold`) since the first call during
+    /// this reception. Call it repeatedly while receiving. This tells a packet lost to a strong
+    /// interferer from a plain CRC error. RegRssiCollision only exists in FSK/OOK mode, so in LoRa
+    /// mode the detection is done here from RegRssiValue.
+    pub fn collision_detected(&mut self) -> Result<bool, Error<E, CS::Error, RESET::Error>> {
+        if !self.receiving()? {
+            self.collision_baseline = None;
+            return Ok(false);
+        }
+        let rssi = self.get_current_rssi()?;
+        let baseline = *self.collision_baseline.get_or_insert(rssi);
+        Ok(rssi - baseline >= i32::from(self.collision_threshold_db))
+    }
+
+    /// Puts the radio in `RxContinuous` and returns the mean of `samples` RSSI readings in dBm, taken
+    /// 1 ms apart. This is useful to find the noise floor of a channel before using it. `samples`
+    /// must be at least `1`.
+    pub fn measure_noise_floor(
+        &mut self,
+        samples: u16,
+        delay: &mut dyn DelayMs<u8>,
+    ) -> Result<i32, Error<E, CS::Error, RESET::Error>> {
+        if samples == 0 {
+            return Err(InvalidInput);
+        }
+        self.set_mode(RadioMode::RxContinuous)?;
+        let mut sum: i32 = 0;
+        for _ in 0..samples {
+            delay.delay_ms(1);
+            sum += self.get_current_rssi()?;
+        }
+        Ok(sum / i32::from(samples))
+    }
+
+    /// Surveys the channels at `freqs`, in Hz, e.g. before picking the channels of a new site. Each
+    /// channel is listened to in `RxContinuous` for `dwell_ms`, the RSSI being sampled every
+    /// millisecond, then checked for LoRa activity with `cad`, at the current spreading factor and
+    /// bandwidth. `out[i]` is filled with the report of `freqs[i]`, and the number of channels
+    /// scanned is returned, the shorter of both slices. The frequency and mode are restored
+    /// afterwards, also when the scan failed, with `Err(PllLockFailed)` if the receiver could not
+    /// be restarted. `RxSingle` is restored as a new single reception, its symbol timeout starting
+    /// over, and a transmission or CAD that already completed is restored as standby, the radio
+    /// having returned to it on its own. Returns `Err(InvalidInput)` if `dwell_ms` is `0` or if a
+    /// transmission or CAD is in progress, and `Err(Busy)` if a packet reported by `poll_irq` or
+    /// `receive` waits to be read, as restarting the receiver would drop it.
+    pub fn scan_channels(
+        &mut self,
+        delay: &mut dyn DelayMs<u8>,
+        freqs: &[u64],
+        dwell_ms: u16,
+        out: &mut [ChannelReport],
+    ) -> Result<usize, Error<E, CS::Error, RESET::Error>> {
+        if dwell_ms == 0 {
+            return Err(InvalidInput);
+        }
+        if self.rx_packet.is_some() {
+            return Err(Busy);
+        }
+        let frequency = self.frequency;
+        let mode = match self.mode {
+            mode @ (RadioMode::Tx | RadioMode::Cad) => {
+                if self.read_register(Register::RegOpMode)? & 0x07 == mode as u8 {
+                    return Err(InvalidInput);
+                }
+                RadioMode::Stdby
+            }
+            mode => mode,
+        };
+        let result = out.iter_mut().zip(freqs).try_fold(0, |count, (report, &freq)| {
+            *report = self.scan_channel(delay, freq, dwell_ms)?;
+            Ok(count + 1)
+        });
+        self.set_mode(RadioMode::Stdby)?;
+        self.set_frequency_hz(frequency)?;
+        match mode {
+            RadioMode::RxContinuous | RadioMode::RxSingle => self.set_mode_pll_checked(mode)?,
+            mode => self.set_mode(mode)?,
+        }
+        result
     }
 
-    /// Returns the signal to noise radio of the the last received packet.
+    fn scan_channel(
+        &mut self,
+        delay: &mut dyn DelayMs<u8>,
+        freq: u64,
+        dwell_ms: u16,
+    ) -> Result<ChannelReport, Error<E, CS::Error, RESET::Error>> {
+        self.set_mode(RadioMode::Stdby)?;
+        self.set_frequency_hz(freq)?;
+        self.set_mode(RadioMode::RxContinuous)?;
+        let (mut min_rssi, mut max_rssi, mut sum) = (i32::MAX, i32::MIN, 0);
+        for _ in 0..dwell_ms {
+            delay.delay_ms(1);
+            let rssi = self.get_current_rssi()?;
+            min_rssi = min_rssi.min(rssi);
+            max_rssi = max_rssi.max(rssi);
+            sum += rssi;
+        }
+        Ok(ChannelReport {
+            frequency_hz: freq,
+            min_rssi,
+            mean_rssi: sum / i32::from(dwell_ms),
+            max_rssi,
+            lora_activity: self.cad(delay)?,
+        })
+    }
+
+    /// Returns the offset in dB added to the raw RSSI registers (5.5.5. RSSI and SNR in LoRa Mode),
+    /// `-157` on the high frequency port and `-164` on the low frequency port (below 525 MHz). The
+    /// single port SX1272 uses `-139` on all frequencies.
+    pub fn rssi_offset(&self) -> i32 {
+        match (self.chip, self.band()) {
+            (Chip::Sx1272, _) => -139,
+            (Chip::Sx1276, Band::Lf) => -164,
+            (Chip::Sx1276, Band::Hf) => -157,
+        }
+    }
+
+    /// Returns the frequency band the radio is tuned in, from the frequency last set. This selects
+    /// the RSSI offset and the LowFrequencyModeOn bit written by `set_mode`.
+    pub fn band(&self) -> Band {
+        Band::from_hz(self.frequency)
+    }
+
+    /// Returns the chip variant detected from RegVersion at construction, see `Chip`.
+    pub fn chip(&self) -> Chip {
+        self.chip
+    }
+
+    /// Returns the value of RegVersion read at construction.
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// Reads RegVersion again and returns the chip variant it identifies, which is then used for
+    /// all register accesses, or `Err(VersionMismatch)` if it is not a supported chip and
+    /// `Err(NoResponse)` if the radio doesn't answer, see `LoRaBuilder::build`.
+    pub fn detect_chip(&mut self) -> Result<Chip, Error<E, CS::Error, RESET::Error>> {
+        let version = self.read_register(Register::RegVersion)?;
+        self.chip = chip_from_version(version)?;
+        Ok(self.chip)
+    }
+
+    /// Returns true if the LowFrequencyModeOn bit of RegOpMode is set, which should match
+    /// `band()` being `Band::Lf`.
+    pub fn is_low_frequency_mode(&mut self) -> Result<bool, Error<E, CS::Error, RESET::Error>> {
+        Ok(self.read_register(Register::RegOpMode)?.get_bit(3))
+    }
+
+    /// Returns the signal to noise radio of the the last received packet in dB.
     pub fn get_packet_snr(&mut self) -> Result<f64, Error<E, CS::Error, RESET::Error>> {
-        Ok(f64::from(
-            self.read_register(Register::RegPktSnrValue)?,
+        Ok(f64::from(self.get_packet_snr_quarter_db()?) / 4.0)
+    }
+
+    /// Returns the signal to noise radio of the the last received packet in steps of 0.25 dB, e.g.
+    /// `-20` for -5 dB. This avoids floating point math on targets without an FPU.
+    pub fn get_packet_snr_quarter_db(&mut self) -> Result<i16, Error<E, CS::Error, RESET::Error>> {
+        // RegPktSnrValue is a signed value in steps of 0.25 dB
+        Ok(i16::from(self.read_register(Register::RegPktSnrValue)? as i8))
+    }
+
+    /// Returns the RSSI in dBm and the SNR in dB of the last received packet, read back to back so
+    /// that both belong to the same packet.
+    pub fn last_packet_signal(&mut self) -> Result<(i32, f64), Error<E, CS::Error, RESET::Error>> {
+        let rssi = self.read_register(Register::RegPktRssiValue)?;
+        let snr = self.read_register(Register::RegPktSnrValue)?;
+        Ok((
+            i32::from(rssi) + self.rssi_offset(),
+            f64::from(snr as i8) / 4.0,
         ))
     }
 
+    /// Returns the parameters the demodulator decoded from the explicit header of the last received
+    /// packet. The spreading factor and bandwidth are not reported by the radio since they must match
+    /// the local configuration for a packet to be received at all.
+    pub fn get_rx_header_info(&mut self) -> Result<RxHeaderInfo, Error<E, CS::Error, RESET::Error>> {
+        let modem_stat = self.read_register(Register::RegModemStat)?;
+        let hop_channel = self.read_register(Register::RegHopChannel)?;
+        let payload_length = self.read_register(Register::RegRxNbBytes)?;
+        Ok(RxHeaderInfo {
+            coding_rate: modem_stat.get_bits(5..8) + 4,
+            crc_on_payload: hop_channel.get_bit(6),
+            payload_length,
+        })
+    }
+
+    /// Returns the number of valid headers received since the radio last entered a receive mode.
+    /// The count wraps around at 65535.
+    pub fn get_rx_header_count(&mut self) -> Result<u16, Error<E, CS::Error, RESET::Error>> {
+        self.read_u16_be(Register::RegRxHeaderCntValueMsb, Register::RegRxHeaderCntValueLsb)
+    }
+
+    /// Returns the number of valid packets received since the radio last entered a receive mode,
+    /// packets with a CRC error excluded. The count wraps around at 65535.
+    pub fn get_rx_packet_count(&mut self) -> Result<u16, Error<E, CS::Error, RESET::Error>> {
+        self.read_u16_be(Register::RegRxPacketCntValueMsb, Register::RegRxPacketCntValueLsb)
+    }
+
+    /// Returns the valid header and valid packet counts, then resets both by returning to standby
+    /// and entering the receive mode again, e.g. to compute the packet error rate over fixed
+    /// intervals. Only RegOpMode is written for the reset, right after the counts are read, and a
+    /// packet being received is dropped. If the radio is not receiving the counts are returned
+    /// as they are, the next receive mode resetting them. Returns `Err(Busy)` without reading or
+    /// resetting anything while a packet reported by `poll_irq` or `receive` waits to be read,
+    /// since entering the receive mode again would drop it.
+    pub fn take_rx_counters(&mut self) -> Result<(u16, u16), Error<E, CS::Error, RESET::Error>> {
+        if self.rx_packet.is_some() {
+            return Err(Busy);
+        }
+        let headers = self.get_rx_header_count()?;
+        let packets = self.get_rx_packet_count()?;
+        let mode = self.mode;
+        if matches!(mode, RadioMode::RxContinuous | RadioMode::RxSingle) {
+            self.write_op_mode(RadioMode::Stdby)?;
+            self.write_op_mode(mode)?;
+        }
+        Ok((headers, packets))
+    }
+
+    /// Same as `read_packet_into`, but also returns the framing of the packet, e.g. to find out the
+    /// settings of an unknown transmitter. See `ReceivedFrameInfo` for which fields come from the
+    /// local configuration or are read after the packet rather than decoded from it.
+    pub fn read_packet_with_frame_info(
+        &mut self,
+        buffer: &mut [u8],
+    ) -> Result<(usize, ReceivedFrameInfo), Error<E, CS::Error, RESET::Error>> {
+        let size = self.read_packet_into(buffer)?;
+        let header = if self.explicit_header {
+            Some(RxHeaderInfo {
+                payload_length: size as u8,
+                ..self.get_rx_header_info()?
+            })
+        } else {
+            None
+        };
+        let modem_stat = self.read_register(Register::RegModemStat)?;
+        let info = ReceivedFrameInfo {
+            explicit_header: self.explicit_header,
+            header,
+            preamble_length: self.get_preamble_length()?,
+            crc_error: self.rx_crc_error,
+            signal_detected: modem_stat.get_bit(0),
+            signal_synchronized: modem_stat.get_bit(1),
+        };
+        Ok((size, info))
+    }
+
+    /// Returns the RSSI, SNR and frequency error of the last received packet, along with the RSSI
+    /// offset that was applied.
+    pub fn get_packet_info(&mut self) -> Result<PacketInfo, Error<E, CS::Error, RESET::Error>> {
+        let (rssi, snr) = self.last_packet_signal()?;
+        Ok(PacketInfo {
+            rssi,
+            snr,
+            frequency_error: self.get_packet_frequency_error()?,
+            rssi_offset: self.rssi_offset(),
+        })
+    }
+
+    /// Adds the RSSI, SNR, frequency error and CRC result of the last received packet to `stats`,
+    /// timestamped with `clock` if one is provided.
+    pub fn record_link_stats<const N: usize>(
+        &mut self,
+        stats: &mut LinkStats<N>,
+        clock: Option<&mut dyn Clock>,
+    ) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        let info = self.get_packet_info()?;
+        let timestamp_ms = clock.map(|clock| clock.now_ms());
+        stats.push(LinkSample::new(&info, self.rx_crc_error, timestamp_ms));
+        Ok(())
+    }
+
     /// Returns the frequency error of the last received packet in Hz.
     pub fn get_packet_frequency_error(&mut self) -> Result<i64, Error<E, CS::Error, RESET::Error>> {
-        let mut freq_error: i32 = 0;
-        freq_error = i32::from(self.read_register(Register::RegFreqErrorMsb)? & 0x7);
-        freq_error <<= 8i64;
-        freq_error += i32::from(self.read_register(Register::RegFreqErrorMid)?);
-        freq_error <<= 8i64;
-        freq_error += i32::from(self.read_register(Register::RegFreqErrorLsb)?);
-
-        let f_xtal = 32_000_000; // FXOSC: crystal oscillator (XTAL) frequency (2.5. Chip Specification, p. 14)
-        let f_error = ((f64::from(freq_error) * (1i64 << 24) as f64) / f64::from(f_xtal))
-            * (self.get_signal_bandwidth()? as f64 / 500_000.0f64); // p. 37
-        Ok(f_error as i64)
+        let fei = self.read_u24_be(
+            Register::RegFreqErrorMsb,
+            Register::RegFreqErrorMid,
+            Register::RegFreqErrorLsb,
+        )?;
+        // RegFei is a 20 bit two's complement value, the top 4 bits of the MSB are unused
+        let freq_error = sign_extend(fei, 20);
+        let bw = self.get_signal_bandwidth()?;
+        Ok(frequency_error_hz(freq_error, bw, self.fxosc_hz))
     }
 
     fn set_ldo_flag(&mut self) -> Result<(), Error<E, CS::Error, RESET::Error>> {
         let sw = self.get_signal_bandwidth()?;
         // Section 4.1.1.5
-        let symbol_duration = 1000 / (sw / ((1 as i64) << self.get_spreading_factor()?));
+        let symbol_duration = 1000 / (sw / (1i64 << self.get_spreading_factor()?));
 
         // Section 4.1.1.6
         let ldo_on = symbol_duration > 16;
 
-        let mut config_3 = self.read_register(Register::RegModemConfig3)?;
-        config_3.set_bit(3, ldo_on);
-        self.write_register(Register::RegModemConfig3, config_3)
+        match self.chip {
+            Chip::Sx1276 => {
+                let mut modem_config_3 = self.read_modem_config_3()?;
+                modem_config_3.low_data_rate_optimize = ldo_on;
+                self.write_register(Register::RegModemConfig3, modem_config_3.to_bits())
+            }
+            Chip::Sx1272 => {
+                let mut modem_config_1 = self.read_modem_config_1()?;
+                modem_config_1.low_data_rate_optimize = ldo_on;
+                self.write_register(Register::RegModemConfig1, modem_config_1.to_bits(self.chip))
+            }
+        }
     }
 
-    pub fn read_register(&mut self, reg: Register) -> Result<u8, Error<E, CS::Error, RESET::Error>> {
-        self.cs.set_low().map_err(CS)?;
+    /// Returns LowDataRateOptimize, in RegModemConfig3 or RegModemConfig1 on the SX1272.
+    fn get_low_data_rate_optimize(&mut self) -> Result<bool, Error<E, CS::Error, RESET::Error>> {
+        match self.chip {
+            Chip::Sx1276 => Ok(self.read_modem_config_3()?.low_data_rate_optimize),
+            Chip::Sx1272 => Ok(self.read_modem_config_1()?.low_data_rate_optimize),
+        }
+    }
 
-        let mut buffer = [reg as u8 & 0x7f, 0];
-        let transfer = self.spi.transfer(&mut buffer).map_err(SPI)?;
-        self.cs.set_high().map_err(CS)?;
-        Ok(transfer[1])
+    fn read_modem_config_1(&mut self) -> Result<ModemConfig1, Error<E, CS::Error, RESET::Error>> {
+        Ok(ModemConfig1::from_bits(self.read_register(Register::RegModemConfig1)?, self.chip))
+    }
+
+    fn read_modem_config_3(&mut self) -> Result<ModemConfig3, Error<E, CS::Error, RESET::Error>> {
+        Ok(ModemConfig3::from_bits(self.read_register(Register::RegModemConfig3)?))
+    }
+
+    pub fn read_register(&mut self, reg: Register) -> Result<u8, Error<E, CS::Error, RESET::Error>> {
+        self.read_address(reg as u8)
     }
 
     fn write_register(
@@ -729,11 +3386,300 @@ where
         reg: Register,
         byte: u8,
     ) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        self.write_address(reg as u8, byte)?;
+        self.config_shadow.record(reg, byte);
+        Ok(())
+    }
+
+    /// Writes `byte` to `reg` unless it already holds it, the value being remembered for
+    /// `verify_config` either way.
+    fn write_register_if_changed(&mut self, reg: Register, byte: u8) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        if self.read_register(reg)? == byte {
+            self.config_shadow.record(reg, byte);
+            return Ok(());
+        }
+        self.write_register(reg, byte)
+    }
+
+    /// Takes the settings cached by the driver from the registers of a radio left configured in
+    /// LoRa mode, `op_mode` being the value of RegOpMode, without writing to it. The configuration
+    /// registers are remembered as they are for `verify_config`.
+    fn adopt_config(&mut self, op_mode: u8) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        self.frequency = self.get_frequency_hz()?;
+        self.explicit_header = !self.read_modem_config_1()?.implicit_header;
+        self.header_written = Some(self.explicit_header);
+        self.max_payload_length = self.read_register(Register::RegMaxPayloadLength)?;
+        self.pa_output = if self.read_register(Register::RegPaConfig)?.get_bit(7) {
+            PaOutput::PaBoost
+        } else {
+            PaOutput::Rfo
+        };
+        for reg in ConfigShadow::registers() {
+            let value = self.read_register(reg)?;
+            self.config_shadow.record(reg, value);
+        }
+        self.mode = match op_mode & 0x07 {
+            0x00 => RadioMode::Sleep,
+            0x01 => RadioMode::Stdby,
+            0x03 => RadioMode::Tx,
+            0x05 => RadioMode::RxContinuous,
+            0x06 => RadioMode::RxSingle,
+            0x07 => RadioMode::Cad,
+            // Frequency synthesis modes, only entered on the way to TX or RX
+            _ => return self.set_mode(RadioMode::Stdby),
+        };
+        Ok(())
+    }
+
+    /// Reads back the configuration registers written by the driver and returns
+    /// `Err(ConfigMismatch)` with the first of those that changed since, e.g. after an ESD event or
+    /// a brown-out reset the radio. Registers never written by the driver are not checked. See
+    /// `find_config_mismatches` to list all of them and `repair_config`.
+    pub fn verify_config(&mut self) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        match self.find_config_mismatches()?.iter().next() {
+            Some(&mismatch) => Err(ConfigMismatch(mismatch)),
+            None => Ok(()),
+        }
+    }
+
+    /// Same as `verify_config`, but returns every configuration register that changed, the result
+    /// being empty if none did.
+    pub fn find_config_mismatches(&mut self) -> Result<verify::ConfigMismatches, Error<E, CS::Error, RESET::Error>> {
+        let mut mismatches = verify::ConfigMismatches::new();
+        let shadow = self.config_shadow;
+        for (reg, expected, mask) in shadow.entries() {
+            let actual = self.read_register(reg)?;
+            if (actual ^ expected) & mask != 0 {
+                mismatches.push(RegisterMismatch {
+                    register: reg as u8,
+                    expected,
+                    actual,
+                });
+            }
+        }
+        Ok(mismatches)
+    }
+
+    /// Writes the last written value again to the configuration registers that changed, see
+    /// `verify_config`, returning how many were rewritten.
+    pub fn repair_config(&mut self) -> Result<usize, Error<E, CS::Error, RESET::Error>> {
+        let mut repaired = 0;
+        let shadow = self.config_shadow;
+        for (reg, expected, mask) in shadow.entries() {
+            if (self.read_register(reg)? ^ expected) & mask != 0 {
+                self.write_register(reg, expected)?;
+                repaired += 1;
+            }
+        }
+        Ok(repaired)
+    }
+
+    /// Reads registers 0x01 to 0x45 of the LoRa page into `out` in a single burst, for field
+    /// debugging, see `dump::write_register_dump` to print it. Nothing is written to the radio and
+    /// the registers in `dump::SKIPPED_REGISTERS` are not read, their bytes being left at `0`.
+    pub fn dump_registers(&mut self, out: &mut [u8; REGISTER_DUMP_LEN]) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        // The burst starts right after RegFifo
+        self.read_burst(0x01, &mut out[1..])?;
+        for &addr in SKIPPED_REGISTERS.iter() {
+            out[addr as usize] = 0;
+        }
+        Ok(())
+    }
+
+    fn read_fsk_register(&mut self, reg: FskRegister) -> Result<u8, Error<E, CS::Error, RESET::Error>> {
+        self.read_address(reg as u8)
+    }
+
+    fn write_fsk_register(&mut self, reg: FskRegister, byte: u8) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        self.write_address(reg as u8, byte)
+    }
+
+    /// Reads the register at `addr`. Like every SPI access, CS is deasserted before returning, also
+    /// when the transfer failed.
+    fn read_address(&mut self, addr: u8) -> Result<u8, Error<E, CS::Error, RESET::Error>> {
+        self.cs.set_low().map_err(CS)?;
+
+        let mut buffer = [addr & 0x7f, 0];
+        let result = self.spi.transfer(&mut buffer).map(|transfer| transfer[1]);
+        self.cs.set_high().map_err(CS)?;
+        result.map_err(SPI)
+    }
+
+    fn write_address(&mut self, addr: u8, byte: u8) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        self.cs.set_low().map_err(CS)?;
+
+        let buffer = [addr | 0x80, byte];
+        let result = self.spi.write(&buffer);
+        self.cs.set_high().map_err(CS)?;
+        result.map_err(SPI)
+    }
+
+    /// Reads consecutive registers starting at `addr` into `buffer`, or `buffer.len()` bytes of the
+    /// FIFO if `addr` is RegFifo, in transfers of at most `spi_chunk_len` bytes with CS held low
+    /// throughout.
+    fn read_burst(&mut self, addr: u8, buffer: &mut [u8]) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        let chunk_len = self.spi_chunk_len;
+        let spi = &mut self.spi;
         self.cs.set_low().map_err(CS)?;
+        let result = spi.write(&[addr & 0x7f]).and_then(|_| {
+            buffer
+                .chunks_mut(chunk_len)
+                .try_for_each(|chunk| spi.transfer(chunk).map(|_| ()))
+        });
+        self.cs.set_high().map_err(CS)?;
+        result.map_err(SPI)
+    }
 
-        let buffer = [reg as u8 | 0x80, byte];
-        self.spi.write(&buffer).map_err(SPI)?;
+    /// Writes `data` to consecutive registers starting at `addr`, or to the FIFO if `addr` is
+    /// RegFifo, in transfers of at most `spi_chunk_len` bytes with CS held low throughout. The
+    /// written values are not recorded for `verify_config`, that is left to the caller.
+    fn write_burst(&mut self, addr: u8, data: &[u8]) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        let chunk_len = self.spi_chunk_len;
+        let spi = &mut self.spi;
+        self.cs.set_low().map_err(CS)?;
+        let result = spi
+            .write(&[addr | 0x80])
+            .and_then(|_| data.chunks(chunk_len).try_for_each(|chunk| spi.write(chunk)));
         self.cs.set_high().map_err(CS)?;
+        result.map_err(SPI)
+    }
+
+    /// Reads a 16 bit value split over two registers, most significant byte first.
+    fn read_u16_be(&mut self, msb: Register, lsb: Register) -> Result<u16, Error<E, CS::Error, RESET::Error>> {
+        let msb = self.read_register(msb)?;
+        let lsb = self.read_register(lsb)?;
+        Ok(u16::from_be_bytes([msb, lsb]))
+    }
+
+    /// Reads a 24 bit value split over three registers, most significant byte first.
+    fn read_u24_be(
+        &mut self,
+        msb: Register,
+        mid: Register,
+        lsb: Register,
+    ) -> Result<u32, Error<E, CS::Error, RESET::Error>> {
+        let msb = self.read_register(msb)?;
+        let mid = self.read_register(mid)?;
+        let lsb = self.read_register(lsb)?;
+        Ok(u32::from_be_bytes([0, msb, mid, lsb]))
+    }
+
+    /// Writes a 16 bit value split over two registers, most significant byte first.
+    /// The registers are consecutive and written in a single burst, so that a failed write doesn't
+    /// leave half of the value behind.
+    fn write_u16_be(&mut self, msb: Register, lsb: Register, value: u16) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        let [msb_byte, lsb_byte] = value.to_be_bytes();
+        self.write_burst(msb as u8, &[msb_byte, lsb_byte])?;
+        self.config_shadow.record(msb, msb_byte);
+        self.config_shadow.record(lsb, lsb_byte);
+        Ok(())
+    }
+
+    /// Writes the lower 24 bits of a value split over three registers, most significant byte first.
+    /// The least significant byte being written last matters for RegFrf, which only takes effect
+    /// once RegFrfLsb is written. Like `write_u16_be`, the registers are written in a single burst.
+    fn write_u24_be(
+        &mut self,
+        msb: Register,
+        mid: Register,
+        lsb: Register,
+        value: u32,
+    ) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        let [_, msb_byte, mid_byte, lsb_byte] = value.to_be_bytes();
+        self.write_burst(msb as u8, &[msb_byte, mid_byte, lsb_byte])?;
+        self.config_shadow.record(msb, msb_byte);
+        self.config_shadow.record(mid, mid_byte);
+        self.config_shadow.record(lsb, lsb_byte);
+        Ok(())
+    }
+
+    /// Configures the FSK receiver: automatic gain control, automatic frequency correction at each
+    /// receiver startup, and when the receiver restarts on its own, see `FskRxRestart`. A receiver
+    /// listening continuously needs `FskAutoRestart::On` or `OnWaitPllLock`, otherwise it stops
+    /// after the first packet. The radio must be in FSK/OOK mode, `Err(InvalidInput)` being returned
+    /// in LoRa mode where these registers hold LoRa settings.
+    pub fn set_fsk_rx_config(
+        &mut self,
+        agc_auto: bool,
+        afc_auto: bool,
+        restart: FskRxRestart,
+    ) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        self.check_fsk_mode()?;
+        let mut rx_config = self.read_fsk_register(FskRegister::RegRxConfig)?;
+        rx_config.set_bit(7, restart.on_collision);
+        // RestartRxWithoutPllLock and RestartRxWithPllLock are triggers, not settings
+        rx_config.set_bits(5..7, 0b00);
+        rx_config.set_bit(4, afc_auto);
+        rx_config.set_bit(3, agc_auto);
+        self.write_fsk_register(FskRegister::RegRxConfig, rx_config)?;
+        let mut sync_config = self.read_fsk_register(FskRegister::RegSyncConfig)?;
+        sync_config.set_bits(6..8, restart.after_packet as u8);
+        self.write_fsk_register(FskRegister::RegSyncConfig, sync_config)
+    }
+
+    /// Selects the FSK data mode, see `FskDataMode`. Continuous mode is for protocols the packet
+    /// handler can't frame, the bits being streamed on the DIO pins. The radio must be in FSK/OOK
+    /// mode, `Err(InvalidInput)` being returned in LoRa mode. Default value is
+    /// `FskDataMode::Packet`.
+    pub fn set_fsk_data_mode(&mut self, mode: FskDataMode) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        self.check_fsk_mode()?;
+        let mut packet_config_2 = self.read_fsk_register(FskRegister::RegPacketConfig2)?;
+        packet_config_2.set_bit(6, mode == FskDataMode::Packet);
+        self.write_fsk_register(FskRegister::RegPacketConfig2, packet_config_2)
+    }
+
+    /// Returns the FSK data mode. The radio must be in FSK/OOK mode.
+    pub fn get_fsk_data_mode(&mut self) -> Result<FskDataMode, Error<E, CS::Error, RESET::Error>> {
+        self.check_fsk_mode()?;
+        if self.read_fsk_register(FskRegister::RegPacketConfig2)?.get_bit(6) {
+            Ok(FskDataMode::Packet)
+        } else {
+            Ok(FskDataMode::Continuous)
+        }
+    }
+
+    /// Configures the automatic frequency correction of the FSK receiver, which measures the
+    /// frequency offset of the transmitter at each receiver startup and corrects it, tolerating
+    /// cheap crystals at both ends. With `auto_clear`, the correction is cleared at each receiver
+    /// startup instead of building on the previous one. The radio must be in FSK/OOK mode,
+    /// `Err(InvalidInput)` being returned in LoRa mode. See `get_afc_value` for the correction.
+    pub fn set_fsk_afc(&mut self, enable: bool, auto_clear: bool) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        self.check_fsk_mode()?;
+        let mut rx_config = self.read_fsk_register(FskRegister::RegRxConfig)?;
+        // RestartRxWithoutPllLock and RestartRxWithPllLock are triggers, not settings
+        rx_config.set_bits(5..7, 0b00);
+        rx_config.set_bit(4, enable);
+        self.write_fsk_register(FskRegister::RegRxConfig, rx_config)?;
+        let mut afc_fei = self.read_fsk_register(FskRegister::RegAfcFei)?;
+        // AgcStart and AfcClear are triggers as well
+        afc_fei.set_bit(4, false);
+        afc_fei.set_bit(1, false);
+        afc_fei.set_bit(0, auto_clear);
+        self.write_fsk_register(FskRegister::RegAfcFei, afc_fei)
+    }
+
+    /// Returns the frequency correction in Hz applied by the FSK AFC, RegAfc being a 16 bit two's
+    /// complement value in synthesizer steps. The radio must be in FSK/OOK mode.
+    pub fn get_afc_value(&mut self) -> Result<i64, Error<E, CS::Error, RESET::Error>> {
+        self.check_fsk_mode()?;
+        let msb = self.read_fsk_register(FskRegister::RegAfcMsb)?;
+        let lsb = self.read_fsk_register(FskRegister::RegAfcLsb)?;
+        let afc = i16::from_be_bytes([msb, lsb]);
+        Ok(hz_from_fsteps(i32::from(afc), self.fxosc_hz))
+    }
+
+    /// Returns `Err(InvalidInput)` if the radio is in LoRa mode, where the FSK registers hold LoRa
+    /// settings.
+    fn check_fsk_mode(&mut self) -> Result<(), Error<E, CS::Error, RESET::Error>> {
+        if self.read_register(Register::RegOpMode)?.get_bit(7) {
+            return Err(InvalidInput);
+        }
         Ok(())
     }
 
@@ -760,8 +3706,76 @@ where
         self.write_register(Register::RegPaRamp as u8, *pa_ramp)
     }*/
 }
+
+/// Replaces each invalid UTF-8 sequence of `bytes` with `?` bytes and returns them as a string.
+fn utf8_lossy_in_place(bytes: &mut [u8]) -> &str {
+    let mut start = 0;
+    while let Err(e) = core::str::from_utf8(&bytes[start..]) {
+        let invalid = start + e.valid_up_to();
+        // No error length means a sequence cut short by the end of the packet
+        let len = e.error_len().unwrap_or(bytes.len() - invalid);
+        bytes[invalid..invalid + len].fill(b'?');
+        start = invalid + len;
+    }
+    core::str::from_utf8(bytes).unwrap_or_default()
+}
+
+/// Returns the current limit of the over current protection set in `ocp`, the value of RegOcp, or
+/// `None` if it is off.
+fn ocp_limit_ma(ocp: u8) -> Option<u16> {
+    if !ocp.get_bit(5) {
+        return None;
+    }
+    Some(match u16::from(ocp.get_bits(0..5)) {
+        trim @ 0..=15 => 45 + 5 * trim,
+        trim @ 16..=27 => 10 * trim - 30,
+        _ => 240,
+    })
+}
+
+/// Returns true if `version` is what RegVersion reads when the radio doesn't drive MISO: `0x00`
+/// with MISO disconnected or pulled low, `0xff` with MISO stuck high.
+fn is_no_response(version: u8) -> bool {
+    version == 0x00 || version == 0xff
+}
+
+/// Returns the chip identified by `version`, `Err(NoResponse)` if the radio doesn't answer or
+/// `Err(VersionMismatch)` if it is not a supported chip.
+fn chip_from_version<E, CS, RESET>(version: u8) -> Result<Chip, Error<E, CS, RESET>> {
+    if is_no_response(version) {
+        return Err(NoResponse(version));
+    }
+    Chip::from_version(version).ok_or(VersionMismatch(version))
+}
+
+/// Waits until `clock` advanced by at least `ms`, whether its time advances on its own or through
+/// `Clock::wait_ms`.
+fn wait_at_least_ms(clock: &mut impl Clock, ms: u64) {
+    let until_ms = clock.now_ms() + ms;
+    while clock.now_ms() < until_ms {
+        clock.wait_ms(1);
+    }
+}
+
+/// Waits until `clock` reaches `until_ms`, in waits of at most 255 ms.
+fn wait_until_ms(clock: &mut impl Clock, until_ms: u64) {
+    loop {
+        let now_ms = clock.now_ms();
+        if now_ms >= until_ms {
+            return;
+        }
+        clock.wait_ms((until_ms - now_ms).min(255) as u8);
+    }
+}
+
+/// Sign-extends the two's complement value held in the lower `bits` bits of `value`.
+fn sign_extend(value: u32, bits: u32) -> i32 {
+    let shift = 32 - bits;
+    ((value << shift) as i32) >> shift
+}
+
 /// Modes of the radio and their corresponding register values.
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug)]
 pub enum RadioMode {
     LongRangeMode = 0x80,
     Sleep = 0x00,
@@ -769,8 +3783,322 @@ pub enum RadioMode {
     Tx = 0x03,
     RxContinuous = 0x05,
     RxSingle = 0x06,
+    Cad = 0x07,
+}
+
+/// Signal quality of a received packet, see `LoRa::get_packet_info`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PacketInfo {
+    /// RSSI in dBm.
+    pub rssi: i32,
+    /// SNR in dB.
+    pub snr: f64,
+    /// Frequency error in Hz.
+    pub frequency_error: i64,
+    /// Offset in dB that was added to the raw RSSI register, which depends on the band.
+    pub rssi_offset: i32,
+}
+
+/// Parameters decoded from the header of a received packet, see `LoRa::get_rx_header_info`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RxHeaderInfo {
+    /// Denominator of the coding rate, `5` to `8` for 4/5 to 4/8.
+    pub coding_rate: u8,
+    /// True if the header announced a payload CRC.
+    pub crc_on_payload: bool,
+    /// Payload length in bytes.
+    pub payload_length: u8,
+}
+
+/// Framing of a received packet, see `LoRa::read_packet_with_frame_info`.
+///
+/// Not every field is a measurement of the packet. `preamble_length` is the programmed value, not
+/// the length of the preamble received: the radio doesn't count the preamble symbols, it only needs
+/// the programmed length or fewer to lock on. `signal_detected` and `signal_synchronized` are read
+/// from RegModemStat after the packet was read, so they report the state of the receiver at that
+/// time, e.g. a following packet in `RxContinuous`, and say nothing about this packet.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReceivedFrameInfo {
+    /// True if the packet was received in explicit header mode, from the local configuration since
+    /// both ends must agree on it.
+    pub explicit_header: bool,
+    /// Header decoded by the radio, `None` in implicit header mode where there is none.
+    pub header: Option<RxHeaderInfo>,
+    /// Programmed preamble length in symbols (RegPreambleMsb/Lsb), the longest preamble the receiver
+    /// looks for, whatever the transmitter sent.
+    pub preamble_length: u16,
+    /// The payload CRC check failed.
+    pub crc_error: bool,
+    /// RegModemStat SignalDetected bit, i.e. a preamble is being detected. Read after the packet,
+    /// this is about a following packet in `RxContinuous`.
+    pub signal_detected: bool,
+    /// RegModemStat SignalSynchronized bit, i.e. the receiver locked on a preamble. Read after the
+    /// packet like `signal_detected`.
+    pub signal_synchronized: bool,
+}
+
+/// Power amplifier output pins, selected with `LoRaBuilder::pa_output`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PaOutput {
+    /// RFO pin, up to +14 dBm.
+    Rfo,
+    /// PA_BOOST pin, up to +20 dBm.
+    PaBoost,
+}
+
+/// Regulatory regions with a dwell time limit, see `LoRa::set_region`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Region {
+    /// 902-928 MHz, FCC part 15.247: 400 ms per channel.
+    Us915,
+    /// 863-870 MHz: no dwell time limit, duty cycle limits apply instead.
+    Eu868,
+}
+
+impl Region {
+    /// Returns the maximum time on air of a single transmission in milliseconds, if any.
+    pub fn dwell_time_limit_ms(self) -> Option<u32> {
+        match self {
+            Region::Us915 => Some(400),
+            Region::Eu868 => None,
+        }
+    }
+}
+
+/// Detection of transmissions that never complete, e.g. after a glitched mode write left the PA
+/// keyed, see `LoRa::set_tx_watchdog`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TxWatchdog {
+    /// A transmission is stuck once it took longer than its time on air times `margin`.
+    pub margin: u8,
+    /// Pulse the reset pin when a transmission is stuck and write the configuration again, rather
+    /// than only returning to standby.
+    pub reset: bool,
+}
+
+/// Restart of the receiver after a burst of CRC errors, e.g. caused by a strong interferer, after
+/// which some radios stop receiving until continuous receive mode is entered again. See
+/// `LoRa::set_rx_restart_policy` and `LoRa::rx_restarts`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RxRestartPolicy {
+    /// Number of consecutive packets with a CRC error restarting the receiver. Only
+    /// `RxContinuous` is restarted, the packet with the last error can still be read.
+    pub crc_errors: u8,
+}
+
+/// How `LoRaBuilder::build` brings up the radio.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InitMode {
+    /// Pulses the reset pin and configures the radio.
+    Reset,
+    /// Configures the radio without pulsing the reset pin. Registers already holding the value
+    /// written, such as the FIFO base addresses, are not written again.
+    Rewrite,
+    /// Neither pulses the reset pin nor writes to the radio if it is in LoRa mode, taking the
+    /// frequency, mode, header mode and PA output from its registers instead, so a packet being
+    /// received is kept. The frequency given to the builder and `LoRaBuilder::pa_output` are
+    /// ignored. A radio in FSK mode, i.e. reset or power cycled, is configured like `Rewrite`.
+    Adopt,
+}
+
+/// Chip variants the driver supports, told apart by RegVersion. The register layouts differ in
+/// RegModemConfig1 to 3 and RegPaDac, and the SX1272 has a single RF port and only the 125, 250
+/// and 500 kHz bandwidths.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Chip {
+    /// SX1272 and SX1273, RegVersion `0x22`.
+    Sx1272,
+    /// SX1276 to SX1279 and the RFM95 to RFM98 modules, RegVersion `0x12`, or `0x09` for early
+    /// silicon with the `version_0x09` feature.
+    Sx1276,
+}
+
+impl Chip {
+    /// Returns the chip identified by the value of RegVersion, or `None` if it is not supported.
+    pub fn from_version(version: u8) -> Option<Self> {
+        match version {
+            0x22 => Some(Chip::Sx1272),
+            version if version == 0x12 || version == VERSION_CHECK => Some(Chip::Sx1276),
+            _ => None,
+        }
+    }
+
+    /// Returns true if `freq` in Hz is within one of the bands of the chip.
+    fn is_in_band(self, freq: u64) -> bool {
+        match self {
+            Chip::Sx1272 => matches!(freq, 860_000_000..=1_020_000_000),
+            Chip::Sx1276 => {
+                matches!(freq, 137_000_000..=175_000_000 | 410_000_000..=525_000_000 | 862_000_000..=1_020_000_000)
+            }
+        }
+    }
 }
 
+/// Frequency bands of the two RF ports of the radio, see `LoRa::band`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Band {
+    /// Low frequency port, bands 2 (410-525 MHz) and 3 (137-175 MHz), up to `LF_BAND_MAX_HZ`.
+    Lf,
+    /// High frequency port, band 1 (862-1020 MHz), above `LF_BAND_MAX_HZ`.
+    Hf,
+}
+
+impl Band {
+    /// Returns the band of a frequency in Hz.
+    pub fn from_hz(frequency_hz: u64) -> Self {
+        if frequency_hz <= LF_BAND_MAX_HZ {
+            Band::Lf
+        } else {
+            Band::Hf
+        }
+    }
+}
+
+/// Modes of RegPaDac, see `LoRa::set_pa_dac`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PaDac {
+    /// Default power of the PA_BOOST pin, up to +17 dBm.
+    Default = 0x84,
+    /// +20 dBm on the PA_BOOST pin.
+    HighPower = 0x87,
+}
+
+/// Setting in the way of the high power mode of RegPaDac, see `Error::HighPowerConflict`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PowerSetting {
+    /// The RFO pin is selected in RegPaConfig.
+    Rfo,
+    /// The over current protection is set below 140 mA.
+    Ocp,
+}
+
+/// Output pin selection for `LoRa::set_tx_power_dbm`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PaSelection {
+    /// Picks the pin from the board's wiring: PA_BOOST if the board has it wired to the antenna
+    /// (e.g. HopeRF RFM95W, Modtronix inAir9B), RFO otherwise (e.g. Modtronix inAir9).
+    Auto { pa_boost_wired: bool },
+    /// RFO pin.
+    Rfo,
+    /// PA_BOOST pin.
+    PaBoost,
+}
+
+/// Receive modes selectable in `LoRa::receive`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RxMode {
+    /// Listen for a single packet or until the symbol timeout expires, then return to standby.
+    Single,
+    /// Listen until stopped.
+    Continuous,
+}
+
+impl From<RxMode> for RadioMode {
+    fn from(rx_mode: RxMode) -> Self {
+        match rx_mode {
+            RxMode::Single => RadioMode::RxSingle,
+            RxMode::Continuous => RadioMode::RxContinuous,
+        }
+    }
+}
+
+/// Modem configurations applied with `LoRa::set_modem_preset`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ModemPreset {
+    /// 125 kHz bandwidth, coding rate 4/5, spreading factor 7 (128 chips per symbol), CRC on, explicit
+    /// header and a preamble of 8 symbols. This is the default configuration of RadioHead's RH_RF95
+    /// driver.
+    Bw125Cr45Sf128,
+    /// The defaults of the arduino-LoRa library (sandeepmistry) with the CRC on. This only differs
+    /// from `Bw125Cr45Sf128` in also setting the sync word to `0x12`, which `Bw125Cr45Sf128` leaves
+    /// as it is. See `interop`.
+    ArduinoLoRa,
+}
+
+/// Settings of one direction of a `ListenConfig`, or of an `RxWindow`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChannelConfig {
+    pub frequency_hz: u64,
+    /// See `LoRa::set_spreading_factor`.
+    pub spreading_factor: u8,
+    /// In Hz, see `LoRa::set_signal_bandwidth`.
+    pub bandwidth: i64,
+    pub crc: bool,
+    pub invert_iq: bool,
+}
+
+/// Occupancy of one channel surveyed by `LoRa::scan_channels`, RSSI values being in dBm.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ChannelReport {
+    pub frequency_hz: u64,
+    pub min_rssi: i32,
+    pub mean_rssi: i32,
+    pub max_rssi: i32,
+    /// True if channel activity detection found a LoRa preamble.
+    pub lora_activity: bool,
+}
+
+/// Reception and transmission settings of `LoRa::continuous_listen`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ListenConfig {
+    pub rx: ChannelConfig,
+    pub tx: ChannelConfig,
+}
+
+/// A receive window of `LoRa::open_rx_windows`, e.g. RX1 or RX2 of a LoRaWAN Class A device.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RxWindow {
+    /// Time from `TxDone` to the opening of the window in milliseconds.
+    pub offset_ms: u32,
+    /// How long the receiver waits for a preamble in milliseconds, programmed as the symbol timeout
+    /// of `RxSingle` and rounded down to whole symbols, 4 to 1023 (see `LoRa::set_symbol_timeout`).
+    /// A packet whose preamble was found is received to its end.
+    pub duration_ms: u32,
+    /// Frequency, spreading factor, bandwidth, CRC and IQ inversion of the window.
+    pub channel: ChannelConfig,
+}
+
+/// Receiver profiles applied with `LoRa::set_rx_profile`. The deltas below are approximate and
+/// depend on band and bandwidth, see the electrical specification of the datasheet.
+///
+/// | Profile          | LNA boost | AGC | LNA gain | RX current          | Sensitivity        |
+/// |------------------|-----------|-----|----------|---------------------|--------------------|
+/// | `MaxSensitivity` | on        | on  | by AGC   | reference           | reference          |
+/// | `LowPower`       | off       | off | G3       | about 1 mA lower    | several dB worse   |
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RxProfile {
+    /// LNA boost on and gain controlled by the AGC.
+    MaxSensitivity,
+    /// LNA boost off, AGC off and a fixed mid-range LNA gain.
+    LowPower,
+    /// Individual settings. `lna_gain` ranges from `1` (G1, maximum gain) to `6` (G6, minimum gain)
+    /// and only applies when `agc_auto` is false.
+    Custom {
+        lna_gain: u8,
+        lna_boost: bool,
+        agc_auto: bool,
+    },
+}
+
+/// AGC reference level and step thresholds (RegAgcRef and RegAgcThresh1-3). The reference level
+/// sets the floor of all thresholds: `AGC reference [dBm] = -174 + 10 * log10(2 * RxBw) + 8 +
+/// reference_level`. Each step is the offset in dB from the previous threshold at which the LNA gain
+/// is decreased by one step. Steps 2 to 5 share registers two by two and are limited to 4 bits.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AgcConfig {
+    /// 6 bits, default value is `0x19`.
+    pub reference_level: u8,
+    /// 5 bits, default value is `0x0c`.
+    pub step1: u8,
+    /// 4 bits, default value is `0x04`.
+    pub step2: u8,
+    /// 4 bits, default value is `0x0b`.
+    pub step3: u8,
+    /// 4 bits, default value is `0x0c`.
+    pub step4: u8,
+    /// 4 bits, default value is `0x0c`.
+    pub step5: u8,
+}
 
 bitflags! {
     struct Flags: u32 {
@@ -0,0 +1,138 @@
+//! Link statistics of the last received packets, for adaptive data rate or monitoring.
+//!
+//! `LinkStats` keeps the last `N` samples in a fixed size ring buffer owned by the application,
+//! without allocating. It is fed with `LoRa::record_link_stats` after each packet, or with
+//! `LinkStats::push` from any source.
+
+use crate::PacketInfo;
+
+/// Link quality of one received packet.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LinkSample {
+    /// RSSI in dBm.
+    pub rssi: i32,
+    /// SNR in dB.
+    pub snr: f64,
+    /// Frequency error in Hz.
+    pub frequency_error: i64,
+    /// Time of reception in milliseconds, if a clock was provided.
+    pub timestamp_ms: Option<u64>,
+    /// The payload CRC check failed.
+    pub crc_error: bool,
+}
+
+impl LinkSample {
+    const EMPTY: LinkSample = LinkSample {
+        rssi: 0,
+        snr: 0.0,
+        frequency_error: 0,
+        timestamp_ms: None,
+        crc_error: false,
+    };
+
+    /// Creates a sample from the information of a received packet.
+    pub fn new(info: &PacketInfo, crc_error: bool, timestamp_ms: Option<u64>) -> Self {
+        LinkSample {
+            rssi: info.rssi,
+            snr: info.snr,
+            frequency_error: info.frequency_error,
+            timestamp_ms,
+            crc_error,
+        }
+    }
+}
+
+/// Ring buffer of the last `N` link samples. Once full, each new sample replaces the oldest one.
+/// The statistics cover the samples in the buffer, packets with a CRC error included.
+pub struct LinkStats<const N: usize> {
+    samples: [LinkSample; N],
+    next: usize,
+    len: usize,
+}
+
+impl<const N: usize> LinkStats<N> {
+    pub const fn new() -> Self {
+        LinkStats {
+            samples: [LinkSample::EMPTY; N],
+            next: 0,
+            len: 0,
+        }
+    }
+
+    /// Adds a sample, dropping the oldest one if the buffer is full.
+    pub fn push(&mut self, sample: LinkSample) {
+        if N == 0 {
+            return;
+        }
+        self.samples[self.next] = sample;
+        self.next = (self.next + 1) % N;
+        self.len = (self.len + 1).min(N);
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Removes all samples.
+    pub fn clear(&mut self) {
+        self.next = 0;
+        self.len = 0;
+    }
+
+    /// Returns the samples from the oldest to the latest.
+    pub fn iter(&self) -> impl Iterator<Item = &LinkSample> {
+        let start = (self.next + N - self.len) % N.max(1);
+        (0..self.len).map(move |i| &self.samples[(start + i) % N])
+    }
+
+    /// Returns the latest sample.
+    pub fn latest(&self) -> Option<&LinkSample> {
+        self.iter().last()
+    }
+
+    pub fn min_rssi(&self) -> Option<i32> {
+        self.iter().map(|sample| sample.rssi).min()
+    }
+
+    pub fn max_rssi(&self) -> Option<i32> {
+        self.iter().map(|sample| sample.rssi).max()
+    }
+
+    pub fn mean_rssi(&self) -> Option<f64> {
+        self.mean(|sample| f64::from(sample.rssi))
+    }
+
+    pub fn min_snr(&self) -> Option<f64> {
+        self.iter().map(|sample| sample.snr).reduce(f64::min)
+    }
+
+    pub fn max_snr(&self) -> Option<f64> {
+        self.iter().map(|sample| sample.snr).reduce(f64::max)
+    }
+
+    pub fn mean_snr(&self) -> Option<f64> {
+        self.mean(|sample| sample.snr)
+    }
+
+    /// Returns the number of samples whose payload CRC check failed.
+    pub fn crc_errors(&self) -> usize {
+        self.iter().filter(|sample| sample.crc_error).count()
+    }
+
+    fn mean(&self, value: impl Fn(&LinkSample) -> f64) -> Option<f64> {
+        if self.is_empty() {
+            return None;
+        }
+        Some(self.iter().map(value).sum::<f64>() / self.len as f64)
+    }
+}
+
+impl<const N: usize> Default for LinkStats<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
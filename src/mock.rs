@@ -0,0 +1,569 @@
+//! Host-side mock of the radio for unit tests: an SPI bus backed by an in-memory register map and
+//! FIFO that records every register write, plus pins and a delay. Transmissions complete instantly
+//! unless scripted otherwise, and packets, TxDone, RxTimeout, CadDone and frequency hops can be
+//! scripted to arrive some register accesses after the driver enters a mode, see
+//! `State::after_mode`.
+
+extern crate std;
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+use std::vec::Vec;
+
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+use embedded_hal::blocking::spi::{Transfer, Write};
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+use embedded_hal::timer::CountDown;
+use void::Void;
+
+use crate::clock::Clock;
+use crate::register::Register;
+use crate::{LoRa, RadioMode};
+
+const REG_FIFO: u8 = Register::RegFifo as u8;
+const REG_FIFO_ADDR_PTR: u8 = Register::RegFifoAddrPtr as u8;
+const REG_IRQ_FLAGS: u8 = Register::RegIrqFlags as u8;
+const REG_OP_MODE: u8 = Register::RegOpMode as u8;
+
+const MODE_STDBY: u8 = RadioMode::Stdby as u8;
+const MODE_TX: u8 = RadioMode::Tx as u8;
+const MODE_RX_CONTINUOUS: u8 = RadioMode::RxContinuous as u8;
+const MODE_RX_SINGLE: u8 = RadioMode::RxSingle as u8;
+
+#[derive(Debug, PartialEq)]
+pub struct MockError;
+
+/// Something happening on air, played by a scenario.
+#[derive(Clone, Debug)]
+pub enum Event {
+    /// The transmission completes, TxDone being raised and the radio returning to standby.
+    TxDone,
+    /// A packet is received into the FIFO right after the previous one, RxDone and ValidHeader
+    /// being raised, and PayloadCrcError as well if `crc_error`. `RxSingle` returns to standby.
+    Packet { payload: Vec<u8>, crc_error: bool },
+    /// `RxSingle` times out, RxTimeout being raised and the radio returning to standby.
+    RxTimeout,
+    /// The channel activity detection completes, CadDone being raised, CadDetected as well if
+    /// `detected`, and the radio returning to standby.
+    CadDone { detected: bool },
+    /// The hop period elapses, FhssPresentChannel of RegHopChannel becoming `channel` and
+    /// FhssChangeChannel being raised.
+    Hop { channel: u8 },
+}
+
+/// An event of a scenario and when it happens.
+struct Step {
+    /// Mode whose next entry starts the count, or `None` to start it right after the previous step.
+    mode: Option<u8>,
+    accesses: usize,
+    event: Event,
+}
+
+/// Register map, FIFO and history of the mocked radio.
+pub struct State {
+    pub registers: [u8; 0x80],
+    pub fifo: [u8; 256],
+    /// Every register write, in order.
+    pub writes: Vec<(u8, u8)>,
+    /// Values returned by the next reads of a register instead of the register map.
+    pub reads: HashMap<u8, VecDeque<u8>>,
+    pub cs_low: bool,
+    /// Register and offset the burst in progress continues at, until CS goes high.
+    burst: Option<(u8, usize)>,
+    /// Length of every SPI transfer, in order.
+    pub transfer_lens: Vec<usize>,
+    /// Number of SPI transfers and writes so far, failed ones included.
+    pub spi_ops: usize,
+    /// SPI transfer or write failing with `MockError` without reaching the radio, counted like
+    /// `spi_ops`.
+    pub fail_spi_op: Option<usize>,
+    /// Register and value whose next single register write fails with `MockError` without
+    /// reaching the radio, once.
+    pub fail_next_write: Option<(Register, u8)>,
+    /// Steps of the scenario yet to start.
+    script: VecDeque<Step>,
+    /// Event of the started step and the number of register accesses left before it happens.
+    pending: Option<(usize, Event)>,
+    /// FIFO address the next received packet is written at.
+    rx_addr: u8,
+}
+
+impl State {
+    fn read(&mut self, addr: u8) -> u8 {
+        self.tick();
+        if let Some(value) = self.reads.get_mut(&addr).and_then(|q| q.pop_front()) {
+            return value;
+        }
+        if addr == REG_FIFO {
+            let ptr = self.registers[REG_FIFO_ADDR_PTR as usize];
+            self.registers[REG_FIFO_ADDR_PTR as usize] = ptr.wrapping_add(1);
+            return self.fifo[ptr as usize];
+        }
+        self.registers[addr as usize]
+    }
+
+    fn write(&mut self, addr: u8, value: u8) {
+        self.tick();
+        self.writes.push((addr, value));
+        match addr {
+            REG_FIFO => {
+                let ptr = self.registers[REG_FIFO_ADDR_PTR as usize];
+                self.registers[REG_FIFO_ADDR_PTR as usize] = ptr.wrapping_add(1);
+                self.fifo[ptr as usize] = value;
+            }
+            // IRQ flags are cleared by writing a 1
+            REG_IRQ_FLAGS => self.registers[addr as usize] &= !value,
+            REG_OP_MODE => self.write_op_mode(value),
+            _ => self.registers[addr as usize] = value,
+        }
+    }
+
+    fn mode(&self) -> u8 {
+        self.registers[REG_OP_MODE as usize] & 0x07
+    }
+
+    fn write_op_mode(&mut self, value: u8) {
+        let mode = value & 0x07;
+        let receiving = |mode| mode == MODE_RX_CONTINUOUS || mode == MODE_RX_SINGLE;
+        if receiving(mode) && !receiving(self.mode()) {
+            self.rx_addr = self.reg(Register::RegFifoRxBaseAddr);
+        }
+        self.registers[REG_OP_MODE as usize] = value;
+        if self.pending.is_none() && self.script.front().is_some_and(|step| step.mode == Some(mode)) {
+            self.start_next_step();
+        } else if mode == MODE_TX {
+            // Transmissions complete instantly unless scripted
+            self.play(Event::TxDone);
+        }
+    }
+
+    /// Scripts `event` to happen `accesses` register accesses after the driver next enters `mode`,
+    /// after the steps scripted before. While a step waits for `RadioMode::Tx`, transmissions no
+    /// longer complete instantly.
+    pub fn after_mode(&mut self, mode: RadioMode, accesses: usize, event: Event) {
+        self.script.push_back(Step {
+            mode: Some(mode as u8),
+            accesses,
+            event,
+        });
+    }
+
+    /// Scripts `event` to happen `accesses` register accesses after the previous step, or from now
+    /// if no step is left.
+    pub fn after(&mut self, accesses: usize, event: Event) {
+        self.script.push_back(Step {
+            mode: None,
+            accesses,
+            event,
+        });
+        if self.pending.is_none() && self.script.len() == 1 {
+            self.start_next_step();
+        }
+    }
+
+    fn start_next_step(&mut self) {
+        let step = self.script.pop_front().unwrap();
+        self.pending = Some((step.accesses, step.event));
+        self.tick_pending(0);
+    }
+
+    /// Counts a register access towards the started step.
+    fn tick(&mut self) {
+        self.tick_pending(1);
+    }
+
+    fn tick_pending(&mut self, accesses: usize) {
+        let Some((left, _)) = self.pending.as_mut() else {
+            return;
+        };
+        *left = left.saturating_sub(accesses);
+        if *left > 0 {
+            return;
+        }
+        let (_, event) = self.pending.take().unwrap();
+        self.play(event);
+        if self.script.front().is_some_and(|step| step.mode.is_none()) {
+            self.start_next_step();
+        }
+    }
+
+    fn play(&mut self, event: Event) {
+        let op_mode = self.registers[REG_OP_MODE as usize];
+        let irq_flags = match event {
+            Event::TxDone => {
+                self.registers[REG_OP_MODE as usize] = op_mode & 0xf8 | MODE_STDBY;
+                0x08
+            }
+            Event::Packet { payload, crc_error } => {
+                let start = self.rx_addr;
+                for &byte in &payload {
+                    self.fifo[self.rx_addr as usize] = byte;
+                    self.rx_addr = self.rx_addr.wrapping_add(1);
+                }
+                self.set_reg(Register::RegFifoRxCurrentAddr, start);
+                self.set_reg(Register::RegFifoRxByteAddr, self.rx_addr);
+                self.set_reg(Register::RegRxNbBytes, payload.len() as u8);
+                self.increment(Register::RegRxHeaderCntValueMsb, Register::RegRxHeaderCntValueLsb);
+                if crc_error {
+                    0x70
+                } else {
+                    self.increment(Register::RegRxPacketCntValueMsb, Register::RegRxPacketCntValueLsb);
+                    0x50
+                }
+            }
+            Event::RxTimeout => 0x80,
+            Event::CadDone { detected } => {
+                self.registers[REG_OP_MODE as usize] = op_mode & 0xf8 | MODE_STDBY;
+                0x04 | u8::from(detected)
+            }
+            Event::Hop { channel } => {
+                let hop_channel = self.reg(Register::RegHopChannel);
+                self.set_reg(Register::RegHopChannel, hop_channel & 0xc0 | channel & 0x3f);
+                self.registers[REG_IRQ_FLAGS as usize] |= 0x02;
+                return;
+            }
+        };
+        if self.mode() == MODE_RX_SINGLE {
+            self.registers[REG_OP_MODE as usize] = op_mode & 0xf8 | MODE_STDBY;
+        }
+        self.registers[REG_IRQ_FLAGS as usize] |= irq_flags;
+    }
+
+    fn increment(&mut self, msb: Register, lsb: Register) {
+        let count = u16::from_be_bytes([self.reg(msb), self.reg(lsb)]).wrapping_add(1);
+        let [high, low] = count.to_be_bytes();
+        self.set_reg(msb, high);
+        self.set_reg(lsb, low);
+    }
+
+    /// Returns the register of a register write access, the FIFO not being auto-incremented.
+    fn burst_addr(base: u8, offset: usize) -> u8 {
+        if base == REG_FIFO {
+            base
+        } else {
+            base.wrapping_add(offset as u8)
+        }
+    }
+
+    /// Counts an SPI transfer or write, returning `Err(MockError)` if it is the one to fail.
+    fn spi_op(&mut self) -> Result<(), MockError> {
+        self.spi_ops += 1;
+        if self.fail_spi_op == Some(self.spi_ops) {
+            return Err(MockError);
+        }
+        Ok(())
+    }
+
+    /// Returns the register and offset the words of an SPI transfer start at, taking the address
+    /// from the first word unless a burst is in progress.
+    fn burst_start(&mut self, words: &[u8]) -> (u8, usize, usize) {
+        self.transfer_lens.push(words.len());
+        match self.burst {
+            Some((base, offset)) => (base, offset, 0),
+            None => (words[0] & 0x7f, 0, 1),
+        }
+    }
+
+    /// Queues values to be returned by the next reads of `reg`.
+    pub fn queue_reads(&mut self, reg: Register, values: &[u8]) {
+        self.reads
+            .entry(reg as u8)
+            .or_default()
+            .extend(values.iter().copied());
+    }
+
+    pub fn reg(&self, reg: Register) -> u8 {
+        self.registers[reg as usize]
+    }
+
+    pub fn set_reg(&mut self, reg: Register, value: u8) {
+        self.registers[reg as usize] = value;
+    }
+
+    /// Returns the values written to `reg`, in order.
+    pub fn writes_to(&self, reg: Register) -> Vec<u8> {
+        self.writes
+            .iter()
+            .filter(|(addr, _)| *addr == reg as u8)
+            .map(|(_, value)| *value)
+            .collect()
+    }
+}
+
+pub type Shared = Rc<RefCell<State>>;
+
+pub struct MockSpi(pub Shared);
+
+impl Transfer<u8> for MockSpi {
+    type Error = MockError;
+
+    fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], MockError> {
+        let mut state = self.0.borrow_mut();
+        assert!(state.cs_low, "SPI transfer with CS high");
+        state.spi_op()?;
+        let (base, start, skip) = state.burst_start(words);
+        for (offset, word) in words.iter_mut().skip(skip).enumerate() {
+            *word = state.read(State::burst_addr(base, start + offset));
+        }
+        state.burst = Some((base, start + words.len() - skip));
+        Ok(words)
+    }
+}
+
+impl Write<u8> for MockSpi {
+    type Error = MockError;
+
+    fn write(&mut self, words: &[u8]) -> Result<(), MockError> {
+        let mut state = self.0.borrow_mut();
+        assert!(state.cs_low, "SPI write with CS high");
+        state.spi_op()?;
+        let failing = state.fail_next_write.map(|(reg, value)| [reg as u8 | 0x80, value]);
+        if state.burst.is_none() && failing.is_some_and(|failing| words == failing) {
+            state.fail_next_write = None;
+            return Err(MockError);
+        }
+        let (base, start, skip) = state.burst_start(words);
+        for (offset, word) in words.iter().skip(skip).enumerate() {
+            state.write(State::burst_addr(base, start + offset), *word);
+        }
+        state.burst = Some((base, start + words.len() - skip));
+        Ok(())
+    }
+}
+
+pub struct MockCs(pub Shared);
+
+impl OutputPin for MockCs {
+    type Error = MockError;
+
+    fn set_low(&mut self) -> Result<(), MockError> {
+        self.0.borrow_mut().cs_low = true;
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), MockError> {
+        let mut state = self.0.borrow_mut();
+        state.cs_low = false;
+        state.burst = None;
+        Ok(())
+    }
+}
+
+pub struct MockReset;
+
+impl OutputPin for MockReset {
+    type Error = MockError;
+
+    fn set_low(&mut self) -> Result<(), MockError> {
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), MockError> {
+        Ok(())
+    }
+}
+
+/// Input pin returning queued levels, then staying high.
+#[derive(Default)]
+pub struct MockInput {
+    pub levels: RefCell<VecDeque<bool>>,
+}
+
+impl InputPin for MockInput {
+    type Error = MockError;
+
+    fn is_high(&self) -> Result<bool, MockError> {
+        Ok(self.levels.borrow_mut().pop_front().unwrap_or(true))
+    }
+
+    fn is_low(&self) -> Result<bool, MockError> {
+        self.is_high().map(|high| !high)
+    }
+}
+
+/// Input pin whose edges are always already there, counting the waits.
+#[cfg(feature = "async")]
+#[derive(Default)]
+pub struct MockWait {
+    pub waits: u32,
+}
+
+#[cfg(feature = "async")]
+impl embedded_hal_1::digital::ErrorType for MockWait {
+    type Error = core::convert::Infallible;
+}
+
+#[cfg(feature = "async")]
+impl embedded_hal_async::digital::Wait for MockWait {
+    async fn wait_for_high(&mut self) -> Result<(), Self::Error> {
+        self.waits += 1;
+        Ok(())
+    }
+
+    async fn wait_for_low(&mut self) -> Result<(), Self::Error> {
+        self.waits += 1;
+        Ok(())
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> Result<(), Self::Error> {
+        self.waits += 1;
+        Ok(())
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> Result<(), Self::Error> {
+        self.waits += 1;
+        Ok(())
+    }
+
+    async fn wait_for_any_edge(&mut self) -> Result<(), Self::Error> {
+        self.waits += 1;
+        Ok(())
+    }
+}
+
+/// Runs a future to completion on the current thread.
+#[cfg(feature = "async")]
+pub fn block_on<F: core::future::Future>(future: F) -> F::Output {
+    let mut future = core::pin::pin!(future);
+    let mut context = core::task::Context::from_waker(core::task::Waker::noop());
+    loop {
+        if let core::task::Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+    }
+}
+
+/// Delay that returns immediately, counting the requested time.
+#[derive(Default)]
+pub struct MockDelay {
+    pub elapsed_ms: u32,
+    pub elapsed_us: u32,
+}
+
+impl DelayMs<u8> for MockDelay {
+    fn delay_ms(&mut self, ms: u8) {
+        self.elapsed_ms += u32::from(ms);
+    }
+}
+
+impl DelayUs<u16> for MockDelay {
+    fn delay_us(&mut self, us: u16) {
+        self.elapsed_us += u32::from(us);
+    }
+}
+
+/// Clock advancing by `step_ms` every time it is read, and by the requested time when waiting.
+#[derive(Default)]
+pub struct FakeClock {
+    pub now_ms: u64,
+    pub step_ms: u64,
+    pub waits: u32,
+}
+
+impl Clock for FakeClock {
+    fn now_ms(&mut self) -> u64 {
+        self.now_ms += self.step_ms;
+        self.now_ms
+    }
+
+    fn wait_ms(&mut self, ms: u8) {
+        self.now_ms += u64::from(ms);
+        self.waits += 1;
+    }
+}
+
+/// Count down expiring after `wait` was called `count` times, `count` being the time it was
+/// started with.
+#[derive(Default)]
+pub struct MockTimer {
+    pub remaining: u32,
+    pub started: u32,
+}
+
+impl CountDown for MockTimer {
+    type Time = u32;
+
+    fn start<T: Into<u32>>(&mut self, count: T) {
+        self.remaining = count.into();
+        self.started += 1;
+    }
+
+    fn wait(&mut self) -> nb::Result<(), Void> {
+        if self.remaining == 0 {
+            return Ok(());
+        }
+        self.remaining -= 1;
+        Err(nb::Error::WouldBlock)
+    }
+}
+
+/// LoRa mode reset values of the registers that are not zero (Table 41).
+const RESET_VALUES: &[(Register, u8)] = &[
+    (Register::RegOpMode, 0x01),
+    (Register::RegFrfMsb, 0x6c),
+    (Register::RegFrfMid, 0x80),
+    (Register::RegPaConfig, 0x4f),
+    (Register::RegPaRamp, 0x09),
+    (Register::RegOcp, 0x2b),
+    (Register::RegLna, 0x20),
+    (Register::RegFifoTxBaseAddr, 0x80),
+    (Register::RegModemStat, 0x10),
+    (Register::RegModemConfig1, 0x72),
+    (Register::RegModemConfig2, 0x70),
+    (Register::RegSymbTimeoutLsb, 0x64),
+    (Register::RegPreambleLsb, 0x08),
+    (Register::RegPayloadLength, 0x01),
+    (Register::RegMaxPayloadLength, 0xff),
+    (Register::RegDetectionOptimize, 0xc3),
+    (Register::RegInvertiq, 0x27),
+    (Register::RegDetectionThreshold, 0x0a),
+    (Register::RegSyncWord, 0x12),
+    (Register::RegInvertiq2, 0x1d),
+    (Register::RegPaDac, 0x84),
+    (Register::RegPll, 0xd0),
+];
+
+pub type MockLoRa = LoRa<MockSpi, MockCs, MockReset>;
+
+/// Returns the state of a mocked radio right after reset.
+pub fn reset_state() -> Shared {
+    let state = Rc::new(RefCell::new(State {
+        registers: [0; 0x80],
+        fifo: [0; 256],
+        writes: Vec::new(),
+        reads: HashMap::new(),
+        cs_low: false,
+        burst: None,
+        transfer_lens: Vec::new(),
+        spi_ops: 0,
+        fail_spi_op: None,
+        fail_next_write: None,
+        script: VecDeque::new(),
+        pending: None,
+        rx_addr: 0,
+    }));
+    {
+        let mut state = state.borrow_mut();
+        for &(reg, value) in RESET_VALUES {
+            state.set_reg(reg, value);
+        }
+        state.set_reg(Register::RegVersion, crate::VERSION_CHECK);
+    }
+    state
+}
+
+/// Returns a radio initialized at `frequency` MHz and the state of its mock.
+pub fn radio(frequency: u32) -> (MockLoRa, Shared) {
+    let state = reset_state();
+    let lora = LoRa::new(
+        MockSpi(state.clone()),
+        MockCs(state.clone()),
+        MockReset,
+        frequency,
+        &mut MockDelay::default(),
+    )
+    .unwrap();
+    state.borrow_mut().writes.clear();
+    (lora, state)
+}
@@ -0,0 +1,88 @@
+//! Framing compatible with the RH_RF95 driver of the RadioHead Arduino library.
+//!
+//! RadioHead prepends a 4 byte header to every payload: the address of the recipient, the address of
+//! the sender, a message id and flags, in that order. Nodes running RH_RF95 with its default modem
+//! configuration also need `ModemPreset::Bw125Cr45Sf128`. See `LoRa::send_to` and
+//! `LoRa::recv_from`.
+
+/// Length of the RadioHead header.
+pub const RH_HEADER_LEN: usize = 4;
+
+/// Recipient address received by every node.
+pub const RH_BROADCAST_ADDRESS: u8 = 0xff;
+
+/// Longest payload that fits in a packet after the header.
+pub const RH_MAX_MESSAGE_LEN: usize = 255 - RH_HEADER_LEN;
+
+/// The header of a RadioHead packet.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RhHeader {
+    pub to: u8,
+    pub from: u8,
+    pub id: u8,
+    pub flags: u8,
+}
+
+impl RhHeader {
+    /// Returns the header as sent over the air.
+    pub fn to_bytes(self) -> [u8; RH_HEADER_LEN] {
+        [self.to, self.from, self.id, self.flags]
+    }
+
+    /// Splits a packet into its header and payload, or returns `None` if it is shorter than a
+    /// header.
+    pub fn parse(packet: &[u8]) -> Option<(RhHeader, &[u8])> {
+        if packet.len() < RH_HEADER_LEN {
+            return None;
+        }
+        let header = RhHeader {
+            to: packet[0],
+            from: packet[1],
+            id: packet[2],
+            flags: packet[3],
+        };
+        Some((header, &packet[RH_HEADER_LEN..]))
+    }
+}
+
+/// Addressing state of a RadioHead node, enabled with `LoRa::set_radiohead`. Like RH_RF95, the id
+/// and flags are sent unchanged until modified, e.g. by a reliable datagram layer on top.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RadioHead {
+    /// Address of this node, sent as the sender of every packet.
+    pub address: u8,
+    /// Message id sent with every packet. Default value is `0`.
+    pub id: u8,
+    /// Flags sent with every packet. Default value is `0`.
+    pub flags: u8,
+    /// Accept packets addressed to any node rather than only to `address` and
+    /// `RH_BROADCAST_ADDRESS`. Default value is `false`.
+    pub promiscuous: bool,
+}
+
+impl RadioHead {
+    /// Creates the state of the node at `address`.
+    pub fn new(address: u8) -> Self {
+        RadioHead {
+            address,
+            id: 0,
+            flags: 0,
+            promiscuous: false,
+        }
+    }
+
+    /// Returns the header of a packet from this node to `to`.
+    pub fn header_to(&self, to: u8) -> RhHeader {
+        RhHeader {
+            to,
+            from: self.address,
+            id: self.id,
+            flags: self.flags,
+        }
+    }
+
+    /// Returns whether a packet with `header` is for this node.
+    pub fn accepts(&self, header: &RhHeader) -> bool {
+        self.promiscuous || header.to == self.address || header.to == RH_BROADCAST_ADDRESS
+    }
+}
@@ -1,6 +1,6 @@
 #![allow(dead_code)]
 
-use crate::RadioMode;
+use crate::Chip;
 
 #[derive(Clone, Copy)]
 pub enum Register {
@@ -19,27 +19,63 @@ pub enum Register {
     RegFifoRxCurrentAddr = 0x10,
     RegIrqFlags = 0x12,
     RegRxNbBytes = 0x13,
+    RegRxHeaderCntValueMsb = 0x14,
+    RegRxHeaderCntValueLsb = 0x15,
+    RegRxPacketCntValueMsb = 0x16,
+    RegRxPacketCntValueLsb = 0x17,
+    RegModemStat = 0x18,
     RegPktSnrValue = 0x19,
     RegPktRssiValue = 0x1a,
+    RegRssiValue = 0x1b,
+    RegHopChannel = 0x1c,
     RegModemConfig1 = 0x1d,
     RegModemConfig2 = 0x1e,
+    RegSymbTimeoutLsb = 0x1f,
     RegPreambleMsb = 0x20,
     RegPreambleLsb = 0x21,
     RegPayloadLength = 0x22,
+    RegMaxPayloadLength = 0x23,
+    RegHopPeriod = 0x24,
+    RegFifoRxByteAddr = 0x25,
     RegModemConfig3 = 0x26,
     RegFreqErrorMsb = 0x28,
     RegFreqErrorMid = 0x29,
     RegFreqErrorLsb = 0x2a,
     RegRssiWideband = 0x2c,
+    RegIfFreq1 = 0x2f,
+    RegIfFreq2 = 0x30,
     RegDetectionOptimize = 0x31,
     RegInvertiq = 0x33,
     RegDetectionThreshold = 0x37,
     RegSyncWord = 0x39,
     RegInvertiq2 = 0x3b,
     RegDioMapping1 = 0x40,
+    RegDioMapping2 = 0x41,
     RegVersion = 0x42,
+    RegPllHop = 0x44,
     RegPaDac = 0x4d,
+    /// RegPaDac of the SX1272.
+    RegPaDacSx1272 = 0x5a,
+    /// RegPll of the SX1272.
+    RegPllSx1272 = 0x5c,
+    RegAgcRef = 0x61,
+    RegAgcThresh1 = 0x62,
+    RegAgcThresh2 = 0x63,
+    RegAgcThresh3 = 0x64,
+    RegPll = 0x70,
 }
+/// Registers of the FSK/OOK page that differ from the LoRa page, only accessible while the radio
+/// is in FSK/OOK mode (LongRangeMode cleared).
+#[derive(Clone, Copy)]
+pub enum FskRegister {
+    RegRxConfig = 0x0d,
+    RegAfcFei = 0x1a,
+    RegAfcMsb = 0x1b,
+    RegAfcLsb = 0x1c,
+    RegSyncConfig = 0x27,
+    RegPacketConfig2 = 0x31,
+}
+
 #[derive(Clone, Copy, PartialOrd, PartialEq)]
 pub enum PaConfig {
     PaBoost = 0x80,
@@ -51,6 +87,11 @@ pub enum IrqMask {
     TxDone = 0x08,
     RxDone = 0x40,
     PayloadCrcError = 0x20,
+    FhssChangeChannel = 0x02,
+    ValidHeader = 0x10,
+    RxTimeout = 0x80,
+    CadDone = 0x04,
+    CadDetected = 0x01,
 }
 
 impl PaConfig {
@@ -65,6 +106,209 @@ impl IrqMask {
     }
 }
 
+/// Decoded fields of RegModemConfig1, whose layout depends on the chip. The bandwidth is in the
+/// SX1276 encoding on both chips.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ModemConfig1 {
+    /// Bits 7-4, or bits 7-6 on the SX1272 where only 125, 250 and 500 kHz (7 to 9) exist.
+    pub bandwidth: u8,
+    /// Bits 3-1, or bits 5-3 on the SX1272.
+    pub coding_rate: u8,
+    /// Bit 0, or bit 2 on the SX1272.
+    pub implicit_header: bool,
+    /// Bit 1 on the SX1272, always false on the SX1276 where the flag is in RegModemConfig2.
+    pub rx_payload_crc_on: bool,
+    /// Bit 0 on the SX1272, always false on the SX1276 where the flag is in RegModemConfig3.
+    pub low_data_rate_optimize: bool,
+}
+
+impl ModemConfig1 {
+    pub fn from_bits(bits: u8, chip: Chip) -> Self {
+        match chip {
+            Chip::Sx1276 => ModemConfig1 {
+                bandwidth: bits >> 4,
+                coding_rate: (bits >> 1) & 0x07,
+                implicit_header: bits & 0x01 != 0,
+                rx_payload_crc_on: false,
+                low_data_rate_optimize: false,
+            },
+            Chip::Sx1272 => ModemConfig1 {
+                bandwidth: (bits >> 6) + 7,
+                coding_rate: (bits >> 3) & 0x07,
+                implicit_header: bits & 0x04 != 0,
+                rx_payload_crc_on: bits & 0x02 != 0,
+                low_data_rate_optimize: bits & 0x01 != 0,
+            },
+        }
+    }
+
+    /// Returns the register value, fields too wide for their bits being truncated.
+    pub fn to_bits(self, chip: Chip) -> u8 {
+        match chip {
+            Chip::Sx1276 => (self.bandwidth & 0x0f) << 4 | (self.coding_rate & 0x07) << 1 | u8::from(self.implicit_header),
+            Chip::Sx1272 => {
+                (self.bandwidth.wrapping_sub(7) & 0x03) << 6
+                    | (self.coding_rate & 0x07) << 3
+                    | u8::from(self.implicit_header) << 2
+                    | u8::from(self.rx_payload_crc_on) << 1
+                    | u8::from(self.low_data_rate_optimize)
+            }
+        }
+    }
+}
+
+/// Decoded fields of RegModemConfig2, whose bit 2 depends on the chip.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ModemConfig2 {
+    /// Bits 7-4.
+    pub spreading_factor: u8,
+    /// Bit 3.
+    pub tx_continuous_mode: bool,
+    /// Bit 2 on the SX1276, always false on the SX1272 where the flag is in RegModemConfig1.
+    pub rx_payload_crc_on: bool,
+    /// Bit 2 on the SX1272, always false on the SX1276 where the flag is in RegModemConfig3.
+    pub agc_auto_on: bool,
+    /// Bits 1-0, the two most significant bits of the symbol timeout.
+    pub symb_timeout_msb: u8,
+}
+
+impl ModemConfig2 {
+    pub fn from_bits(bits: u8, chip: Chip) -> Self {
+        let bit_2 = bits & 0x04 != 0;
+        ModemConfig2 {
+            spreading_factor: bits >> 4,
+            tx_continuous_mode: bits & 0x08 != 0,
+            rx_payload_crc_on: bit_2 && chip == Chip::Sx1276,
+            agc_auto_on: bit_2 && chip == Chip::Sx1272,
+            symb_timeout_msb: bits & 0x03,
+        }
+    }
+
+    /// Returns the register value, fields too wide for their bits being truncated.
+    pub fn to_bits(self, chip: Chip) -> u8 {
+        let bit_2 = match chip {
+            Chip::Sx1276 => self.rx_payload_crc_on,
+            Chip::Sx1272 => self.agc_auto_on,
+        };
+        (self.spreading_factor & 0x0f) << 4
+            | u8::from(self.tx_continuous_mode) << 3
+            | u8::from(bit_2) << 2
+            | self.symb_timeout_msb & 0x03
+    }
+}
+
+/// Decoded fields of RegModemConfig3, which only exists on the SX1276.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ModemConfig3 {
+    /// Bit 3.
+    pub low_data_rate_optimize: bool,
+    /// Bit 2.
+    pub agc_auto_on: bool,
+    /// Bits 7-4 and 1-0, unused, kept as read.
+    pub reserved: u8,
+}
+
+impl ModemConfig3 {
+    pub fn from_bits(bits: u8) -> Self {
+        ModemConfig3 {
+            low_data_rate_optimize: bits & 0x08 != 0,
+            agc_auto_on: bits & 0x04 != 0,
+            reserved: bits & 0xf3,
+        }
+    }
+
+    /// Returns the register value.
+    pub fn to_bits(self) -> u8 {
+        u8::from(self.low_data_rate_optimize) << 3 | u8::from(self.agc_auto_on) << 2 | self.reserved & 0xf3
+    }
+}
+
+/// PLL bandwidth, RegPll bits 7-6.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PllBandwidth {
+    Khz75 = 0b00,
+    Khz150 = 0b01,
+    Khz225 = 0b10,
+    Khz300 = 0b11,
+}
+
+impl PllBandwidth {
+    pub fn from_bits(bits: u8) -> Self {
+        match bits & 0b11 {
+            0b00 => PllBandwidth::Khz75,
+            0b01 => PllBandwidth::Khz150,
+            0b10 => PllBandwidth::Khz225,
+            _ => PllBandwidth::Khz300,
+        }
+    }
+}
+
+/// LoRa detection optimization, RegDetectionOptimize bits 2-0. Each value goes with a detection
+/// threshold, see `LoRa::set_detection_threshold`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DetectionOptimize {
+    /// For SF7 to SF12, with a threshold of `0x0a`.
+    Sf7To12 = 0x03,
+    /// For SF6, with a threshold of `0x0c`.
+    Sf6 = 0x05,
+}
+
+impl DetectionOptimize {
+    /// Returns the value in `bits`, or `None` for a reserved one.
+    pub fn from_bits(bits: u8) -> Option<Self> {
+        match bits & 0x07 {
+            0x03 => Some(DetectionOptimize::Sf7To12),
+            0x05 => Some(DetectionOptimize::Sf6),
+            _ => None,
+        }
+    }
+}
+
+/// Signal mapped on DIO3 in LoRa mode, RegDioMapping1 bits 1-0.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dio3Mapping {
+    CadDone = 0b00,
+    ValidHeader = 0b01,
+    PayloadCrcError = 0b10,
+}
+
+/// Signal mapped on DIO4 in LoRa mode, RegDioMapping2 bits 7-6.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dio4Mapping {
+    CadDetected = 0b00,
+    PllLock = 0b01,
+}
+
+/// Automatic restart of the FSK receiver after a packet, RegSyncConfig bits 7-6.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FskAutoRestart {
+    /// The receiver stops after a packet until restarted.
+    Off = 0b00,
+    /// The receiver restarts right away.
+    On = 0b01,
+    /// The receiver restarts once the PLL locked again, needed if the frequency was changed.
+    OnWaitPllLock = 0b10,
+}
+
+/// FSK data mode, RegPacketConfig2 bit 6.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FskDataMode {
+    /// Bits are streamed on DIO2, with the clock on DIO1, bypassing the FIFO and packet handler.
+    Continuous = 0,
+    /// Packets go through the FIFO and the packet handler.
+    Packet = 1,
+}
+
+/// Restart behavior of the FSK receiver, see `LoRa::set_fsk_rx_config`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FskRxRestart {
+    /// Restart after a packet was received (RegSyncConfig AutoRestartRxMode).
+    pub after_packet: FskAutoRestart,
+    /// Restart when the RSSI jumps by RegRssiCollision, i.e. a stronger signal started
+    /// (RegRxConfig RestartRxOnCollision).
+    pub on_collision: bool,
+}
+
 #[derive(Clone, Copy)]
 pub enum FskDataModulationShaping {
     None = 1,
@@ -0,0 +1,105 @@
+//! Reassembly of messages sent as a numbered sequence of packets.
+//!
+//! Each packet starts with a 2 byte header, the index of the fragment followed by the total number
+//! of fragments, and carries `fragment_len` bytes of the message, except the last fragment which
+//! may be shorter. Fragments can arrive in any order and duplicates are ignored.
+
+/// Errors returned by `StreamReceiver::feed`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StreamError {
+    /// The packet is shorter than the 2 byte header.
+    TooShort,
+    /// The header declares zero fragments or an index past the total.
+    InvalidHeader,
+    /// The total number of fragments differs from the one of previous packets.
+    TotalMismatch,
+    /// The fragment is longer than `fragment_len`, or a fragment other than the last is shorter.
+    InvalidLength,
+    /// The message doesn't fit in the buffer.
+    BufferTooSmall,
+}
+
+/// Assembles a message from numbered fragments into a caller-provided buffer.
+pub struct StreamReceiver<'a> {
+    buffer: &'a mut [u8],
+    fragment_len: usize,
+    total: Option<u8>,
+    received: [u8; 32],
+    len: usize,
+}
+
+impl<'a> StreamReceiver<'a> {
+    /// Creates a receiver for fragments carrying `fragment_len` bytes of the message each.
+    pub fn new(buffer: &'a mut [u8], fragment_len: usize) -> Self {
+        StreamReceiver {
+            buffer,
+            fragment_len,
+            total: None,
+            received: [0; 32],
+            len: 0,
+        }
+    }
+
+    /// Adds a received packet, header included, to the message.
+    pub fn feed(&mut self, packet: &[u8]) -> Result<(), StreamError> {
+        if packet.len() < 2 {
+            return Err(StreamError::TooShort);
+        }
+        let (index, total, data) = (packet[0], packet[1], &packet[2..]);
+        if total == 0 || index >= total {
+            return Err(StreamError::InvalidHeader);
+        }
+        if self.total.is_some_and(|t| t != total) {
+            return Err(StreamError::TotalMismatch);
+        }
+        let is_last = index == total - 1;
+        if data.len() > self.fragment_len || (!is_last && data.len() != self.fragment_len) {
+            return Err(StreamError::InvalidLength);
+        }
+        let offset = usize::from(index) * self.fragment_len;
+        if offset + data.len() > self.buffer.len() {
+            return Err(StreamError::BufferTooSmall);
+        }
+        self.total = Some(total);
+        if self.has_fragment(index) {
+            return Ok(());
+        }
+        self.buffer[offset..offset + data.len()].copy_from_slice(data);
+        self.received[usize::from(index / 8)] |= 1 << (index % 8);
+        if is_last {
+            self.len = offset + data.len();
+        }
+        Ok(())
+    }
+
+    /// Returns true once every fragment has been received.
+    pub fn is_complete(&self) -> bool {
+        self.total.is_some() && self.missing().next().is_none()
+    }
+
+    /// Returns the indices of the fragments not received yet. Nothing is known to be missing until
+    /// a first fragment announced the total.
+    pub fn missing(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..self.total.unwrap_or(0)).filter(move |&index| !self.has_fragment(index))
+    }
+
+    /// Returns the assembled message once complete.
+    pub fn data(&self) -> Option<&[u8]> {
+        if self.is_complete() {
+            Some(&self.buffer[..self.len])
+        } else {
+            None
+        }
+    }
+
+    /// Forgets all received fragments to receive a new message in the same buffer.
+    pub fn reset(&mut self) {
+        self.total = None;
+        self.received = [0; 32];
+        self.len = 0;
+    }
+
+    fn has_fragment(&self, index: u8) -> bool {
+        self.received[usize::from(index / 8)] & (1 << (index % 8)) != 0
+    }
+}
@@ -0,0 +1,3091 @@
+extern crate std;
+
+use core::ops::ControlFlow;
+use std::vec::Vec;
+
+use quickcheck::{Arbitrary, Gen, QuickCheck};
+
+use crate::mock::{radio, reset_state, Event, FakeClock, MockCs, MockDelay, MockError, MockInput, MockLoRa, MockReset, MockSpi, MockTimer, Shared};
+use crate::dedup::{leading_u16, DedupFilter};
+use crate::link_stats::{LinkSample, LinkStats};
+use crate::radiohead::{RadioHead, RhHeader};
+use crate::stream::{StreamError, StreamReceiver};
+use crate::register::{DetectionOptimize, Dio3Mapping, Dio4Mapping, FskAutoRestart, FskDataMode, FskRegister, FskRxRestart, ModemConfig1, ModemConfig2, ModemConfig3, PllBandwidth, Register};
+use crate::frf::{frequency_error_hz, frf_from_hz, hz_from_frf, FRF_MAX};
+use crate::{AgcConfig, Band, ChannelConfig, ChannelReport, Chip, Error, InitMode, ListenConfig, LoRa, LoRaBuilder, ModemPreset, PaDac, PaOutput, PaSelection, PowerSetting, RadioMode, ReceivedFrameInfo, RxHeaderInfo, RxMode, RxProfile, RxRestartPolicy, RxWindow, TxWatchdog, FXOSC_HZ, FAST_RESET_DELAY_MS, RESET_DELAY_MS, RESET_LOW_US, VERSION_RETRIES};
+
+/// A packet received without CRC error.
+fn packet(payload: &[u8]) -> Event {
+    Event::Packet { payload: payload.to_vec(), crc_error: false }
+}
+
+#[test]
+fn frequency_sets_frf() {
+    let (mut lora, state) = radio(915);
+    for (mhz, frf) in [(915, 0xE4_C000u32), (868, 0xD9_0000), (433, 0x6C_4000)] {
+        lora.set_frequency(mhz).unwrap();
+        let state = state.borrow();
+        let written = u32::from(state.reg(Register::RegFrfMsb)) << 16
+            | u32::from(state.reg(Register::RegFrfMid)) << 8
+            | u32::from(state.reg(Register::RegFrfLsb));
+        assert_eq!(written, frf, "{} MHz", mhz);
+    }
+}
+
+#[test]
+fn spreading_factor_round_trip() {
+    let (mut lora, state) = radio(915);
+    state.borrow_mut().set_reg(Register::RegModemConfig2, 0x77);
+    for sf in 6..=12 {
+        lora.set_spreading_factor(sf).unwrap();
+        assert_eq!(lora.get_spreading_factor().unwrap(), sf);
+        // The CRC and symbol timeout bits are left alone
+        assert_eq!(state.borrow().reg(Register::RegModemConfig2) & 0x0f, 0x07);
+    }
+    lora.set_spreading_factor(13).unwrap();
+    assert_eq!(lora.get_spreading_factor().unwrap(), 12);
+}
+
+#[test]
+fn spreading_factor_6_detection_settings() {
+    let (mut lora, state) = radio(915);
+    lora.set_spreading_factor(6).unwrap();
+    lora.set_spreading_factor(7).unwrap();
+    let state = state.borrow();
+    assert_eq!(state.writes_to(Register::RegDetectionOptimize), [0xc5, 0xc3]);
+    assert_eq!(state.writes_to(Register::RegDetectionThreshold), [0x0c, 0x0a]);
+}
+
+#[test]
+fn detection_settings_keep_automatic_if() {
+    let (mut lora, state) = radio(915);
+    for (bw, automatic_if) in [(500_000, true), (125_000, false)] {
+        lora.set_signal_bandwidth(bw).unwrap();
+        lora.set_spreading_factor(6).unwrap();
+        assert_eq!(lora.get_detection_optimize().unwrap(), Some(DetectionOptimize::Sf6));
+        assert_eq!(lora.get_detection_threshold().unwrap(), 0x0c);
+        assert_eq!(lora.get_automatic_if().unwrap(), automatic_if);
+        lora.set_spreading_factor(9).unwrap();
+        assert_eq!(lora.get_detection_optimize().unwrap(), Some(DetectionOptimize::Sf7To12));
+        assert_eq!(lora.get_detection_threshold().unwrap(), 0x0a);
+        assert_eq!(lora.get_automatic_if().unwrap(), automatic_if);
+    }
+    // Only bits 2-0 are written, the reserved bits 6-3 are left alone
+    state.borrow_mut().set_reg(Register::RegDetectionOptimize, 0x4b);
+    lora.set_detection_optimize(DetectionOptimize::Sf6).unwrap();
+    assert_eq!(state.borrow().reg(Register::RegDetectionOptimize), 0x4d);
+    state.borrow_mut().set_reg(Register::RegDetectionOptimize, 0x41);
+    assert_eq!(lora.get_detection_optimize().unwrap(), None);
+    lora.set_detection_threshold(0x0b).unwrap();
+    assert_eq!(state.borrow().reg(Register::RegDetectionThreshold), 0x0b);
+}
+
+#[test]
+fn signal_bandwidth_round_trip() {
+    let (mut lora, _state) = radio(915);
+    for bw in [7_800, 10_400, 15_600, 20_800, 31_250, 41_700, 62_500, 125_000, 250_000] {
+        lora.set_signal_bandwidth(bw).unwrap();
+        assert_eq!(lora.get_signal_bandwidth().unwrap(), bw);
+    }
+}
+
+#[test]
+fn signal_bandwidth_applies_if_errata() {
+    let (mut lora, state) = radio(915);
+    for (bw, if_freq) in [(7_800, 0x48), (20_800, 0x44), (41_700, 0x44), (62_500, 0x40), (250_000, 0x40)] {
+        lora.set_signal_bandwidth(bw).unwrap();
+        assert!(!lora.get_automatic_if().unwrap());
+        assert_eq!(state.borrow().reg(Register::RegIfFreq1), if_freq);
+        assert_eq!(state.borrow().reg(Register::RegIfFreq2), 0x00);
+    }
+    lora.set_signal_bandwidth(500_000).unwrap();
+    assert!(lora.get_automatic_if().unwrap());
+    // The spreading factor leaves AutomaticIFOn alone
+    lora.set_spreading_factor(6).unwrap();
+    assert_eq!(state.borrow().reg(Register::RegDetectionOptimize), 0xc5);
+    lora.set_automatic_if(false).unwrap();
+    lora.set_spreading_factor(7).unwrap();
+    assert_eq!(state.borrow().reg(Register::RegDetectionOptimize), 0x43);
+}
+
+#[test]
+fn modem_config_bitfields_round_trip() {
+    for bits in 0..=255u8 {
+        for chip in [Chip::Sx1276, Chip::Sx1272] {
+            assert_eq!(ModemConfig1::from_bits(bits, chip).to_bits(chip), bits, "{:?} {:#04x}", chip, bits);
+            assert_eq!(ModemConfig2::from_bits(bits, chip).to_bits(chip), bits, "{:?} {:#04x}", chip, bits);
+        }
+        assert_eq!(ModemConfig3::from_bits(bits).to_bits(), bits);
+    }
+    let sx1272 = ModemConfig1::from_bits(0x62, Chip::Sx1272);
+    assert_eq!((sx1272.bandwidth, sx1272.coding_rate, sx1272.implicit_header), (8, 4, false));
+    assert!(sx1272.rx_payload_crc_on && !sx1272.low_data_rate_optimize);
+    let sx1276 = ModemConfig1::from_bits(0x73, Chip::Sx1276);
+    assert_eq!((sx1276.bandwidth, sx1276.coding_rate, sx1276.implicit_header), (7, 1, true));
+    // Bit 2 of RegModemConfig2 is the CRC flag on the SX1276 and AgcAutoOn on the SX1272
+    let sx1276 = ModemConfig2::from_bits(0x74, Chip::Sx1276);
+    assert!(sx1276.rx_payload_crc_on && !sx1276.agc_auto_on);
+    let sx1272 = ModemConfig2::from_bits(0x74, Chip::Sx1272);
+    assert!(!sx1272.rx_payload_crc_on && sx1272.agc_auto_on);
+    // The field of the other chip is ignored
+    assert_eq!(ModemConfig2 { agc_auto_on: true, ..sx1276 }.to_bits(Chip::Sx1276), 0x74);
+    assert_eq!(ModemConfig2 { rx_payload_crc_on: true, agc_auto_on: false, ..sx1272 }.to_bits(Chip::Sx1272), 0x70);
+}
+
+#[test]
+fn modem_config_1_fields_are_independent() {
+    let (mut lora, state) = radio(915);
+    lora.set_signal_bandwidth(250_000).unwrap();
+    lora.set_coding_rate_4(8).unwrap();
+    lora.explicit_header = false;
+    lora.set_mode(RadioMode::Stdby).unwrap();
+    assert_eq!(state.borrow().reg(Register::RegModemConfig1), 0x89);
+
+    lora.explicit_header = true;
+    lora.set_mode(RadioMode::Stdby).unwrap();
+    assert_eq!(state.borrow().reg(Register::RegModemConfig1), 0x88);
+    assert_eq!(lora.get_coding_rate_4().unwrap(), 8);
+    assert_eq!(lora.get_signal_bandwidth().unwrap(), 250_000);
+}
+
+#[test]
+fn ocp_trim() {
+    let (mut lora, state) = radio(915);
+    for (ma, reg) in [(30, 0x20), (45, 0x20), (100, 0x2b), (120, 0x2f), (140, 0x31), (240, 0x3b)] {
+        lora.set_ocp(ma).unwrap();
+        assert_eq!(state.borrow().reg(Register::RegOcp), reg, "{} mA", ma);
+    }
+}
+
+#[test]
+fn packet_snr_is_signed_quarter_db() {
+    let (mut lora, state) = radio(915);
+    for (reg, snr) in [(0xb0u8, -20.0), (0x00, 0.0), (0x28, 10.0), (0xff, -0.25)] {
+        state.borrow_mut().set_reg(Register::RegPktSnrValue, reg);
+        assert_eq!(lora.get_packet_snr().unwrap(), snr);
+    }
+    state.borrow_mut().queue_reads(Register::RegPktSnrValue, &[0xec, 0x14]);
+    assert_eq!(lora.get_packet_snr_quarter_db().unwrap(), -20);
+    assert_eq!(lora.get_packet_snr_quarter_db().unwrap(), 20);
+}
+
+#[test]
+fn packet_frequency_error_is_signed() {
+    let (mut lora, state) = radio(915);
+    lora.set_signal_bandwidth(125_000).unwrap();
+    // -1000 as a 20 bit two's complement value
+    {
+        let mut state = state.borrow_mut();
+        state.set_reg(Register::RegFreqErrorMsb, 0x0f);
+        state.set_reg(Register::RegFreqErrorMid, 0xfc);
+        state.set_reg(Register::RegFreqErrorLsb, 0x18);
+    }
+    assert_eq!(lora.get_packet_frequency_error().unwrap(), -131);
+
+    {
+        let mut state = state.borrow_mut();
+        state.set_reg(Register::RegFreqErrorMsb, 0x00);
+        state.set_reg(Register::RegFreqErrorMid, 0x03);
+        state.set_reg(Register::RegFreqErrorLsb, 0xe8);
+    }
+    assert_eq!(lora.get_packet_frequency_error().unwrap(), 131);
+}
+
+#[test]
+fn preamble_length_round_trip() {
+    let (mut lora, _state) = radio(915);
+    for length in [6, 8, 0x1234, 0xffff] {
+        lora.set_preamble_length(length).unwrap();
+        assert_eq!(i64::from(lora.get_preamble_length().unwrap()), length);
+    }
+}
+
+#[test]
+fn symbol_timeout_round_trip() {
+    let (mut lora, state) = radio(915);
+    lora.set_spreading_factor(9).unwrap();
+    for symbols in [4, 255, 256, 1023] {
+        lora.set_symbol_timeout(symbols).unwrap();
+        assert_eq!(lora.get_symbol_timeout().unwrap(), symbols);
+    }
+    assert_eq!(lora.get_spreading_factor().unwrap(), 9);
+    assert!(matches!(lora.set_symbol_timeout(3), Err(Error::InvalidInput)));
+    assert!(matches!(lora.set_symbol_timeout(1024), Err(Error::InvalidInput)));
+    assert_eq!(state.borrow().reg(Register::RegSymbTimeoutLsb), 0xff);
+}
+
+#[test]
+fn time_on_air_and_dwell_time() {
+    let (mut lora, _state) = radio(915);
+    lora.set_spreading_factor(7).unwrap();
+    lora.set_signal_bandwidth(125_000).unwrap();
+    lora.set_coding_rate_4(5).unwrap();
+    lora.set_preamble_length(8).unwrap();
+    lora.set_crc(true).unwrap();
+    // 40.25 symbols of 1.024 ms
+    assert_eq!(lora.time_on_air_us(10).unwrap(), 41_216);
+    assert_eq!(lora.time_on_air_ms(10).unwrap(), 42);
+
+    lora.set_dwell_time_limit_ms(Some(400));
+    assert!(lora.check_dwell_time(50).is_ok());
+    lora.set_spreading_factor(10).unwrap();
+    assert!(matches!(
+        lora.check_dwell_time(50),
+        Err(Error::DwellTimeExceeded { limit_ms: 400, .. })
+    ));
+}
+
+#[test]
+fn bitrate() {
+    let (mut lora, _state) = radio(915);
+    lora.set_signal_bandwidth(125_000).unwrap();
+    lora.set_coding_rate_4(5).unwrap();
+    lora.set_spreading_factor(7).unwrap();
+    assert_eq!(lora.get_bitrate().unwrap(), 5_468);
+    lora.set_spreading_factor(12).unwrap();
+    lora.set_coding_rate_4(8).unwrap();
+    assert_eq!(lora.get_bitrate().unwrap(), 183);
+}
+
+#[test]
+fn receive_until_checks_the_clock() {
+    let (mut lora, _state) = radio(915);
+    lora.set_poll_interval_ms(10);
+    // Each poll takes 5 ms on top of the 10 ms wait
+    let mut clock = FakeClock { now_ms: 1_000, step_ms: 5, ..Default::default() };
+    let result = lora.receive_until(RxMode::Continuous, Some(1_050), &mut clock);
+    assert!(matches!(result, Err(Error::Timeout)));
+    assert_eq!(clock.waits, 3);
+    assert_eq!(clock.now_ms, 1_050);
+}
+
+#[test]
+fn receive_until_header_timeout() {
+    let (mut lora, state) = radio(915);
+    lora.set_header_timeout_ms(Some(20));
+    // Synchronized on a preamble, but no valid header follows
+    state.borrow_mut().set_reg(Register::RegModemStat, 0x02);
+    let mut clock = FakeClock::default();
+    let result = lora.receive_until(RxMode::Continuous, None, &mut clock);
+    assert!(matches!(result, Err(Error::InvalidHeader)));
+    assert_eq!(clock.now_ms, 20);
+}
+
+#[test]
+fn poll_irq_timeout_with_delay() {
+    let (mut lora, state) = radio(915);
+    let mut delay = MockDelay::default();
+    let result = lora.poll_irq(Some(30), &mut delay);
+    assert!(matches!(result, Err(Error::Uninformative)));
+    assert_eq!(delay.elapsed_ms, 30);
+
+    state.borrow_mut().set_reg(Register::RegIrqFlags, 0x40);
+    state.borrow_mut().set_reg(Register::RegRxNbBytes, 12);
+    assert_eq!(lora.poll_irq(Some(30), &mut delay).unwrap(), 12);
+}
+
+#[test]
+fn wait_for_tx_done_until_deadline() {
+    let (mut lora, state) = radio(915);
+    state.borrow_mut().set_reg(Register::RegOpMode, 0x83);
+    let mut clock = FakeClock::default();
+    let result = lora.wait_for_tx_done_until(Some(5), &mut clock);
+    assert!(matches!(result, Err(Error::Timeout)));
+    assert_eq!(clock.now_ms, 5);
+
+    state.borrow_mut().set_reg(Register::RegOpMode, 0x81);
+    state.borrow_mut().set_reg(Register::RegIrqFlags, 0x08);
+    lora.wait_for_tx_done_until(Some(5), &mut clock).unwrap();
+    assert_eq!(state.borrow().reg(Register::RegIrqFlags), 0);
+}
+
+#[test]
+fn tx_watchdog_recovers_stuck_transmission() {
+    let (mut lora, state) = radio(915);
+    state.borrow_mut().set_reg(Register::RegPayloadLength, 10);
+    // TxDone never arrives
+    state.borrow_mut().set_reg(Register::RegOpMode, 0x83);
+    state.borrow_mut().set_reg(Register::RegIrqFlags, 0x40);
+    lora.set_tx_watchdog(Some(TxWatchdog { margin: 2, reset: false }));
+    let stuck_after_ms = u64::from(lora.time_on_air_ms(10).unwrap()) * 2;
+    let mut clock = FakeClock::default();
+    let result = lora.wait_for_tx_done_until(None, &mut clock);
+    assert!(matches!(result, Err(Error::TxStuck)));
+    assert_eq!(clock.now_ms, stuck_after_ms + 1);
+    assert_eq!(state.borrow().reg(Register::RegOpMode), 0x81);
+    assert_eq!(state.borrow().reg(Register::RegIrqFlags), 0);
+
+    // The deadline still applies while the transmission is not considered stuck
+    state.borrow_mut().set_reg(Register::RegOpMode, 0x83);
+    let mut clock = FakeClock::default();
+    assert!(matches!(lora.wait_for_tx_done_until(Some(5), &mut clock), Err(Error::Timeout)));
+}
+
+#[test]
+fn tx_watchdog_reset_restores_config() {
+    let (mut lora, state) = radio(915);
+    lora.set_spreading_factor(9).unwrap();
+    state.borrow_mut().set_reg(Register::RegPayloadLength, 10);
+    state.borrow_mut().set_reg(Register::RegOpMode, 0x83);
+    lora.set_tx_watchdog(Some(TxWatchdog { margin: 3, reset: true }));
+    let stuck_after_ms = lora.time_on_air_ms(10).unwrap() * 3;
+    state.borrow_mut().writes.clear();
+    let mut delay = MockDelay::default();
+    lora.check_tx_watchdog(stuck_after_ms, &mut delay).unwrap();
+    assert!(state.borrow().writes.is_empty());
+
+    // The reset put the modem config back to its reset value
+    state.borrow_mut().set_reg(Register::RegModemConfig2, 0x70);
+    let result = lora.check_tx_watchdog(stuck_after_ms + 1, &mut delay);
+    assert!(matches!(result, Err(Error::TxStuck)));
+    assert!(delay.elapsed_ms >= 7);
+    assert_eq!(state.borrow().writes_to(Register::RegOpMode), [0x81, 0x00, 0x80, 0x81]);
+    assert_eq!(state.borrow().reg(Register::RegModemConfig2) >> 4, 9);
+
+    lora.set_tx_watchdog(None);
+    state.borrow_mut().set_reg(Register::RegOpMode, 0x83);
+    lora.check_tx_watchdog(u32::MAX, &mut delay).unwrap();
+}
+
+#[test]
+fn tx_power_keeps_max_power() {
+    let (mut lora, state) = radio(915);
+    assert_eq!(lora.get_pa_max_power().unwrap(), 7);
+    lora.set_pa_max_power(4).unwrap();
+    lora.set_tx_power(17).unwrap();
+    assert_eq!(state.borrow().reg(Register::RegPaConfig), 0xcf);
+    lora.set_tx_power(20).unwrap();
+    assert_eq!(state.borrow().reg(Register::RegPaConfig), 0xcf);
+    assert_eq!(state.borrow().reg(Register::RegPaDac), 0x87);
+    lora.set_tx_power(2).unwrap();
+    assert_eq!(state.borrow().reg(Register::RegPaConfig), 0xc0);
+    assert!(matches!(lora.set_pa_max_power(8), Err(Error::InvalidInput)));
+    assert_eq!(lora.get_pa_max_power().unwrap(), 4);
+}
+
+#[test]
+fn poll_irq_with_timer_expires() {
+    let (mut lora, _state) = radio(915);
+    let mut timer = MockTimer::default();
+    let result = lora.poll_irq_with_timer(&mut timer, 3u32);
+    assert!(matches!(result, Err(Error::Timeout)));
+    assert_eq!(timer.started, 1);
+    assert_eq!(timer.remaining, 0);
+}
+
+#[test]
+fn poll_irq_with_timer_packet_before_expiry() {
+    let (mut lora, state) = radio(915);
+    state.borrow_mut().set_reg(Register::RegRxNbBytes, 7);
+    // RxDone is raised on the last poll before the timer expires
+    state.borrow_mut().queue_reads(Register::RegIrqFlags, &[0x00, 0x00, 0x00, 0x40]);
+    let mut timer = MockTimer::default();
+    assert_eq!(lora.poll_irq_with_timer(&mut timer, 2u32).unwrap(), 7);
+    assert_eq!(timer.remaining, 0);
+}
+
+#[test]
+fn poll_irq_in_microseconds_and_symbols() {
+    let (mut lora, state) = radio(915);
+    state.borrow_mut().set_reg(Register::RegRxNbBytes, 5);
+    // RxDone is raised on the third poll, after clearing the IRQs when entering RX
+    state.borrow_mut().queue_reads(Register::RegIrqFlags, &[0x00, 0x00, 0x00, 0x40]);
+    let mut delay = MockDelay::default();
+    assert_eq!(lora.poll_irq_us(Some(500), 100, &mut delay).unwrap(), 5);
+    assert_eq!(delay.elapsed_us, 200);
+
+    // 4 symbols of 1024 µs at SF7 and 125 kHz
+    assert_eq!(lora.symbol_duration_us().unwrap(), 1024);
+    let mut delay = MockDelay::default();
+    let result = lora.poll_irq_symbols(4, 100, &mut delay);
+    assert!(matches!(result, Err(Error::Timeout)));
+    assert_eq!(delay.elapsed_us, 4100);
+}
+
+#[test]
+fn wait_for_tx_done_with_timer() {
+    let (mut lora, state) = radio(915);
+    state.borrow_mut().set_reg(Register::RegOpMode, 0x83);
+    let mut timer = MockTimer::default();
+    let result = lora.wait_for_tx_done_with_timer(&mut timer, 2u32);
+    assert!(matches!(result, Err(Error::Timeout)));
+
+    state.borrow_mut().queue_reads(Register::RegOpMode, &[0x83, 0x81]);
+    lora.wait_for_tx_done_with_timer(&mut timer, 2u32).unwrap();
+    assert_eq!(timer.remaining, 1);
+}
+
+#[test]
+fn transmit_payload_with_preamble_restores_length() {
+    let (mut lora, state) = radio(915);
+    lora.set_preamble_length(8).unwrap();
+    state.borrow_mut().writes.clear();
+    let payload = *b"wake";
+    lora.transmit_payload_with_preamble(&payload, 1000, &mut MockDelay::default())
+        .unwrap();
+    assert_eq!(state.borrow().writes_to(Register::RegPreambleMsb), [0x03, 0x00]);
+    assert_eq!(state.borrow().writes_to(Register::RegPreambleLsb), [0xe8, 0x08]);
+    assert_eq!(lora.get_preamble_length().unwrap(), 8);
+
+    // Restored as well when the transmission is refused
+    lora.set_dwell_time_limit_ms(Some(100));
+    let result = lora.transmit_payload_with_preamble(&payload, 1000, &mut MockDelay::default());
+    assert!(matches!(result, Err(Error::DwellTimeExceeded { .. })));
+    assert_eq!(lora.get_preamble_length().unwrap(), 8);
+
+    // A transmission still going after twice its time on air, long preamble included, is aborted
+    let (mut lora, state) = radio(915);
+    lora.set_preamble_length(1000).unwrap();
+    let airtime_ms = lora.time_on_air_ms(payload.len() as u8).unwrap();
+    lora.set_preamble_length(8).unwrap();
+    state.borrow_mut().after_mode(RadioMode::Tx, 100_000, Event::TxDone);
+    let mut delay = MockDelay::default();
+    let result = lora.transmit_payload_with_preamble(&payload, 1000, &mut delay);
+    assert!(matches!(result, Err(Error::Timeout)));
+    assert_eq!(delay.elapsed_ms, 2 * airtime_ms + 1);
+    assert_eq!(state.borrow().reg(Register::RegOpMode) & 0x07, 0x01);
+    assert!(matches!(lora.mode, RadioMode::Stdby));
+    assert_eq!(lora.get_preamble_length().unwrap(), 8);
+}
+
+#[test]
+fn reset_timing() {
+    let state = reset_state();
+    let mut delay = MockDelay::default();
+    LoRa::new(MockSpi(state.clone()), MockCs(state.clone()), MockReset, 915, &mut delay).unwrap();
+    assert_eq!(RESET_DELAY_MS, 10);
+    assert_eq!(delay.elapsed_us, u32::from(RESET_LOW_US) + 10_000);
+
+    // Opting into the datasheet timing
+    let mut delay = MockDelay::default();
+    LoRaBuilder::new(MockSpi(state.clone()), MockCs(state), MockReset, 915)
+        .reset_delay_ms(FAST_RESET_DELAY_MS)
+        .build(&mut delay)
+        .unwrap();
+    assert_eq!(delay.elapsed_us, u32::from(RESET_LOW_US) + 6_000);
+}
+
+#[test]
+fn version_read_again_while_zero() {
+    let state = reset_state();
+    state.borrow_mut().queue_reads(Register::RegVersion, &[0x00, 0x00]);
+    let mut delay = MockDelay::default();
+    LoRa::new(MockSpi(state.clone()), MockCs(state.clone()), MockReset, 915, &mut delay).unwrap();
+    assert_eq!(delay.elapsed_us, u32::from(RESET_LOW_US) + 12_000);
+
+    state
+        .borrow_mut()
+        .queue_reads(Register::RegVersion, &[0x00; 1 + VERSION_RETRIES as usize]);
+    let result = LoRa::new(MockSpi(state.clone()), MockCs(state), MockReset, 915, &mut delay);
+    assert!(matches!(result, Err(Error::NoResponse(0x00))));
+}
+
+#[test]
+fn version_readback_classified() {
+    for (version, retried) in [(0x00, true), (0xff, true), (0x62, false)] {
+        let state = reset_state();
+        state.borrow_mut().set_reg(Register::RegVersion, version);
+        let mut delay = MockDelay::default();
+        let result = LoRa::new(MockSpi(state.clone()), MockCs(state), MockReset, 915, &mut delay);
+        let error = result.err().unwrap();
+        match error {
+            Error::NoResponse(v) => assert!(retried && v == version),
+            Error::VersionMismatch(v) => assert!(!retried && v == version),
+            _ => panic!("unexpected error for {:#04x}", version),
+        }
+        let message = std::format!("{}", error);
+        let retries = if retried { u32::from(VERSION_RETRIES) } else { 0 };
+        assert_eq!(delay.elapsed_us, u32::from(RESET_LOW_US) + 10_000 + retries * 1_000);
+        let cause = match version {
+            0x00 => "MISO disconnected",
+            0xff => "MISO stuck high",
+            _ => "not an SX1272 or SX1276",
+        };
+        assert!(message.contains(cause), "{}", message);
+    }
+}
+
+#[test]
+fn tx_done_polling() {
+    let (mut lora, state) = radio(915);
+    state.borrow_mut().set_reg(Register::RegIrqFlags, 0x48);
+    assert!(lora.is_tx_done().unwrap());
+    assert!(lora.is_tx_done().unwrap());
+    assert!(lora.take_tx_done().unwrap());
+    assert!(!lora.take_tx_done().unwrap());
+    // Only TxDone is cleared
+    assert_eq!(state.borrow().reg(Register::RegIrqFlags), 0x40);
+}
+
+#[test]
+fn builder_tunes_in_hz() {
+    let state = reset_state();
+    let mut lora = LoRaBuilder::new(MockSpi(state.clone()), MockCs(state.clone()), MockReset, 868)
+        .frequency_hz(868_100_000)
+        .build(&mut MockDelay::default())
+        .unwrap();
+    // Tuned once, straight to 868.1 MHz
+    assert_eq!(state.borrow().writes_to(Register::RegFrfMsb), [0xd9]);
+    assert_eq!(state.borrow().writes_to(Register::RegFrfMid), [0x06]);
+    assert_eq!(state.borrow().writes_to(Register::RegFrfLsb), [0x66]);
+    assert_eq!(lora.get_frequency_hz().unwrap(), 868_099_975);
+
+    let state = reset_state();
+    let result = LoRaBuilder::new(MockSpi(state.clone()), MockCs(state.clone()), MockReset, 868)
+        .frequency_hz(2_400_000_000)
+        .build(&mut MockDelay::default());
+    assert!(matches!(result, Err(Error::InvalidInput)));
+}
+
+#[test]
+fn fifo_bursts_are_chunked() {
+    let state = reset_state();
+    let mut lora = LoRaBuilder::new(MockSpi(state.clone()), MockCs(state.clone()), MockReset, 868)
+        .spi_chunk_len(16)
+        .build(&mut MockDelay::default())
+        .unwrap();
+    let payload: [u8; 40] = core::array::from_fn(|i| i as u8);
+    state.borrow_mut().transfer_lens.clear();
+    lora.transmit_payload(&payload).unwrap();
+    assert_eq!(state.borrow().writes_to(Register::RegFifo), payload);
+    assert!(state.borrow().transfer_lens.iter().all(|&len| len <= 16));
+    assert_eq!(state.borrow().fifo[..40], payload);
+
+    state.borrow_mut().set_reg(Register::RegIrqFlags, 0x40);
+    state.borrow_mut().set_reg(Register::RegFifoRxCurrentAddr, 0);
+    state.borrow_mut().set_reg(Register::RegRxNbBytes, 40);
+    state.borrow_mut().transfer_lens.clear();
+    let mut buffer = [0; 64];
+    assert_eq!(lora.read_packet_into(&mut buffer).unwrap(), 40);
+    assert_eq!(buffer[..40], payload);
+    assert!(state.borrow().transfer_lens.iter().all(|&len| len <= 16));
+
+    let state = reset_state();
+    let result = LoRaBuilder::new(MockSpi(state.clone()), MockCs(state.clone()), MockReset, 868)
+        .spi_chunk_len(0)
+        .build(&mut MockDelay::default());
+    assert!(matches!(result, Err(Error::InvalidInput)));
+}
+
+#[test]
+fn version_check_options() {
+    let build = |version, builder: fn(LoRaBuilder<MockSpi, MockCs, MockReset>) -> LoRaBuilder<MockSpi, MockCs, MockReset>| {
+        let state = reset_state();
+        state.borrow_mut().set_reg(Register::RegVersion, version);
+        builder(LoRaBuilder::new(MockSpi(state.clone()), MockCs(state), MockReset, 915))
+            .build(&mut MockDelay::default())
+    };
+
+    // Strict by default
+    assert_eq!(build(0x12, |builder| builder).unwrap().version(), 0x12);
+    assert!(matches!(build(0x11, |builder| builder), Err(Error::VersionMismatch(0x11))));
+
+    let lora = build(0x11, |builder| builder.skip_version_check()).unwrap();
+    assert_eq!(lora.version(), 0x11);
+    assert_eq!(lora.chip(), Chip::Sx1276);
+    assert_eq!(build(0x22, |builder| builder.skip_version_check()).unwrap().chip(), Chip::Sx1272);
+
+    let lora = build(0x11, |builder| builder.expect_version(0x11)).unwrap();
+    assert_eq!(lora.version(), 0x11);
+    assert_eq!(lora.chip(), Chip::Sx1276);
+    assert!(matches!(build(0x12, |builder| builder.expect_version(0x11)), Err(Error::VersionMismatch(0x12))));
+    // A silent radio is reported as such, not as another version
+    assert!(matches!(build(0xff, |builder| builder.expect_version(0x11)), Err(Error::NoResponse(0xff))));
+}
+
+#[test]
+fn sx1272_detected_and_laid_out() {
+    let state = reset_state();
+    state.borrow_mut().set_reg(Register::RegVersion, 0x22);
+    state.borrow_mut().set_reg(Register::RegModemConfig1, 0x08);
+    let mut lora = LoRaBuilder::new(MockSpi(state.clone()), MockCs(state.clone()), MockReset, 433)
+        .build(&mut MockDelay::default())
+        .unwrap();
+    assert_eq!(lora.chip(), Chip::Sx1272);
+    // No LowFrequencyModeOn bit on the single port SX1272, AgcAutoOn in RegModemConfig2
+    assert_eq!(state.borrow().reg(Register::RegOpMode), 0x81);
+    assert_eq!(state.borrow().reg(Register::RegModemConfig2) & 0x04, 0x04);
+    assert!(state.borrow().writes_to(Register::RegModemConfig3).is_empty());
+
+    lora.set_signal_bandwidth(250_000).unwrap();
+    lora.set_coding_rate_4(8).unwrap();
+    lora.set_crc(true).unwrap();
+    // 250 kHz in bits 7-6, 4/8 in bits 5-3, CRC in bit 1
+    assert_eq!(state.borrow().reg(Register::RegModemConfig1), 0x62);
+    assert_eq!(lora.get_signal_bandwidth().unwrap(), 250_000);
+    assert_eq!(lora.get_coding_rate_4().unwrap(), 8);
+    assert!(lora.get_crc_enabled().unwrap());
+    lora.set_signal_bandwidth(125_000).unwrap();
+    lora.set_spreading_factor(12).unwrap();
+    assert_eq!(state.borrow().reg(Register::RegModemConfig1) & 0x01, 0x01);
+    assert!(matches!(lora.set_signal_bandwidth(62_500), Err(Error::InvalidInput)));
+    // AgcAutoOn is switched without touching the CRC flag
+    lora.set_rx_profile(RxProfile::LowPower).unwrap();
+    assert_eq!(state.borrow().reg(Register::RegModemConfig2) & 0x04, 0x00);
+    assert!(!lora.get_modem_config_2().unwrap().agc_auto_on);
+    assert!(lora.get_crc_enabled().unwrap());
+    lora.set_rx_profile(RxProfile::MaxSensitivity).unwrap();
+    assert!(lora.get_modem_config_2().unwrap().agc_auto_on);
+    assert!(lora.get_crc_enabled().unwrap());
+    // The IF errata is SX1276 only
+    assert!(state.borrow().writes_to(Register::RegIfFreq1).is_empty());
+
+    lora.set_tx_power_dbm(20, PaSelection::PaBoost).unwrap();
+    assert_eq!(state.borrow().reg(Register::RegPaDacSx1272), 0x87);
+    assert_eq!(state.borrow().reg(Register::RegPaDac), 0x84);
+
+    state.borrow_mut().set_reg(Register::RegVersion, 0x12);
+    assert_eq!(lora.detect_chip().unwrap(), Chip::Sx1276);
+    state.borrow_mut().set_reg(Register::RegVersion, 0x62);
+    assert!(matches!(lora.detect_chip(), Err(Error::VersionMismatch(0x62))));
+    assert_eq!(Chip::from_version(0x12), Some(Chip::Sx1276));
+}
+
+#[test]
+fn sx1272_rssi_offset_and_rfo_power() {
+    let state = reset_state();
+    state.borrow_mut().set_reg(Register::RegVersion, 0x22);
+    let mut lora = LoRaBuilder::new(MockSpi(state.clone()), MockCs(state.clone()), MockReset, 868)
+        .build(&mut MockDelay::default())
+        .unwrap();
+    // A single RSSI offset, whatever the frequency
+    assert_eq!(lora.rssi_offset(), -139);
+    state.borrow_mut().set_reg(Register::RegRssiValue, 0x50);
+    assert_eq!(lora.get_current_rssi().unwrap(), -59);
+    lora.set_frequency(433).unwrap();
+    assert_eq!(lora.rssi_offset(), -139);
+
+    // No MaxPower field, init leaves the unused bits 6-4 alone
+    assert!(state.borrow().writes_to(Register::RegPaConfig).is_empty());
+    assert!(matches!(lora.set_pa_max_power(7), Err(Error::InvalidInput)));
+    assert!(matches!(lora.get_pa_max_power(), Err(Error::InvalidInput)));
+    // Pout = -1 + OutputPower on RFO
+    for (dbm, output_power) in [(-1, 0x0), (0, 0x1), (10, 0xb), (14, 0xf)] {
+        lora.set_tx_power_dbm(dbm, PaSelection::Rfo).unwrap();
+        assert_eq!(state.borrow().reg(Register::RegPaConfig), 0x40 | output_power, "{} dBm", dbm);
+    }
+    assert!(matches!(lora.set_tx_power_dbm(-2, PaSelection::Rfo), Err(Error::InvalidInput)));
+    lora.set_tx_power(7).unwrap();
+    assert_eq!(state.borrow().reg(Register::RegPaConfig), 0x48);
+    // Pout = 2 + OutputPower on PA_BOOST, as on the SX1276
+    lora.set_tx_power_dbm(17, PaSelection::PaBoost).unwrap();
+    assert_eq!(state.borrow().reg(Register::RegPaConfig), 0xcf);
+}
+
+#[test]
+fn init_without_reset_rewrites() {
+    let state = reset_state();
+    state.borrow_mut().set_reg(Register::RegFifoTxBaseAddr, 0x00);
+    let mut delay = MockDelay::default();
+    LoRaBuilder::new(MockSpi(state.clone()), MockCs(state.clone()), MockReset, 868)
+        .init_mode(InitMode::Rewrite)
+        .build(&mut delay)
+        .unwrap();
+    // No reset pulse, and the FIFO bases already at 0 are left alone
+    assert_eq!(delay.elapsed_us, 0);
+    assert!(state.borrow().writes_to(Register::RegFifoTxBaseAddr).is_empty());
+    assert!(state.borrow().writes_to(Register::RegFifoRxBaseAddr).is_empty());
+    assert_eq!(state.borrow().reg(Register::RegOpMode), 0x81);
+    assert_eq!(state.borrow().reg(Register::RegFrfMsb), 0xd9);
+
+    // A radio that was power cycled is configured as well when adopting
+    let state = reset_state();
+    LoRaBuilder::new(MockSpi(state.clone()), MockCs(state.clone()), MockReset, 868)
+        .init_mode(InitMode::Adopt)
+        .build(&mut delay)
+        .unwrap();
+    assert_eq!(state.borrow().writes_to(Register::RegFifoTxBaseAddr), [0x00]);
+    assert_eq!(state.borrow().reg(Register::RegOpMode), 0x81);
+}
+
+#[test]
+fn init_adopts_configured_radio() {
+    let state = reset_state();
+    {
+        let mut state = state.borrow_mut();
+        // Left receiving at 868.1 MHz in implicit header mode on RFO, accepting up to 64 bytes,
+        // with a packet waiting
+        state.set_reg(Register::RegOpMode, 0x85);
+        state.set_reg(Register::RegFrfMsb, 0xd9);
+        state.set_reg(Register::RegFrfMid, 0x06);
+        state.set_reg(Register::RegFrfLsb, 0x66);
+        state.set_reg(Register::RegModemConfig1, 0x73);
+        state.set_reg(Register::RegPaConfig, 0x4f);
+        state.set_reg(Register::RegFifoTxBaseAddr, 0x80);
+        state.set_reg(Register::RegMaxPayloadLength, 0x40);
+        state.set_reg(Register::RegIrqFlags, 0x40);
+        state.set_reg(Register::RegRxNbBytes, 3);
+        state.fifo[..3].copy_from_slice(b"abc");
+    }
+    let mut delay = MockDelay::default();
+    let mut lora = LoRaBuilder::new(MockSpi(state.clone()), MockCs(state.clone()), MockReset, 433)
+        .init_mode(InitMode::Adopt)
+        .build(&mut delay)
+        .unwrap();
+    assert_eq!(delay.elapsed_us, 0);
+    assert!(state.borrow().writes.is_empty());
+    assert_eq!(lora.band(), Band::Hf);
+    assert_eq!(lora.get_pa_output(), PaOutput::Rfo);
+    let debug = std::format!("{:?}", lora);
+    assert!(debug.contains("frequency_hz: 868099975, mode: RxContinuous, explicit_header: false"), "{}", debug);
+    assert!(debug.contains("max_payload_length: 64"), "{}", debug);
+
+    let mut buffer = [0; 8];
+    assert_eq!(lora.read_packet_into(&mut buffer).unwrap(), 3);
+    assert_eq!(&buffer[..3], b"abc");
+    lora.verify_config().unwrap();
+    state.borrow_mut().set_reg(Register::RegModemConfig1, 0x72);
+    assert!(matches!(lora.verify_config(), Err(Error::ConfigMismatch(_))));
+}
+
+#[test]
+fn frf_lorawan_channels() {
+    let channels = [
+        // EU868 default channels and RX2
+        (868_100_000, 0xd9_0666),
+        (868_300_000, 0xd9_1333),
+        (868_500_000, 0xd9_2000),
+        (867_100_000, 0xd8_c666),
+        (869_525_000, 0xd9_6199),
+        // US915 first uplink and downlink channels
+        (902_300_000, 0xe1_9333),
+        (923_300_000, 0xe6_d333),
+        // EU433 and CN470
+        (433_175_000, 0x6c_4b33),
+        (470_300_000, 0x75_9333),
+    ];
+    for (hz, frf) in channels {
+        assert_eq!(frf_from_hz(hz, FXOSC_HZ), frf, "{} Hz", hz);
+        let programmed = hz_from_frf(frf, FXOSC_HZ);
+        assert!(programmed <= hz && hz - programmed < 62, "{} Hz", hz);
+    }
+}
+
+#[test]
+fn frf_boundaries() {
+    // The top of the 24 bit register, just below 1024 MHz
+    assert_eq!(hz_from_frf(FRF_MAX, FXOSC_HZ), 1_023_999_938);
+    assert_eq!(frf_from_hz(1_023_999_938, FXOSC_HZ), FRF_MAX - 1);
+    assert_eq!(frf_from_hz(1_023_999_939, FXOSC_HZ), FRF_MAX);
+    assert_eq!(frf_from_hz(1_024_000_000, FXOSC_HZ), FRF_MAX + 1);
+    // Where `MHz * 2^19` overflowed a u32, and any frequency in Hz did
+    assert_eq!(frf_from_hz(8_191_000_000, FXOSC_HZ), 134_201_344);
+    assert_eq!(frf_from_hz(8_192_000_000, FXOSC_HZ), 134_217_728);
+    assert_eq!(frf_from_hz(u64::MAX, FXOSC_HZ), u32::MAX);
+    assert_eq!(frf_from_hz(0, FXOSC_HZ), 0);
+    assert_eq!(hz_from_frf(u32::MAX, FXOSC_HZ), (u64::from(u32::MAX) * 32_000_000) >> 19);
+    // A 26 MHz crystal has a smaller step
+    assert_eq!(frf_from_hz(433_000_000, 26_000_000), 0x85_3b13);
+    // The most negative 20 bit FreqError
+    assert_eq!(frequency_error_hz(-1 << 19, 500_000, FXOSC_HZ), -274_877);
+    assert_eq!(frequency_error_hz(1000, 125_000, FXOSC_HZ), 131);
+}
+
+#[test]
+fn frf_round_trip_exhaustive() {
+    for frf in 0..=FRF_MAX {
+        let hz = hz_from_frf(frf, FXOSC_HZ);
+        // Rounding down to whole hertz may land just below the step, never more than one step
+        let back = frf_from_hz(hz, FXOSC_HZ);
+        assert!(back == frf || back + 1 == frf, "Frf {:#x}", frf);
+        assert!(frf_from_hz(hz + 1, FXOSC_HZ) >= frf, "Frf {:#x}", frf);
+    }
+}
+
+#[test]
+fn crystal_frequency() {
+    let state = reset_state();
+    let mut lora = LoRaBuilder::new(MockSpi(state.clone()), MockCs(state.clone()), MockReset, 433)
+        .crystal_hz(26_000_000)
+        .build(&mut MockDelay::default())
+        .unwrap();
+    // 433 MHz * 2^19 / 26 MHz, instead of 0x6c4000 with a 32 MHz crystal
+    let frf = [
+        state.borrow().reg(Register::RegFrfMsb),
+        state.borrow().reg(Register::RegFrfMid),
+        state.borrow().reg(Register::RegFrfLsb),
+    ];
+    assert_eq!(frf, [0x85, 0x3b, 0x13]);
+    assert_eq!(lora.get_frequency_hz().unwrap(), 432_999_965);
+    // Out of reach of the synthesizer with this crystal
+    assert!(matches!(lora.set_frequency(915), Err(Error::InvalidInput)));
+}
+
+#[test]
+fn transmit_payload_crc_restores_setting() {
+    let (mut lora, state) = radio(915);
+    lora.set_crc(true).unwrap();
+    state.borrow_mut().writes.clear();
+    let payload = *b"beacon";
+    lora.transmit_payload_crc(&payload, false, &mut MockDelay::default())
+        .unwrap();
+    let crc_bits: Vec<bool> = state
+        .borrow()
+        .writes_to(Register::RegModemConfig2)
+        .iter()
+        .map(|config| config & 0x04 != 0)
+        .collect();
+    assert_eq!(crc_bits, [false, true]);
+    assert!(lora.get_crc_enabled().unwrap());
+
+    // A transmission still going after twice its time on air is aborted
+    let (mut lora, state) = radio(915);
+    lora.set_crc(true).unwrap();
+    let airtime_ms = lora.time_on_air_ms(payload.len() as u8).unwrap();
+    lora.set_crc(false).unwrap();
+    state.borrow_mut().after_mode(RadioMode::Tx, 100_000, Event::TxDone);
+    let mut delay = MockDelay::default();
+    let result = lora.transmit_payload_crc(&payload, true, &mut delay);
+    assert!(matches!(result, Err(Error::Timeout)));
+    assert_eq!(delay.elapsed_ms, 2 * airtime_ms + 1);
+    assert_eq!(state.borrow().reg(Register::RegOpMode) & 0x07, 0x01);
+    assert!(!lora.get_crc_enabled().unwrap());
+}
+
+#[test]
+fn modem_clear_guard_waits() {
+    let (mut lora, state) = radio(915);
+    let payload = *b"data";
+    lora.set_modem_clear_guard(Some(10));
+    // Clear right away
+    let mut delay = MockDelay::default();
+    lora.transmit_payload_crc(&payload, true, &mut delay).unwrap();
+    assert_eq!(delay.elapsed_ms, 0);
+    // The modem clears on the third read, a poll interval apart
+    state.borrow_mut().queue_reads(Register::RegModemStat, &[0x0b, 0x0b, 0x10]);
+    lora.transmit_payload_crc(&payload, true, &mut delay).unwrap();
+    assert!(state.borrow().reads[&(Register::RegModemStat as u8)].is_empty());
+    assert_eq!(delay.elapsed_ms, 2);
+
+    let config = ChannelConfig {
+        frequency_hz: 868_100_000,
+        spreading_factor: 9,
+        bandwidth: 125_000,
+        crc: true,
+        invert_iq: false,
+    };
+    state.borrow_mut().queue_reads(Register::RegModemStat, &[0x0b, 0x10]);
+    let mut delay = MockDelay::default();
+    lora.apply_config(&config, &mut delay).unwrap();
+    assert_eq!(delay.elapsed_ms, 1);
+    assert_eq!(lora.get_spreading_factor().unwrap(), 9);
+    assert_eq!(lora.frequency, 868_100_000);
+}
+
+#[test]
+fn modem_clear_guard_gives_up() {
+    let (mut lora, state) = radio(915);
+    let payload = *b"data";
+    assert!(lora.is_modem_clear().unwrap());
+    lora.set_modem_clear_guard(Some(3));
+    state.borrow_mut().set_reg(Register::RegModemStat, 0x0b);
+    // Checked once without blocking
+    assert!(matches!(lora.transmit_payload(&payload), Err(Error::ChannelBusy)));
+    assert!(state.borrow().writes_to(Register::RegFifo).is_empty());
+    // Waited for up to the timeout
+    let mut delay = MockDelay::default();
+    assert!(matches!(lora.transmit_payload_crc(&payload, true, &mut delay), Err(Error::ChannelBusy)));
+    assert_eq!(delay.elapsed_ms, 3);
+    let config = ChannelConfig {
+        frequency_hz: 868_100_000,
+        spreading_factor: 9,
+        bandwidth: 125_000,
+        crc: true,
+        invert_iq: false,
+    };
+    state.borrow_mut().writes.clear();
+    assert!(matches!(lora.apply_config(&config, &mut delay), Err(Error::ChannelBusy)));
+    assert!(state.borrow().writes.is_empty());
+    assert!(matches!(lora.wait_modem_clear(5, &mut delay), Err(Error::ChannelBusy)));
+    assert_eq!(delay.elapsed_ms, 11);
+
+    // Off, the previous behavior is kept
+    lora.set_modem_clear_guard(None);
+    assert!(matches!(lora.transmit_payload(&payload), Err(Error::Busy)));
+}
+
+#[test]
+fn transmit_if_clear() {
+    let (mut lora, state) = radio(915);
+    let payload = *b"data";
+    lora.set_symbol_timeout(100).unwrap();
+
+    state.borrow_mut().queue_reads(Register::RegIrqFlags, &[0x00, 0x80]);
+    assert!(lora.transmit_if_clear(&payload, 16, &mut MockDelay::default()).unwrap());
+    assert_eq!(state.borrow().writes_to(Register::RegSymbTimeoutLsb), [100, 16, 100]);
+    assert_eq!(state.borrow().writes_to(Register::RegFifo).len(), 4);
+
+    state.borrow_mut().writes.clear();
+    state.borrow_mut().queue_reads(Register::RegIrqFlags, &[0x00, 0x40]);
+    assert!(!lora.transmit_if_clear(&payload, 16, &mut MockDelay::default()).unwrap());
+    assert!(state.borrow().writes_to(Register::RegFifo).is_empty());
+    assert_eq!(lora.get_symbol_timeout().unwrap(), 100);
+}
+
+#[test]
+fn cad_polling() {
+    let (mut lora, state) = radio(915);
+    state.borrow_mut().queue_reads(Register::RegIrqFlags, &[0x00, 0x05]);
+    assert!(lora.cad(&mut MockDelay::default()).unwrap());
+    assert_eq!(state.borrow().writes_to(Register::RegOpMode).last(), Some(&0x87));
+    assert_eq!(state.borrow().writes_to(Register::RegIrqFlags), [0x05, 0x05]);
+
+    state.borrow_mut().queue_reads(Register::RegIrqFlags, &[0x04]);
+    assert!(!lora.cad(&mut MockDelay::default()).unwrap());
+
+    // CadDone never raised, 4 symbols of 1.024 ms at SF7 and 125 kHz
+    let mut delay = MockDelay::default();
+    assert!(matches!(lora.cad(&mut delay), Err(Error::Timeout)));
+    assert_eq!(delay.elapsed_ms, 6);
+    assert_eq!(state.borrow().reg(Register::RegOpMode) & 0x07, 0x01);
+    assert!(matches!(lora.mode, RadioMode::Stdby));
+}
+
+#[test]
+fn cad_on_dio3() {
+    let (mut lora, state) = radio(915);
+    lora.set_dio3_mapping(Dio3Mapping::CadDone).unwrap();
+    lora.set_dio4_mapping(Dio4Mapping::CadDetected).unwrap();
+    lora.set_dio3_mapping(Dio3Mapping::ValidHeader).unwrap();
+    lora.set_dio4_mapping(Dio4Mapping::PllLock).unwrap();
+    assert_eq!(state.borrow().reg(Register::RegDioMapping1), 0x01);
+    assert_eq!(state.borrow().reg(Register::RegDioMapping2), 0x40);
+    // Mapping TxDone on DIO0 keeps the DIO3 mapping
+    lora.set_dio0_tx_done().unwrap();
+    assert_eq!(state.borrow().reg(Register::RegDioMapping1), 0x41);
+
+    let dio3 = MockInput::default();
+    dio3.levels.borrow_mut().extend([false, false, true]);
+    // Read once the pin went high
+    state.borrow_mut().queue_reads(Register::RegIrqFlags, &[0x05]);
+    let mut delay = MockDelay::default();
+    assert!(lora.cad_on_irq(&dio3, &mut delay).unwrap());
+    assert_eq!(delay.elapsed_ms, 2);
+
+    // The pin never going high times out
+    dio3.levels.borrow_mut().extend([false; 100]);
+    let mut delay = MockDelay::default();
+    assert!(matches!(lora.cad_on_irq(&dio3, &mut delay), Err(Error::Timeout)));
+    assert_eq!(delay.elapsed_ms, 6);
+    assert_eq!(state.borrow().reg(Register::RegOpMode) & 0x07, 0x01);
+}
+
+#[test]
+fn packet_frequency_error_ignores_unused_bits() {
+    let (mut lora, state) = radio(915);
+    lora.set_signal_bandwidth(125_000).unwrap();
+    // Bits 7-4 of RegFeiMsb are not part of the value
+    {
+        let mut state = state.borrow_mut();
+        state.set_reg(Register::RegFreqErrorMsb, 0xff);
+        state.set_reg(Register::RegFreqErrorMid, 0xfc);
+        state.set_reg(Register::RegFreqErrorLsb, 0x18);
+    }
+    assert_eq!(lora.get_packet_frequency_error().unwrap(), -131);
+    state.borrow_mut().set_reg(Register::RegFreqErrorMsb, 0xf0);
+    state.borrow_mut().set_reg(Register::RegFreqErrorMid, 0x03);
+    state.borrow_mut().set_reg(Register::RegFreqErrorLsb, 0xe8);
+    assert_eq!(lora.get_packet_frequency_error().unwrap(), 131);
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn cad_async() {
+    use crate::mock::{block_on, MockWait};
+
+    let (mut lora, state) = radio(915);
+    let mut dio3 = MockWait::default();
+    state.borrow_mut().queue_reads(Register::RegIrqFlags, &[0x05]);
+    assert!(block_on(lora.cad_async(&mut dio3)).unwrap());
+    state.borrow_mut().queue_reads(Register::RegIrqFlags, &[0x04]);
+    assert!(!block_on(lora.cad_async(&mut dio3)).unwrap());
+    assert_eq!(dio3.waits, 2);
+    assert_eq!(state.borrow().writes_to(Register::RegIrqFlags), [0x05, 0x05, 0x05, 0x05]);
+}
+
+#[test]
+fn packet_frequency_error_extremes() {
+    let (mut lora, state) = radio(915);
+    lora.set_signal_bandwidth(250_000).unwrap();
+    // -1, the most negative and the most positive 20 bit values
+    for (fei, error) in [([0x0f, 0xff, 0xff], 0), ([0x08, 0x00, 0x00], -137_438), ([0x07, 0xff, 0xff], 137_438)] {
+        {
+            let mut state = state.borrow_mut();
+            state.set_reg(Register::RegFreqErrorMsb, fei[0]);
+            state.set_reg(Register::RegFreqErrorMid, fei[1]);
+            state.set_reg(Register::RegFreqErrorLsb, fei[2]);
+        }
+        assert_eq!(lora.get_packet_frequency_error().unwrap(), error, "{:02x?}", fei);
+    }
+}
+
+#[test]
+fn transmit_payload_length_validation() {
+    let (mut lora, state) = radio(915);
+    for (len, accepted) in [(0, false), (1, true), (255, true)] {
+        state.borrow_mut().writes.clear();
+        let payload: heapless::Vec<u8, 255> = core::iter::repeat_n(0xa5, len).collect();
+        let result = lora.transmit_payload(&payload);
+        assert_eq!(result.is_ok(), accepted, "{} bytes", len);
+        if accepted {
+            assert_eq!(state.borrow().writes_to(Register::RegFifo).len(), len);
+            assert_eq!(state.borrow().writes_to(Register::RegPayloadLength), [len as u8]);
+        } else {
+            assert!(matches!(result, Err(Error::InvalidInput)));
+            assert!(state.borrow().writes.is_empty());
+        }
+    }
+    assert!(matches!(lora.transmit_payload(&[0; 256]), Err(Error::InvalidInput)));
+}
+
+#[test]
+fn transmit_payload_array() {
+    let (mut lora, state) = radio(915);
+    let mut buffer = [0u8; 255];
+    buffer[..5].copy_from_slice(b"hello");
+    assert_eq!(lora.transmit_payload_array(&buffer, 5).unwrap(), 5);
+    assert_eq!(state.borrow().writes_to(Register::RegFifo), b"hello");
+    assert!(matches!(lora.transmit_payload_array(&buffer, 0), Err(Error::InvalidInput)));
+    assert!(matches!(lora.transmit_payload_array(&buffer, 256), Err(Error::InvalidInput)));
+}
+
+#[test]
+fn transmit_str_and_read_str() {
+    let (mut lora, state) = radio(915);
+    assert_eq!(lora.transmit_str("héllo").unwrap(), 6);
+    assert_eq!(state.borrow().writes_to(Register::RegFifo), "héllo".as_bytes());
+    assert!(matches!(lora.transmit_str(""), Err(Error::InvalidInput)));
+    let long = "x".repeat(256);
+    assert!(matches!(lora.transmit_str(&long), Err(Error::InvalidInput)));
+
+    let mut buffer = [0u8; 255];
+    for (payload, text) in [(&b"h\xc3\xa9llo"[..], "héllo"), (b"a\xffb\xe2\x82", "a?b??"), (b"\xc3(", "?(")] {
+        lora.arm_rx().unwrap();
+        state.borrow_mut().after(0, packet(payload));
+        assert_eq!(lora.read_str(&mut buffer).unwrap(), text);
+    }
+}
+
+#[test]
+fn transmit_small_vec() {
+    let (mut lora, state) = radio(915);
+    let payload: heapless::Vec<u8, 16> = heapless::Vec::from_slice(b"sixteen bytes!!!").unwrap();
+    lora.transmit_payload(&payload).unwrap();
+    assert_eq!(state.borrow().writes_to(Register::RegFifo), b"sixteen bytes!!!");
+}
+
+#[test]
+fn transmit_fhss_follows_hops() {
+    let (mut lora, state) = radio(915);
+    let channels = [902_300_000, 902_500_000, 902_700_000];
+    {
+        let mut state = state.borrow_mut();
+        state.after_mode(RadioMode::Tx, 10, Event::Hop { channel: 1 });
+        state.after(10, Event::Hop { channel: 2 });
+        state.after(10, Event::Hop { channel: 0 });
+        state.after(10, Event::TxDone);
+    }
+    state.borrow_mut().writes.clear();
+    lora.transmit_fhss(b"hop", &channels, Some(100), &mut MockDelay::default()).unwrap();
+    // 902.3 MHz first, then channels 1, 2 and 0 as they are signalled
+    assert_eq!(state.borrow().writes_to(Register::RegFrfMid), [0x93, 0xa0, 0xac, 0x93]);
+    assert_eq!(state.borrow().reg(Register::RegIrqFlags), 0);
+    assert!(matches!(lora.mode, RadioMode::Stdby));
+
+    // A hop without TxDone times out, the radio being taken out of TX
+    state.borrow_mut().after_mode(RadioMode::Tx, 10, Event::Hop { channel: 1 });
+    let mut delay = MockDelay::default();
+    let result = lora.transmit_fhss(b"hop", &channels, Some(50), &mut delay);
+    assert!(matches!(result, Err(Error::Timeout)));
+    assert_eq!(delay.elapsed_ms, 50);
+    assert_eq!(state.borrow().reg(Register::RegOpMode) & 0x07, 0x01);
+
+    // Without a timeout, the time on air bounds the wait
+    let airtime_ms = lora.time_on_air_ms(3).unwrap();
+    state.borrow_mut().after_mode(RadioMode::Tx, 100_000, Event::TxDone);
+    let mut delay = MockDelay::default();
+    let result = lora.transmit_fhss(b"hop", &channels, None, &mut delay);
+    assert!(matches!(result, Err(Error::Timeout)));
+    assert_eq!(delay.elapsed_ms, 2 * airtime_ms + 1);
+    assert!(matches!(lora.transmit_fhss(b"hop", &[], None, &mut delay), Err(Error::InvalidInput)));
+}
+
+#[test]
+fn receive_fhss_follows_hops() {
+    let (mut lora, state) = radio(915);
+    let channels = [902_300_000, 902_500_000, 902_700_000];
+    {
+        let mut state = state.borrow_mut();
+        state.after_mode(RadioMode::RxContinuous, 10, Event::Hop { channel: 2 });
+        state.after(10, Event::Hop { channel: 1 });
+        state.after(10, packet(b"hopped"));
+    }
+    state.borrow_mut().writes.clear();
+    let mut delay = MockDelay::default();
+    assert_eq!(lora.receive_fhss(&channels, Some(100), &mut delay).unwrap(), 6);
+    assert_eq!(state.borrow().writes_to(Register::RegFrfMid), [0x93, 0xac, 0xa0]);
+    let mut buffer = [0u8; 8];
+    assert_eq!(lora.read_packet_into(&mut buffer).unwrap(), 6);
+    assert_eq!(&buffer[..6], b"hopped");
+
+    // Nothing arriving times out
+    let result = lora.receive_fhss(&channels, Some(20), &mut delay);
+    assert!(matches!(result, Err(Error::Timeout)));
+}
+
+#[test]
+fn start_fhss_transmit_follows_channels() {
+    let (mut lora, state) = radio(915);
+    let payload = *b"hop";
+    let channels = [902_300_000, 902_500_000, 902_700_000];
+    // Two hops are signalled before TxDone
+    {
+        let mut state = state.borrow_mut();
+        state.after_mode(RadioMode::Tx, 10, Event::Hop { channel: 1 });
+        state.after(10, Event::Hop { channel: 2 });
+        state.after(10, Event::TxDone);
+    }
+    lora.start_fhss_transmit(&payload, &channels, 10, None, &mut MockDelay::default())
+        .unwrap();
+    assert_eq!(state.borrow().reg(Register::RegHopPeriod), 10);
+    // 902.3 MHz first, then channels 1 and 2
+    assert_eq!(state.borrow().writes_to(Register::RegFrfMid), [0x93, 0xa0, 0xac]);
+
+    // Hops without TxDone time out, the radio being taken out of TX
+    state.borrow_mut().after_mode(RadioMode::Tx, 10, Event::Hop { channel: 2 });
+    let mut delay = MockDelay::default();
+    let result = lora.start_fhss_transmit(&payload, &channels, 10, Some(30), &mut delay);
+    assert!(matches!(result, Err(Error::Timeout)));
+    assert_eq!(delay.elapsed_ms, 30);
+    assert_eq!(state.borrow().reg(Register::RegOpMode) & 0x07, 0x01);
+    assert_eq!(state.borrow().writes_to(Register::RegFrfMid).last(), Some(&0xac));
+
+    // The TX watchdog fires first when enabled
+    lora.set_tx_watchdog(Some(TxWatchdog { margin: 1, reset: false }));
+    let airtime_ms = lora.time_on_air_ms(3).unwrap();
+    state.borrow_mut().after_mode(RadioMode::Tx, 100_000, Event::TxDone);
+    let mut delay = MockDelay::default();
+    let result = lora.start_fhss_transmit(&payload, &channels, 10, Some(10_000), &mut delay);
+    assert!(matches!(result, Err(Error::TxStuck)));
+    assert_eq!(delay.elapsed_ms, airtime_ms + 1);
+}
+
+#[test]
+fn start_fhss_transmit_validates_channels() {
+    let (mut lora, _state) = radio(915);
+    let payload = *b"hop";
+    let mut delay = MockDelay::default();
+    let invalid: [(&[u64], u8); 4] =
+        [(&[], 10), (&[902_300_000], 0), (&[902_300_000, 600_000_000], 10), (&[860_500_000], 10)];
+    for (channels, hop_period) in invalid {
+        let result = lora.start_fhss_transmit(&payload, channels, hop_period, None, &mut delay);
+        assert!(matches!(result, Err(Error::InvalidInput)));
+    }
+
+    // The SX1272 covers 860-1020 MHz only
+    let state = reset_state();
+    state.borrow_mut().set_reg(Register::RegVersion, 0x22);
+    let mut lora = LoRaBuilder::new(MockSpi(state.clone()), MockCs(state.clone()), MockReset, 915)
+        .build(&mut delay)
+        .unwrap();
+    let result = lora.start_fhss_transmit(&payload, &[433_000_000], 10, None, &mut delay);
+    assert!(matches!(result, Err(Error::InvalidInput)));
+    lora.start_fhss_transmit(&payload, &[860_500_000, 902_300_000], 10, None, &mut delay)
+        .unwrap();
+    assert_eq!(lora.frequency, 860_500_000);
+}
+
+#[test]
+fn last_packet_rssi_and_current_rssi() {
+    let (mut lora, state) = radio(915);
+    // The packet was received at -100 dBm, the channel is now at -120 dBm
+    state.borrow_mut().set_reg(Register::RegPktRssiValue, 57);
+    state.borrow_mut().set_reg(Register::RegRssiValue, 37);
+    assert_eq!(lora.get_last_packet_rssi().unwrap(), -100);
+    assert_eq!(lora.get_current_rssi().unwrap(), -120);
+    #[allow(deprecated)]
+    let rssi = lora.get_packet_rssi().unwrap();
+    assert_eq!(rssi, -100);
+    lora.set_frequency(433).unwrap();
+    assert_eq!(lora.get_last_packet_rssi().unwrap(), -107);
+}
+
+#[test]
+fn scan_channels_reports_in_input_order() {
+    let (mut lora, state) = radio(868);
+    lora.set_frequency_hz(868_100_000).unwrap();
+    lora.arm_rx().unwrap();
+    {
+        let mut state = state.borrow_mut();
+        // -100, -98 and -90 dBm on the first channel, a steady -120 dBm on the second
+        state.queue_reads(Register::RegRssiValue, &[57, 59, 67, 37, 37, 37]);
+        // A preamble detected on the first channel only
+        state.queue_reads(Register::RegIrqFlags, &[0x05, 0x04]);
+    }
+    let freqs = [868_300_000, 868_500_000, 868_700_000];
+    let mut delay = MockDelay::default();
+    let mut out = [ChannelReport::default(); 2];
+    assert_eq!(lora.scan_channels(&mut delay, &freqs, 3, &mut out).unwrap(), 2);
+    assert_eq!(
+        out,
+        [
+            ChannelReport { frequency_hz: 868_300_000, min_rssi: -100, mean_rssi: -96, max_rssi: -90, lora_activity: true },
+            ChannelReport { frequency_hz: 868_500_000, min_rssi: -120, mean_rssi: -120, max_rssi: -120, lora_activity: false },
+        ]
+    );
+    // Listening again on the original frequency
+    assert_eq!(lora.frequency, 868_100_000);
+    assert_eq!(state.borrow().reg(Register::RegFrfLsb), frf_from_hz(868_100_000, FXOSC_HZ) as u8);
+    assert_eq!(state.borrow().reg(Register::RegOpMode), 0x85);
+    assert!(matches!(lora.scan_channels(&mut delay, &freqs, 0, &mut out), Err(Error::InvalidInput)));
+
+    // Restored as well when a channel can't be tuned
+    assert!(matches!(lora.scan_channels(&mut delay, &[u64::MAX], 3, &mut out), Err(Error::InvalidInput)));
+    assert_eq!(lora.frequency, 868_100_000);
+    assert_eq!(state.borrow().reg(Register::RegOpMode), 0x85);
+
+    // Never while a reported packet waits to be read, restarting the receiver would drop it
+    state.borrow_mut().after(0, packet(b"kept"));
+    assert_eq!(lora.poll_irq(Some(10), &mut delay).unwrap(), 4);
+    state.borrow_mut().writes.clear();
+    assert!(matches!(lora.scan_channels(&mut delay, &freqs, 3, &mut out), Err(Error::Busy)));
+    assert!(state.borrow().writes.is_empty());
+    let mut buffer = [0u8; 8];
+    assert_eq!(lora.read_packet_into(&mut buffer).unwrap(), 4);
+    assert_eq!(&buffer[..4], b"kept");
+
+    // Never while transmitting, and a completed transmission is not sent again
+    state.borrow_mut().after_mode(RadioMode::Tx, 1000, Event::TxDone);
+    lora.transmit_payload(b"data").unwrap();
+    state.borrow_mut().writes.clear();
+    assert!(matches!(lora.scan_channels(&mut delay, &freqs, 3, &mut out), Err(Error::InvalidInput)));
+    assert!(state.borrow().writes.is_empty());
+    let (mut lora, state) = radio(868);
+    let no_activity = |state: &Shared| {
+        for _ in 0..2 {
+            state.borrow_mut().after_mode(RadioMode::Cad, 2, Event::CadDone { detected: false });
+        }
+    };
+    lora.transmit_payload(b"data").unwrap();
+    assert!(matches!(lora.mode, RadioMode::Tx));
+    no_activity(&state);
+    lora.scan_channels(&mut delay, &freqs, 3, &mut out).unwrap();
+    let tx_entries = state.borrow().writes_to(Register::RegOpMode).iter().filter(|&&op_mode| op_mode & 0x07 == 0x03).count();
+    assert_eq!(tx_entries, 1);
+    assert_eq!(state.borrow().reg(Register::RegOpMode) & 0x07, 0x01);
+    // RxSingle restarts
+    lora.set_mode(RadioMode::RxSingle).unwrap();
+    no_activity(&state);
+    lora.scan_channels(&mut delay, &freqs, 3, &mut out).unwrap();
+    assert_eq!(state.borrow().reg(Register::RegOpMode) & 0x07, 0x06);
+}
+
+#[test]
+fn collision_detection() {
+    let (mut lora, state) = radio(915);
+    lora.set_collision_threshold(6);
+    assert!(!lora.collision_detected().unwrap());
+
+    // Synchronized on a packet at -100 dBm, then an interferer 6 dB stronger starts
+    state.borrow_mut().set_reg(Register::RegModemStat, 0x0b);
+    state.borrow_mut().queue_reads(Register::RegRssiValue, &[57, 59, 63]);
+    assert!(!lora.collision_detected().unwrap());
+    assert!(!lora.collision_detected().unwrap());
+    assert!(lora.collision_detected().unwrap());
+
+    // The baseline is taken again for the next packet
+    state.borrow_mut().set_reg(Register::RegModemStat, 0x10);
+    assert!(!lora.collision_detected().unwrap());
+    state.borrow_mut().set_reg(Register::RegModemStat, 0x0b);
+    state.borrow_mut().queue_reads(Register::RegRssiValue, &[63, 64]);
+    assert!(!lora.collision_detected().unwrap());
+    assert!(!lora.collision_detected().unwrap());
+}
+
+#[test]
+fn read_packet_into_slice() {
+    let (mut lora, state) = radio(915);
+    let mut buffer = [0u8; 8];
+    assert!(matches!(lora.read_packet_into(&mut buffer), Err(Error::NoPacket)));
+    assert_eq!(lora.try_read_packet_into(&mut buffer).unwrap(), None);
+    state.borrow_mut().set_reg(Register::RegFifoRxBaseAddr, 0x10);
+    lora.arm_rx().unwrap();
+    state.borrow_mut().after(0, packet(b"data"));
+
+    // A buffer too small keeps the packet for the next read
+    let mut small = [0u8; 3];
+    assert!(matches!(lora.read_packet_into(&mut small), Err(Error::InvalidInput)));
+    assert_eq!(lora.try_read_packet_into(&mut buffer).unwrap(), Some(4));
+    assert_eq!(&buffer[..4], b"data");
+
+    // The packet was consumed
+    assert!(matches!(lora.read_packet(), Err(Error::NoPacket)));
+}
+
+#[cfg(feature = "heapless")]
+#[test]
+fn read_packet_vec_has_packet_length() {
+    let (mut lora, state) = radio(915);
+    state.borrow_mut().set_reg(Register::RegFifoRxBaseAddr, 0x20);
+    lora.arm_rx().unwrap();
+    state.borrow_mut().after(0, packet(b"abc"));
+    assert_eq!(lora.read_packet_vec().unwrap().as_slice(), b"abc");
+}
+
+#[test]
+fn arm_rx_does_not_block() {
+    let (mut lora, state) = radio(915);
+    {
+        let mut state = state.borrow_mut();
+        state.set_reg(Register::RegDioMapping1, 0b01_00_00_10);
+        state.set_reg(Register::RegIrqFlags, 0x48);
+        state.set_reg(Register::RegFifoRxBaseAddr, 0x10);
+    }
+    lora.arm_rx().unwrap();
+    {
+        let state = state.borrow();
+        assert_eq!(state.reg(Register::RegDioMapping1), 0b00_00_00_10);
+        assert_eq!(state.reg(Register::RegIrqFlags), 0x00);
+        assert_eq!(state.reg(Register::RegFifoAddrPtr), 0x10);
+        assert_eq!(state.reg(Register::RegOpMode), 0x85);
+    }
+
+    // Packet signalled on DIO0, read from the interrupt handler
+    state.borrow_mut().after(0, packet(b"hi"));
+    assert_eq!(&lora.read_packet().unwrap()[..2], b"hi");
+    assert_eq!(state.borrow().reg(Register::RegIrqFlags), 0x00);
+}
+
+#[test]
+fn radiohead_send_to_frame_layout() {
+    let (mut lora, state) = radio(915);
+    assert!(matches!(lora.send_to(0x02, b"hi"), Err(Error::InvalidInput)));
+
+    let mut radiohead = RadioHead::new(0x01);
+    radiohead.id = 0x2a;
+    radiohead.flags = 0x80;
+    lora.set_radiohead(Some(radiohead));
+    lora.send_to(0x02, b"hi").unwrap();
+    let state = state.borrow();
+    assert_eq!(state.writes_to(Register::RegFifo), [0x02, 0x01, 0x2a, 0x80, b'h', b'i']);
+    assert_eq!(state.writes_to(Register::RegPayloadLength), [6]);
+    assert_eq!(&state.fifo[..6], [0x02, 0x01, 0x2a, 0x80, b'h', b'i']);
+}
+
+#[test]
+fn radiohead_recv_from_filters_addresses() {
+    let (mut lora, state) = radio(915);
+    lora.set_radiohead(Some(RadioHead::new(0x01)));
+    lora.arm_rx().unwrap();
+    let receive = |state: &Shared, payload: &[u8]| state.borrow_mut().after(0, packet(payload));
+    let mut buffer = [0u8; 8];
+
+    receive(&state, &[0x01, 0x05, 0x07, 0x00, b'o', b'k']);
+    let header = RhHeader { to: 0x01, from: 0x05, id: 0x07, flags: 0x00 };
+    assert_eq!(lora.recv_from(&mut buffer).unwrap(), Some((2, header)));
+    assert_eq!(&buffer[..2], b"ok");
+
+    // Broadcasts are received, packets to other nodes and runts are dropped
+    receive(&state, &[0xff, 0x05, 0x08, 0x00, b'x']);
+    assert_eq!(lora.recv_from(&mut buffer).unwrap().map(|(len, h)| (len, h.to)), Some((1, 0xff)));
+    receive(&state, &[0x03, 0x05, 0x09, 0x00, b'x']);
+    assert_eq!(lora.recv_from(&mut buffer).unwrap(), None);
+    receive(&state, &[0x01, 0x05, 0x0a]);
+    assert_eq!(lora.recv_from(&mut buffer).unwrap(), None);
+
+    lora.radiohead_mut().unwrap().promiscuous = true;
+    receive(&state, &[0x03, 0x05, 0x0b, 0x00, b'x']);
+    assert_eq!(lora.recv_from(&mut buffer).unwrap().map(|(_, h)| h.to), Some(0x03));
+}
+
+#[test]
+fn radiohead_modem_preset() {
+    let (mut lora, state) = radio(915);
+    lora.set_modem_preset(ModemPreset::Bw125Cr45Sf128).unwrap();
+    let state = state.borrow();
+    assert_eq!(state.reg(Register::RegModemConfig1), 0x72);
+    assert_eq!(state.reg(Register::RegModemConfig2) & 0xfc, 0x74);
+    assert_eq!(state.reg(Register::RegPreambleLsb), 8);
+}
+
+#[test]
+fn back_to_back_packets_in_rx_continuous() {
+    let (mut lora, state) = radio(915);
+    state.borrow_mut().set_reg(Register::RegFifoRxBaseAddr, 0x40);
+    lora.arm_rx().unwrap();
+    state.borrow_mut().after(0, packet(b"one"));
+    let mut buffer = [0u8; 8];
+    assert_eq!(lora.read_packet_into(&mut buffer).unwrap(), 3);
+    assert_eq!(&buffer[..3], b"one");
+    assert_eq!(state.borrow().reg(Register::RegFifoAddrPtr), 0x40);
+
+    // The second packet of a rapid pair, the pointer being left after the first one
+    state.borrow_mut().set_reg(Register::RegFifoAddrPtr, 0x10);
+    state.borrow_mut().after(0, packet(b"two!"));
+    assert_eq!(lora.read_packet_into(&mut buffer).unwrap(), 4);
+    assert_eq!(&buffer[..4], b"two!");
+    assert_eq!(state.borrow().reg(Register::RegFifoAddrPtr), 0x40);
+}
+
+#[test]
+fn latched_packet_keeps_the_next_rx_done() {
+    let (mut lora, state) = radio(915);
+    let mut delay = MockDelay::default();
+    let mut buffer = [0u8; 8];
+    state.borrow_mut().after_mode(RadioMode::RxContinuous, 2, packet(b"one"));
+    assert_eq!(lora.poll_irq(Some(100), &mut delay).unwrap(), 3);
+    // The second packet arrives before the first one is read
+    state.borrow_mut().after(0, packet(b"two!"));
+    assert_eq!(lora.read_packet_into(&mut buffer).unwrap(), 3);
+    assert_eq!(&buffer[..3], b"one");
+    // Only the flags of the first packet were cleared, when it was latched
+    assert_eq!(state.borrow().writes_to(Register::RegIrqFlags).last(), Some(&0x50));
+    assert_ne!(state.borrow().reg(Register::RegIrqFlags) & 0x40, 0);
+    assert_eq!(lora.read_packet_into(&mut buffer).unwrap(), 4);
+    assert_eq!(&buffer[..4], b"two!");
+    assert!(matches!(lora.read_packet_into(&mut buffer), Err(Error::NoPacket)));
+}
+
+#[test]
+fn arduino_lora_hello_packet() {
+    let (mut lora, state) = radio(915);
+    // Only the arduino-LoRa preset sets the sync word
+    lora.set_sync_word(0x34).unwrap();
+    lora.set_modem_preset(ModemPreset::Bw125Cr45Sf128).unwrap();
+    assert_eq!(state.borrow().reg(Register::RegSyncWord), 0x34);
+    lora.set_modem_preset(ModemPreset::ArduinoLoRa).unwrap();
+    {
+        let state = state.borrow();
+        assert_eq!(state.reg(Register::RegModemConfig1), 0x72);
+        assert_eq!(state.reg(Register::RegModemConfig2) & 0xfc, 0x74);
+        assert_eq!(state.reg(Register::RegSyncWord), 0x12);
+        assert_eq!(state.reg(Register::RegPreambleMsb), 0);
+        assert_eq!(state.reg(Register::RegPreambleLsb), 8);
+    }
+
+    // The SPI frames loading the FIFO, as arduino-LoRa's beginPacket/print/endPacket produce them
+    // but for the zero payload length written by beginPacket
+    state.borrow_mut().writes.clear();
+    lora.transmit_payload(b"hello").unwrap();
+    let fifo_frames: Vec<[u8; 2]> = state
+        .borrow()
+        .writes
+        .iter()
+        .filter(|(addr, _)| [0x00, 0x0d, 0x22].contains(addr))
+        .map(|&(addr, value)| [addr | 0x80, value])
+        .collect();
+    assert_eq!(
+        fifo_frames,
+        [[0x8d, 0x00], [0x80, b'h'], [0x80, b'e'], [0x80, b'l'], [0x80, b'l'], [0x80, b'o'], [0xa2, 0x05]]
+    );
+    assert_eq!(&state.borrow().fifo[..5], b"hello");
+
+    // A "hello" packet sent by arduino-LoRa
+    lora.arm_rx().unwrap();
+    state.borrow_mut().after(0, packet(b"hello"));
+    let mut buffer = [0u8; 255];
+    assert_eq!(lora.read_packet_into(&mut buffer).unwrap(), 5);
+    assert_eq!(&buffer[..5], b"hello");
+}
+
+#[test]
+fn lorawan_uplink_and_rx2() {
+    let (mut lora, state) = radio(868);
+    lora.configure_lorawan_uplink(868_100_000, 7, 125_000).unwrap();
+    {
+        let state = state.borrow();
+        assert_eq!(
+            [state.reg(Register::RegFrfMsb), state.reg(Register::RegFrfMid), state.reg(Register::RegFrfLsb)],
+            [0xd9, 0x06, 0x66]
+        );
+        assert_eq!(state.reg(Register::RegModemConfig1), 0x72);
+        assert_eq!(state.reg(Register::RegModemConfig2) & 0xfc, 0x74);
+        assert_eq!(state.reg(Register::RegModemConfig3) & 0x08, 0x00);
+        assert_eq!(state.reg(Register::RegSyncWord), 0x34);
+        assert_eq!(state.reg(Register::RegPreambleLsb), 8);
+        assert_eq!(state.reg(Register::RegInvertiq), 0x27);
+        assert_eq!(state.reg(Register::RegInvertiq2), 0x1d);
+    }
+
+    // EU868 RX2: 869.525 MHz, SF12, 125 kHz
+    lora.configure_lorawan_rx_window(869_525_000, 12, 125_000).unwrap();
+    {
+        let state = state.borrow();
+        assert_eq!(
+            [state.reg(Register::RegFrfMsb), state.reg(Register::RegFrfMid), state.reg(Register::RegFrfLsb)],
+            [0xd9, 0x61, 0x99]
+        );
+        assert_eq!(state.reg(Register::RegModemConfig1), 0x72);
+        assert_eq!(state.reg(Register::RegModemConfig2) & 0xfc, 0xc0);
+        // Low data rate optimization, the symbols lasting 32 ms
+        assert_eq!(state.reg(Register::RegModemConfig3) & 0x08, 0x08);
+        assert_eq!(state.reg(Register::RegSyncWord), 0x34);
+        assert_eq!(state.reg(Register::RegPreambleLsb), 8);
+        assert_eq!(state.reg(Register::RegInvertiq), 0x66);
+        assert_eq!(state.reg(Register::RegInvertiq2), 0x19);
+    }
+
+    assert!(matches!(lora.configure_lorawan_uplink(868_100_000, 6, 125_000), Err(Error::InvalidInput)));
+    assert!(matches!(lora.configure_lorawan_rx_window(868_100_000, 7, 62_500), Err(Error::InvalidInput)));
+}
+
+#[test]
+fn invert_iq_per_direction() {
+    let (mut lora, state) = radio(868);
+    lora.set_invert_iq_rx(true).unwrap();
+    assert_eq!(state.borrow().reg(Register::RegInvertiq), 0x67);
+    assert_eq!(state.borrow().reg(Register::RegInvertiq2), 0x19);
+    assert!(lora.get_invert_iq_rx().unwrap());
+    assert!(!lora.get_invert_iq_tx().unwrap());
+
+    lora.set_invert_iq_tx(true).unwrap();
+    assert_eq!(state.borrow().reg(Register::RegInvertiq), 0x66);
+    lora.set_invert_iq_rx(false).unwrap();
+    assert_eq!(state.borrow().reg(Register::RegInvertiq), 0x26);
+    assert_eq!(state.borrow().reg(Register::RegInvertiq2), 0x1d);
+    assert!(!lora.get_invert_iq_rx().unwrap());
+    assert!(lora.get_invert_iq_tx().unwrap());
+    lora.set_invert_iq_tx(false).unwrap();
+    assert_eq!(state.borrow().reg(Register::RegInvertiq), 0x27);
+}
+
+#[test]
+fn invert_iq_keeps_reserved_bits() {
+    let (mut lora, state) = radio(868);
+    // Reserved bits 5-1 other than their reset value
+    state.borrow_mut().set_reg(Register::RegInvertiq, 0x2d);
+    lora.set_invert_iq(true).unwrap();
+    assert_eq!(state.borrow().reg(Register::RegInvertiq), 0x6c);
+    assert_eq!(state.borrow().reg(Register::RegInvertiq2), 0x19);
+    lora.set_invert_iq(false).unwrap();
+    assert_eq!(state.borrow().reg(Register::RegInvertiq), 0x2d);
+    assert_eq!(state.borrow().reg(Register::RegInvertiq2), 0x1d);
+    lora.set_invert_iq_rx(true).unwrap();
+    lora.set_invert_iq_tx(true).unwrap();
+    assert_eq!(state.borrow().reg(Register::RegInvertiq), 0x6c);
+}
+
+#[test]
+fn continuous_listen_interrupted_for_tx() {
+    let (mut lora, state) = radio(868);
+    let rx = ChannelConfig {
+        frequency_hz: 869_525_000,
+        spreading_factor: 12,
+        bandwidth: 125_000,
+        crc: false,
+        invert_iq: true,
+    };
+    let tx = ChannelConfig {
+        frequency_hz: 868_100_000,
+        spreading_factor: 7,
+        crc: true,
+        invert_iq: false,
+        ..rx
+    };
+    let mut delay = MockDelay::default();
+    assert!(matches!(lora.interrupt_for_tx(b"up", &mut delay), Err(Error::InvalidInput)));
+    lora.continuous_listen(ListenConfig { rx, tx }).unwrap();
+    let listening = |state: &crate::mock::Shared| {
+        let state = state.borrow();
+        assert_eq!(state.reg(Register::RegOpMode), 0x85);
+        assert_eq!(state.reg(Register::RegFrfMid), 0x61);
+        assert_eq!(state.reg(Register::RegModemConfig2) & 0xf4, 0xc0);
+        assert_eq!(state.reg(Register::RegInvertiq), 0x66);
+        assert_eq!(state.reg(Register::RegIrqFlags), 0x00);
+    };
+    listening(&state);
+
+    // A packet being received is not interrupted
+    state.borrow_mut().set_reg(Register::RegModemStat, 0x11);
+    assert!(matches!(lora.interrupt_for_tx(b"up", &mut delay), Err(Error::Busy)));
+    assert_eq!(state.borrow().reg(Register::RegOpMode), 0x85);
+    state.borrow_mut().set_reg(Register::RegModemStat, 0x10);
+
+    // With the modem clear guard, the packet is waited for instead
+    lora.set_modem_clear_guard(Some(10));
+    state.borrow_mut().queue_reads(Register::RegModemStat, &[0x0b, 0x10]);
+    let mut guarded = MockDelay::default();
+    lora.interrupt_for_tx(b"up", &mut guarded).unwrap();
+    assert_eq!(guarded.elapsed_ms, 1);
+    lora.set_modem_clear_guard(None);
+    listening(&state);
+
+    state.borrow_mut().writes.clear();
+    lora.interrupt_for_tx(b"up", &mut delay).unwrap();
+    {
+        let state = state.borrow();
+        // Sent at 868.1 MHz, SF7, IQ not inverted, then back to RX2
+        assert_eq!(state.writes_to(Register::RegFrfMid), [0x06, 0x61]);
+        assert_eq!(state.writes_to(Register::RegInvertiq), [0x27, 0x66]);
+        assert_eq!(state.writes_to(Register::RegFifo), b"up");
+        // TxDone is cleared after the transmission and before reception starts again
+        let position = |reg: Register, value: u8| {
+            state.writes.iter().rposition(|&write| write == (reg as u8, value)).unwrap()
+        };
+        let tx_at = position(Register::RegOpMode, 0x83);
+        let cleared_at = position(Register::RegIrqFlags, 0xff);
+        let rx_at = position(Register::RegOpMode, 0x85);
+        assert!(tx_at < cleared_at && cleared_at < rx_at);
+    }
+    listening(&state);
+
+    // A missing TxDone times out after twice the time on air at SF7, and listening resumes
+    state.borrow_mut().after_mode(RadioMode::Tx, 100_000, Event::TxDone);
+    let mut delay = MockDelay::default();
+    assert!(matches!(lora.interrupt_for_tx(b"up", &mut delay), Err(Error::Timeout)));
+    assert!(delay.elapsed_ms < 100);
+    listening(&state);
+
+    // The transmission error is kept when resuming fails too, listening being attempted anyway
+    state.borrow_mut().fail_next_write = Some((Register::RegIrqFlags, 0xff));
+    assert!(matches!(lora.interrupt_for_tx(b"", &mut delay), Err(Error::InvalidInput)));
+    assert!(state.borrow().fail_next_write.is_none());
+    assert_eq!(state.borrow().reg(Register::RegOpMode), 0x85);
+    assert_eq!(state.borrow().reg(Register::RegFrfMid), 0x61);
+}
+
+#[test]
+fn fsk_rx_config() {
+    let (mut lora, state) = radio(915);
+    let restart = FskRxRestart { after_packet: FskAutoRestart::On, on_collision: true };
+    // In LoRa mode 0x0d is RegFifoAddrPtr
+    assert!(matches!(lora.set_fsk_rx_config(true, true, restart), Err(Error::InvalidInput)));
+    assert!(state.borrow().writes.is_empty());
+
+    {
+        let mut state = state.borrow_mut();
+        state.set_reg(Register::RegOpMode, 0x01);
+        state.registers[FskRegister::RegRxConfig as usize] = 0x0e;
+        state.registers[FskRegister::RegSyncConfig as usize] = 0x93;
+    }
+    lora.set_fsk_rx_config(true, true, restart).unwrap();
+    assert_eq!(state.borrow().registers[FskRegister::RegRxConfig as usize], 0x9e);
+    assert_eq!(state.borrow().registers[FskRegister::RegSyncConfig as usize], 0x53);
+
+    let restart = FskRxRestart { after_packet: FskAutoRestart::Off, on_collision: false };
+    lora.set_fsk_rx_config(false, false, restart).unwrap();
+    assert_eq!(state.borrow().registers[FskRegister::RegRxConfig as usize], 0x06);
+    assert_eq!(state.borrow().registers[FskRegister::RegSyncConfig as usize], 0x13);
+}
+
+#[test]
+fn fsk_data_mode() {
+    let (mut lora, state) = radio(915);
+    // In LoRa mode 0x31 is RegDetectionOptimize
+    assert!(matches!(lora.set_fsk_data_mode(FskDataMode::Continuous), Err(Error::InvalidInput)));
+    assert!(state.borrow().writes.is_empty());
+
+    {
+        let mut state = state.borrow_mut();
+        state.set_reg(Register::RegOpMode, 0x01);
+        state.registers[FskRegister::RegPacketConfig2 as usize] = 0x40;
+    }
+    assert_eq!(lora.get_fsk_data_mode().unwrap(), FskDataMode::Packet);
+    lora.set_fsk_data_mode(FskDataMode::Continuous).unwrap();
+    assert_eq!(state.borrow().registers[FskRegister::RegPacketConfig2 as usize], 0x00);
+    assert_eq!(lora.get_fsk_data_mode().unwrap(), FskDataMode::Continuous);
+    // PayloadLength bits 2-0 are left alone
+    state.borrow_mut().registers[FskRegister::RegPacketConfig2 as usize] = 0x03;
+    lora.set_fsk_data_mode(FskDataMode::Packet).unwrap();
+    assert_eq!(state.borrow().registers[FskRegister::RegPacketConfig2 as usize], 0x43);
+}
+
+#[test]
+fn fsk_afc() {
+    let (mut lora, state) = radio(915);
+    assert!(matches!(lora.set_fsk_afc(true, true), Err(Error::InvalidInput)));
+    assert!(matches!(lora.get_afc_value(), Err(Error::InvalidInput)));
+    assert!(state.borrow().writes.is_empty());
+
+    {
+        let mut state = state.borrow_mut();
+        state.set_reg(Register::RegOpMode, 0x01);
+        state.registers[FskRegister::RegRxConfig as usize] = 0x8e;
+        state.registers[FskRegister::RegAfcFei as usize] = 0x12;
+    }
+    lora.set_fsk_afc(true, true).unwrap();
+    assert_eq!(state.borrow().registers[FskRegister::RegRxConfig as usize], 0x9e);
+    assert_eq!(state.borrow().registers[FskRegister::RegAfcFei as usize], 0x01);
+    lora.set_fsk_afc(false, false).unwrap();
+    assert_eq!(state.borrow().registers[FskRegister::RegRxConfig as usize], 0x8e);
+    assert_eq!(state.borrow().registers[FskRegister::RegAfcFei as usize], 0x00);
+
+    // 100 steps of 61.03 Hz either way
+    for (afc, hz) in [(100i16, 6_103), (-100, -6_103), (0, 0)] {
+        let [msb, lsb] = afc.to_be_bytes();
+        state.borrow_mut().registers[FskRegister::RegAfcMsb as usize] = msb;
+        state.borrow_mut().registers[FskRegister::RegAfcLsb as usize] = lsb;
+        assert_eq!(lora.get_afc_value().unwrap(), hz);
+    }
+}
+
+fn link_sample(rssi: i32, snr: f64, crc_error: bool) -> LinkSample {
+    LinkSample { rssi, snr, frequency_error: 0, timestamp_ms: None, crc_error }
+}
+
+#[test]
+fn read_packet_with_frame_info() {
+    let (mut lora, state) = radio(915);
+    {
+        let mut state = state.borrow_mut();
+        state.set_reg(Register::RegIrqFlags, 0x60);
+        state.set_reg(Register::RegRxNbBytes, 3);
+        // Coding rate 4/6 in the header, a preamble already being detected
+        state.set_reg(Register::RegModemStat, 0x41);
+        state.set_reg(Register::RegHopChannel, 0x40);
+        state.fifo[..3].copy_from_slice(b"xyz");
+    }
+    let mut buffer = [0; 8];
+    let (size, info) = lora.read_packet_with_frame_info(&mut buffer).unwrap();
+    assert_eq!(&buffer[..size], b"xyz");
+    assert_eq!(
+        info,
+        ReceivedFrameInfo {
+            explicit_header: true,
+            header: Some(RxHeaderInfo {
+                coding_rate: 6,
+                crc_on_payload: true,
+                payload_length: 3,
+            }),
+            preamble_length: 8,
+            crc_error: true,
+            signal_detected: true,
+            signal_synchronized: false,
+        }
+    );
+
+    lora.explicit_header = false;
+    state.borrow_mut().set_reg(Register::RegIrqFlags, 0x40);
+    let (_, info) = lora.read_packet_with_frame_info(&mut buffer).unwrap();
+    assert!(!info.explicit_header);
+    assert_eq!(info.header, None);
+    assert!(!info.crc_error);
+}
+
+#[test]
+fn pause_and_resume_rx() {
+    let (mut lora, state) = radio(915);
+    assert!(matches!(lora.resume_rx(), Err(Error::InvalidInput)));
+    lora.pause_rx().unwrap();
+    assert!(state.borrow().writes.is_empty());
+
+    lora.arm_rx().unwrap();
+    state.borrow_mut().writes.clear();
+    lora.pause_rx().unwrap();
+    assert_eq!(state.borrow().writes, [(Register::RegOpMode as u8, 0x81)]);
+
+    // Transmitting in the slot doesn't change the mode resumed
+    lora.transmit_payload(b"slot").unwrap();
+    state.borrow_mut().writes.clear();
+    lora.resume_rx().unwrap();
+    assert_eq!(state.borrow().writes, [(Register::RegOpMode as u8, 0x85)]);
+    assert!(matches!(lora.resume_rx(), Err(Error::InvalidInput)));
+
+    // Not while still transmitting, the paused mode being kept
+    lora.pause_rx().unwrap();
+    state.borrow_mut().after_mode(RadioMode::Tx, 10, Event::TxDone);
+    lora.transmit_payload(b"slot").unwrap();
+    state.borrow_mut().writes.clear();
+    assert!(matches!(lora.resume_rx(), Err(Error::InvalidInput)));
+    assert!(state.borrow().writes.is_empty());
+    assert_eq!(state.borrow().reg(Register::RegOpMode) & 0x07, 0x03);
+    lora.wait_for_tx_done(Some(100), &mut MockDelay::default()).unwrap();
+    lora.resume_rx().unwrap();
+    assert_eq!(state.borrow().reg(Register::RegOpMode) & 0x07, 0x05);
+}
+
+#[test]
+fn take_rx_counters_resets_by_cycling_rx() {
+    let (mut lora, state) = radio(915);
+    for (reg, value) in [
+        (Register::RegRxHeaderCntValueMsb, 0x01),
+        (Register::RegRxHeaderCntValueLsb, 0x02),
+        (Register::RegRxPacketCntValueMsb, 0x00),
+        (Register::RegRxPacketCntValueLsb, 0xfe),
+    ] {
+        state.borrow_mut().set_reg(reg, value);
+    }
+    assert_eq!(lora.take_rx_counters().unwrap(), (0x0102, 0xfe));
+    assert!(state.borrow().writes.is_empty());
+
+    lora.arm_rx().unwrap();
+    state.borrow_mut().writes.clear();
+    assert_eq!(lora.take_rx_counters().unwrap(), (0x0102, 0xfe));
+    assert_eq!(
+        state.borrow().writes,
+        [(Register::RegOpMode as u8, 0x81), (Register::RegOpMode as u8, 0x85)]
+    );
+
+    // Not while a reported packet waits to be read
+    state.borrow_mut().after(0, packet(b"kept"));
+    assert_eq!(lora.poll_irq(Some(10), &mut MockDelay::default()).unwrap(), 4);
+    state.borrow_mut().writes.clear();
+    assert!(matches!(lora.take_rx_counters(), Err(Error::Busy)));
+    assert!(state.borrow().writes.is_empty());
+    let mut buffer = [0u8; 8];
+    assert_eq!(lora.read_packet_into(&mut buffer).unwrap(), 4);
+    assert_eq!(&buffer[..4], b"kept");
+    lora.take_rx_counters().unwrap();
+}
+
+#[test]
+fn rx_restart_after_crc_errors() {
+    let (mut lora, state) = radio(915);
+    lora.set_rx_restart_policy(Some(RxRestartPolicy { crc_errors: 3 }));
+    lora.arm_rx().unwrap();
+    state.borrow_mut().set_reg(Register::RegFifoRxBaseAddr, 0x10);
+    state.borrow_mut().set_reg(Register::RegRxNbBytes, 4);
+    let mut buffer = [0; 16];
+    let mut receive = |irq_flags| {
+        state.borrow_mut().writes.clear();
+        state.borrow_mut().set_reg(Register::RegIrqFlags, irq_flags);
+        lora.read_packet_into(&mut buffer).unwrap();
+        lora.rx_restarts()
+    };
+
+    // A valid packet in between starts the count again
+    assert_eq!(receive(0x60), 0);
+    assert_eq!(receive(0x60), 0);
+    assert_eq!(receive(0x40), 0);
+    assert_eq!(receive(0x60), 0);
+    assert_eq!(receive(0x60), 0);
+    assert_eq!(receive(0x60), 1);
+    let writes: Vec<(u8, u8)> = state
+        .borrow()
+        .writes
+        .iter()
+        .copied()
+        .filter(|&(addr, _)| {
+            [Register::RegOpMode, Register::RegIrqFlags, Register::RegFifoAddrPtr]
+                .iter()
+                .any(|&reg| reg as u8 == addr)
+        })
+        .collect();
+    assert_eq!(
+        writes,
+        [
+            (Register::RegIrqFlags as u8, 0x60),
+            (Register::RegOpMode as u8, 0x81),
+            (Register::RegIrqFlags as u8, 0xff),
+            (Register::RegFifoAddrPtr as u8, 0x10),
+            (Register::RegOpMode as u8, 0x85),
+            // Reading the packet of the last error
+            (Register::RegFifoAddrPtr as u8, 0x00),
+            (Register::RegFifoAddrPtr as u8, 0x10),
+        ]
+    );
+    assert_eq!(receive(0x60), 1);
+}
+
+#[test]
+fn link_stats_wrap_around() {
+    let mut stats = LinkStats::<3>::new();
+    assert!(stats.is_empty());
+    assert_eq!(stats.mean_rssi(), None);
+    assert_eq!(stats.min_snr(), None);
+
+    for (rssi, snr, crc_error) in [(-100, 5.0, false), (-90, -2.5, true), (-110, 7.5, false)] {
+        stats.push(link_sample(rssi, snr, crc_error));
+    }
+    assert_eq!(stats.len(), 3);
+    assert_eq!(stats.min_rssi(), Some(-110));
+    assert_eq!(stats.max_rssi(), Some(-90));
+    assert_eq!(stats.mean_rssi(), Some(-100.0));
+    assert_eq!(stats.min_snr(), Some(-2.5));
+    assert_eq!(stats.max_snr(), Some(7.5));
+    assert_eq!(stats.mean_snr(), Some(10.0 / 3.0));
+    assert_eq!(stats.crc_errors(), 1);
+
+    // The oldest samples are replaced, the CRC error leaving the window last
+    stats.push(link_sample(-80, 1.0, false));
+    stats.push(link_sample(-70, 2.0, false));
+    assert_eq!(stats.len(), 3);
+    let rssi: Vec<i32> = stats.iter().map(|sample| sample.rssi).collect();
+    assert_eq!(rssi, [-110, -80, -70]);
+    assert_eq!(stats.latest().map(|sample| sample.rssi), Some(-70));
+    assert_eq!(stats.mean_rssi(), Some(-260.0 / 3.0));
+    assert_eq!(stats.mean_snr(), Some(3.5));
+    assert_eq!(stats.crc_errors(), 0);
+
+    stats.clear();
+    assert_eq!(stats.iter().count(), 0);
+}
+
+#[test]
+fn record_link_stats_from_last_packet() {
+    let (mut lora, state) = radio(915);
+    {
+        let mut state = state.borrow_mut();
+        state.set_reg(Register::RegPktRssiValue, 60);
+        state.set_reg(Register::RegPktSnrValue, 0x28);
+        state.set_reg(Register::RegIrqFlags, 0x60);
+        state.set_reg(Register::RegRxNbBytes, 1);
+    }
+    lora.read_packet().unwrap();
+    let mut stats = LinkStats::<4>::new();
+    let mut clock = FakeClock { now_ms: 500, ..Default::default() };
+    lora.record_link_stats(&mut stats, Some(&mut clock)).unwrap();
+    let sample = stats.latest().unwrap();
+    assert_eq!(sample.rssi, -97);
+    assert_eq!(sample.snr, 10.0);
+    assert_eq!(sample.timestamp_ms, Some(500));
+    assert!(sample.crc_error);
+}
+
+#[test]
+fn pa_dac_high_power() {
+    let (mut lora, state) = radio(915);
+    state.borrow_mut().set_reg(Register::RegPaConfig, 0x8f);
+    state.borrow_mut().set_reg(Register::RegOcp, 0x31);
+    assert!(!lora.get_pa_dac_high_power().unwrap());
+    lora.set_pa_dac_high_power(true).unwrap();
+    assert_eq!(state.borrow().reg(Register::RegPaDac), 0x87);
+    assert!(lora.get_pa_dac_high_power().unwrap());
+    lora.set_pa_dac_high_power(false).unwrap();
+    assert_eq!(state.borrow().reg(Register::RegPaDac), 0x84);
+    // Only RegPaDac is written
+    assert_eq!(state.borrow().writes.len(), 2);
+}
+
+#[test]
+fn debug_prints_cached_settings() {
+    let (mut lora, state) = radio(915);
+    lora.set_radiohead(Some(RadioHead::new(0x01)));
+    let writes_before = state.borrow().writes.len();
+    assert_eq!(
+        std::format!("{:?}", lora),
+        "LoRa (cached, may be stale) { chip: Sx1276, frequency_hz: 915000000, mode: Stdby, \
+         explicit_header: true, pa_output: PaBoost, crystal_hz: 32000000, poll_interval_ms: 1, header_timeout_ms: None, \
+         max_payload_length: 255, dwell_time_limit_ms: None, modem_clear_timeout_ms: None, \
+         collision_threshold_db: 10, radiohead: Some(RadioHead { address: 1, id: 0, flags: 0, \
+         promiscuous: false }), listen: None, tx_watchdog: None, afc_every_n_packets: 0 }"
+    );
+    assert_eq!(state.borrow().writes.len(), writes_before);
+}
+
+#[test]
+fn dump_registers_skips_the_fifo() {
+    let (mut lora, state) = radio(915);
+    {
+        let mut state = state.borrow_mut();
+        for addr in 0..0x46 {
+            state.registers[addr] = addr as u8;
+        }
+        state.set_reg(Register::RegFifoAddrPtr, 0x20);
+        state.fifo[0x20] = 0xaa;
+    }
+    let mut dump = [0xffu8; crate::dump::REGISTER_DUMP_LEN];
+    lora.dump_registers(&mut dump).unwrap();
+    assert_eq!(dump[0], 0);
+    for (addr, &value) in dump.iter().enumerate().skip(1) {
+        let expected = if addr == Register::RegFifoAddrPtr as usize { 0x20 } else { addr as u8 };
+        assert_eq!(value, expected);
+    }
+    // Nothing was written and the FIFO pointer didn't move
+    assert!(state.borrow().writes.is_empty());
+    assert_eq!(state.borrow().reg(Register::RegFifoAddrPtr), 0x20);
+
+    let mut text = std::string::String::new();
+    crate::dump::write_register_dump(&mut text, &dump).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 5);
+    assert_eq!(lines[0], "00: -- 01 02 03 04 05 06 07 08 09 0a 0b 0c 20 0e 0f");
+    assert_eq!(lines[4], "40: 40 41 42 43 44 45");
+}
+
+#[test]
+fn band_and_low_frequency_mode() {
+    let (mut lora, state) = radio(433);
+    assert_eq!(lora.band(), Band::Lf);
+    assert_eq!(lora.rssi_offset(), -164);
+    assert_eq!(state.borrow().reg(Register::RegOpMode), 0x89);
+    assert!(lora.is_low_frequency_mode().unwrap());
+
+    lora.set_frequency(868).unwrap();
+    assert_eq!(lora.band(), Band::Hf);
+    lora.set_mode(RadioMode::Stdby).unwrap();
+    assert!(!lora.is_low_frequency_mode().unwrap());
+
+    assert_eq!(Band::from_hz(525_000_000), Band::Lf);
+    assert_eq!(Band::from_hz(525_000_001), Band::Hf);
+}
+
+#[test]
+fn verify_and_repair_config() {
+    let (mut lora, state) = radio(915);
+    lora.set_sync_word(0x34).unwrap();
+    assert!(lora.verify_config().is_ok());
+
+    // A bit of ModemConfig1 flipped, the bandwidth reading 62.5 kHz
+    state.borrow_mut().set_reg(Register::RegModemConfig1, 0x62);
+    let changed = crate::verify::RegisterMismatch { register: 0x1d, expected: 0x72, actual: 0x62 };
+    assert!(matches!(lora.verify_config(), Err(Error::ConfigMismatch(mismatch)) if mismatch == changed));
+    // Every changed register is listed, in register order
+    state.borrow_mut().set_reg(Register::RegSyncWord, 0x12);
+    let mismatches: Vec<_> = lora.find_config_mismatches().unwrap().iter().copied().collect();
+    assert_eq!(
+        mismatches,
+        [changed, crate::verify::RegisterMismatch { register: 0x39, expected: 0x34, actual: 0x12 }]
+    );
+    assert!(matches!(lora.verify_config(), Err(Error::ConfigMismatch(mismatch)) if mismatch == changed));
+
+    state.borrow_mut().writes.clear();
+    assert_eq!(lora.repair_config().unwrap(), 2);
+    assert_eq!(state.borrow().writes, [(0x1d, 0x72), (0x39, 0x34)]);
+    assert!(lora.verify_config().is_ok());
+    assert!(lora.find_config_mismatches().unwrap().is_empty());
+    // The error stays small whatever the number of configuration registers, DwellTimeExceeded
+    // being the largest variant
+    assert_eq!(core::mem::size_of::<Error<MockError, MockError, MockError>>(), 12);
+    assert_eq!(lora.repair_config().unwrap(), 0);
+}
+
+/// A setter call of `setters_keep_invariants`, with arguments in the supported range.
+#[derive(Clone, Debug)]
+enum Setter {
+    Bandwidth(i64),
+    SpreadingFactor(u8),
+    CodingRate(u8),
+    Crc(bool),
+    ExplicitHeader(bool),
+    SymbolTimeout(u16),
+    PreambleLength(u16),
+    Ocp(u8),
+    PaMaxPower(u8),
+    TxPower(u8),
+    TxPowerDbm(PaOutput, i8),
+    RxProfile(RxProfile),
+}
+
+const BANDWIDTHS: [i64; 10] = [7_800, 10_400, 15_600, 20_800, 31_250, 41_700, 62_500, 125_000, 250_000, 500_000];
+
+impl Arbitrary for Setter {
+    fn arbitrary(g: &mut Gen) -> Self {
+        match u8::arbitrary(g) % 12 {
+            0 => Setter::Bandwidth(*g.choose(&BANDWIDTHS).unwrap()),
+            1 => Setter::SpreadingFactor(6 + u8::arbitrary(g) % 7),
+            2 => Setter::CodingRate(5 + u8::arbitrary(g) % 4),
+            3 => Setter::Crc(bool::arbitrary(g)),
+            4 => Setter::ExplicitHeader(bool::arbitrary(g)),
+            5 => Setter::SymbolTimeout(4 + u16::arbitrary(g) % 1020),
+            6 => Setter::PreambleLength(6 + u16::arbitrary(g) % 65530),
+            7 => Setter::Ocp(u8::arbitrary(g)),
+            8 => Setter::PaMaxPower(u8::arbitrary(g) % 8),
+            9 => Setter::TxPower(u8::arbitrary(g)),
+            10 => match bool::arbitrary(g) {
+                true => Setter::TxPowerDbm(PaOutput::PaBoost, 2 + (u8::arbitrary(g) % 19) as i8),
+                false => Setter::TxPowerDbm(PaOutput::Rfo, -4 + (u8::arbitrary(g) % 19) as i8),
+            },
+            _ => Setter::RxProfile(*g.choose(&[RxProfile::MaxSensitivity, RxProfile::LowPower]).unwrap()),
+        }
+    }
+}
+
+/// The settings the register model must decode to after a sequence of setter calls.
+#[derive(Debug)]
+struct ExpectedSettings {
+    bandwidth: i64,
+    spreading_factor: u8,
+    coding_rate: u8,
+    crc: bool,
+    explicit_header: bool,
+    symbol_timeout: u16,
+    preamble_length: u16,
+    /// `None` on the SX1272, which has no MaxPower field.
+    max_power: Option<u8>,
+    agc_auto: bool,
+}
+
+/// Registers with reserved or unused bits, and the mask of these bits. RegModemConfig3 does not
+/// exist on the SX1272.
+fn reserved_bits(chip: Chip) -> &'static [(Register, u8)] {
+    match chip {
+        Chip::Sx1276 => &[(Register::RegOcp, 0xc0), (Register::RegLna, 0x04), (Register::RegModemConfig3, 0xf3)],
+        Chip::Sx1272 => &[(Register::RegOcp, 0xc0), (Register::RegLna, 0x04)],
+    }
+}
+
+fn apply_setter(lora: &mut MockLoRa, expected: &mut ExpectedSettings, setter: &Setter) {
+    match *setter {
+        Setter::Bandwidth(bandwidth) => {
+            if lora.chip() == Chip::Sx1272 && bandwidth < 125_000 {
+                assert!(matches!(lora.set_signal_bandwidth(bandwidth), Err(Error::InvalidInput)));
+                return;
+            }
+            lora.set_signal_bandwidth(bandwidth).unwrap();
+            expected.bandwidth = bandwidth;
+        }
+        Setter::SpreadingFactor(sf) => {
+            lora.set_spreading_factor(sf).unwrap();
+            expected.spreading_factor = sf;
+        }
+        Setter::CodingRate(cr) => {
+            lora.set_coding_rate_4(cr).unwrap();
+            expected.coding_rate = cr;
+        }
+        Setter::Crc(on) => {
+            lora.set_crc(on).unwrap();
+            expected.crc = on;
+        }
+        Setter::ExplicitHeader(explicit) => {
+            lora.explicit_header = explicit;
+            lora.set_mode(RadioMode::Stdby).unwrap();
+            expected.explicit_header = explicit;
+        }
+        Setter::SymbolTimeout(symbols) => {
+            lora.set_symbol_timeout(symbols).unwrap();
+            expected.symbol_timeout = symbols;
+        }
+        Setter::PreambleLength(length) => {
+            lora.set_preamble_length(i64::from(length)).unwrap();
+            expected.preamble_length = length;
+        }
+        Setter::Ocp(ma) => {
+            if ma < 140 && lora.get_pa_dac().unwrap() == PaDac::HighPower {
+                assert!(matches!(lora.set_ocp(ma), Err(Error::HighPowerConflict(PowerSetting::Ocp))));
+            } else {
+                lora.set_ocp(ma).unwrap();
+            }
+        }
+        Setter::PaMaxPower(max_power) => {
+            if lora.chip() == Chip::Sx1272 {
+                assert!(matches!(lora.set_pa_max_power(max_power), Err(Error::InvalidInput)));
+                return;
+            }
+            lora.set_pa_max_power(max_power).unwrap();
+            expected.max_power = Some(max_power);
+        }
+        Setter::TxPower(level) => lora.set_tx_power(level).unwrap(),
+        Setter::TxPowerDbm(pa, dbm) => {
+            let selection = match pa {
+                PaOutput::Rfo => PaSelection::Rfo,
+                PaOutput::PaBoost => PaSelection::PaBoost,
+            };
+            if lora.chip() == Chip::Sx1272 {
+                if pa == PaOutput::Rfo && dbm < -1 {
+                    assert!(matches!(lora.set_tx_power_dbm(dbm, selection), Err(Error::InvalidInput)));
+                } else {
+                    lora.set_tx_power_dbm(dbm, selection).unwrap();
+                }
+                return;
+            }
+            lora.set_tx_power_dbm(dbm, selection).unwrap();
+            match (pa, dbm) {
+                (PaOutput::Rfo, -4..=-1) => expected.max_power = Some(0),
+                (PaOutput::Rfo, _) => expected.max_power = Some(7),
+                (PaOutput::PaBoost, _) => {}
+            }
+        }
+        Setter::RxProfile(profile) => {
+            lora.set_rx_profile(profile).unwrap();
+            expected.agc_auto = profile == RxProfile::MaxSensitivity;
+        }
+    }
+}
+
+fn check_settings(lora: &mut MockLoRa, state: &Shared, expected: &ExpectedSettings) {
+    assert_eq!(lora.get_signal_bandwidth().unwrap(), expected.bandwidth);
+    assert_eq!(lora.get_spreading_factor().unwrap(), expected.spreading_factor);
+    assert_eq!(lora.get_coding_rate_4().unwrap(), expected.coding_rate);
+    assert_eq!(lora.get_crc_enabled().unwrap(), expected.crc);
+    assert_eq!(lora.get_symbol_timeout().unwrap(), expected.symbol_timeout);
+    assert_eq!(lora.get_preamble_length().unwrap(), expected.preamble_length);
+    match expected.max_power {
+        Some(max_power) => assert_eq!(lora.get_pa_max_power().unwrap(), max_power),
+        None => assert!(matches!(lora.get_pa_max_power(), Err(Error::InvalidInput))),
+    }
+
+    let modem_config_1 = state.borrow().reg(Register::RegModemConfig1);
+    let (implicit_header, agc_auto) = match lora.chip() {
+        Chip::Sx1276 => (modem_config_1 & 0x01 != 0, state.borrow().reg(Register::RegModemConfig3) & 0x04 != 0),
+        Chip::Sx1272 => (modem_config_1 & 0x04 != 0, state.borrow().reg(Register::RegModemConfig2) & 0x04 != 0),
+    };
+    assert_eq!(implicit_header, !expected.explicit_header);
+    assert_eq!(agc_auto, expected.agc_auto);
+
+    let symbol_duration_ms = 1000 / (expected.bandwidth / (1 << expected.spreading_factor));
+    assert_eq!(lora.get_low_data_rate_optimize().unwrap(), symbol_duration_ms > 16);
+}
+
+fn setters_keep_invariants(setters: Vec<Setter>, sx1272: bool, reserved: u8) {
+    let state = reset_state();
+    if sx1272 {
+        state.borrow_mut().set_reg(Register::RegVersion, 0x22);
+    }
+    let mut lora = LoRaBuilder::new(MockSpi(state.clone()), MockCs(state.clone()), MockReset, 915)
+        .build(&mut MockDelay::default())
+        .unwrap();
+    // Brings LowDataRateOptimize in line with the bandwidth and spreading factor
+    let sf = lora.get_spreading_factor().unwrap();
+    lora.set_spreading_factor(sf).unwrap();
+
+    let reserved_bits = reserved_bits(lora.chip());
+    for &(reg, mask) in reserved_bits {
+        let value = state.borrow().reg(reg);
+        state.borrow_mut().set_reg(reg, value & !mask | reserved & mask);
+    }
+    state.borrow_mut().writes.clear();
+
+    let mut expected = ExpectedSettings {
+        bandwidth: lora.get_signal_bandwidth().unwrap(),
+        spreading_factor: sf,
+        coding_rate: lora.get_coding_rate_4().unwrap(),
+        crc: lora.get_crc_enabled().unwrap(),
+        explicit_header: lora.explicit_header,
+        symbol_timeout: lora.get_symbol_timeout().unwrap(),
+        preamble_length: lora.get_preamble_length().unwrap(),
+        max_power: lora.get_pa_max_power().ok(),
+        agc_auto: true,
+    };
+    check_settings(&mut lora, &state, &expected);
+
+    for setter in &setters {
+        apply_setter(&mut lora, &mut expected, setter);
+        check_settings(&mut lora, &state, &expected);
+    }
+
+    for &(addr, value) in &state.borrow().writes {
+        for &(reg, mask) in reserved_bits {
+            if addr == reg as u8 {
+                assert_eq!(value & mask, reserved & mask, "reserved bits of {:#04x} written as {:#04x}", addr, value);
+            }
+        }
+    }
+}
+
+#[test]
+fn setters_keep_invariants_for_arbitrary_sequences() {
+    QuickCheck::new()
+        .tests(500)
+        .quickcheck(setters_keep_invariants as fn(Vec<Setter>, bool, u8));
+}
+
+#[test]
+fn scripted_transmission_end_to_end() {
+    let (mut lora, state) = radio(915);
+    state.borrow_mut().after_mode(RadioMode::Tx, 6, Event::TxDone);
+    lora.transmit_payload(b"ping").unwrap();
+    assert!(lora.transmitting().unwrap());
+
+    let mut clock = FakeClock { step_ms: 1, ..Default::default() };
+    lora.wait_for_tx_done_until(Some(100), &mut clock).unwrap();
+    assert!(clock.now_ms > 1);
+    let state = state.borrow();
+    assert_eq!(&state.fifo[..4], b"ping");
+    assert_eq!(state.reg(Register::RegOpMode), 0x81);
+    assert_eq!(state.reg(Register::RegIrqFlags), 0x00);
+}
+
+#[test]
+fn beacon_keeps_cadence() {
+    let (mut lora, state) = radio(915);
+    // Each beacon takes 3 polls of 1 ms to be sent
+    for _ in 0..3 {
+        state.borrow_mut().after_mode(RadioMode::Tx, 3, Event::TxDone);
+    }
+    let mut clock = FakeClock::default();
+    let mut count = 0u8;
+    lora.run_beacon_with_clock(&mut clock, 1000, |buffer| {
+        if count == 3 {
+            return ControlFlow::Break(());
+        }
+        count += 1;
+        buffer[..2].copy_from_slice(&[b'b', count]);
+        ControlFlow::Continue(2)
+    })
+    .unwrap();
+    // Started at 0, 1000 and 2000 ms, the airtime being taken from the sleep
+    assert_eq!(clock.now_ms, 3000);
+    {
+        let state = state.borrow();
+        assert_eq!(state.writes_to(Register::RegFifo), [b'b', 1, b'b', 2, b'b', 3]);
+        let sleeps = state.writes_to(Register::RegOpMode).iter().filter(|&&op_mode| op_mode == 0x80).count();
+        assert_eq!(sleeps, 3);
+    }
+
+    // A beacon still going after a period times out, the radio being taken out of TX
+    state.borrow_mut().after_mode(RadioMode::Tx, 1000, Event::TxDone);
+    let mut delay = MockDelay::default();
+    let result = lora.run_beacon(&mut delay, 20, |_| ControlFlow::Continue(1));
+    assert!(matches!(result, Err(Error::Timeout)));
+    assert_eq!(delay.elapsed_ms, 20);
+    assert_eq!(state.borrow().reg(Register::RegOpMode) & 0x07, 0x01);
+    assert!(matches!(lora.mode, RadioMode::Stdby));
+
+    // So does one still going after twice its time on air, within a long period
+    let (mut lora, state) = radio(915);
+    let airtime_ms = lora.time_on_air_ms(1).unwrap();
+    state.borrow_mut().after_mode(RadioMode::Tx, 1000, Event::TxDone);
+    let mut delay = MockDelay::default();
+    let result = lora.run_beacon(&mut delay, 10_000, |_| ControlFlow::Continue(1));
+    assert!(matches!(result, Err(Error::Timeout)));
+    assert_eq!(delay.elapsed_ms, 2 * airtime_ms + 1);
+    assert_eq!(state.borrow().reg(Register::RegOpMode) & 0x07, 0x01);
+    assert_eq!(state.borrow().reg(Register::RegIrqFlags), 0);
+    assert!(matches!(lora.run_beacon(&mut delay, 100, |_| ControlFlow::Continue(0)), Err(Error::InvalidInput)));
+}
+
+#[test]
+fn rx_windows_second_window_receives() {
+    let (mut lora, state) = radio(868);
+    let rx1 = ChannelConfig {
+        frequency_hz: 868_100_000,
+        spreading_factor: 7,
+        bandwidth: 125_000,
+        crc: false,
+        invert_iq: true,
+    };
+    let w1 = RxWindow { offset_ms: 1000, duration_ms: 20, channel: rx1 };
+    let w2 = RxWindow {
+        offset_ms: 2000,
+        duration_ms: 300,
+        channel: ChannelConfig { frequency_hz: 869_525_000, spreading_factor: 12, ..rx1 },
+    };
+    {
+        let mut state = state.borrow_mut();
+        state.after_mode(RadioMode::RxSingle, 5, Event::RxTimeout);
+        state.after_mode(RadioMode::RxSingle, 5, packet(b"downlink"));
+        state.set_reg(Register::RegPktRssiValue, 60);
+    }
+    let mut clock = FakeClock { now_ms: 500, ..Default::default() };
+    let (size, info) = lora.open_rx_windows_with_clock(&mut clock, 500, w1, Some(w2)).unwrap().unwrap();
+    assert_eq!(size, 8);
+    assert_eq!(info.rssi, -97);
+    // W2 opened 2 s after TxDone, the packet arriving within a few polls
+    assert!((2500..2510).contains(&clock.now_ms), "{}", clock.now_ms);
+    {
+        let state = state.borrow();
+        // 20 ms of 1.024 ms symbols at SF7, 300 ms of 32.768 ms symbols at SF12
+        assert_eq!(state.writes_to(Register::RegSymbTimeoutLsb), [19, 9]);
+        assert_eq!(state.reg(Register::RegFrfMsb), 0xd9);
+        assert_eq!(state.reg(Register::RegOpMode) & 0x07, 0x01);
+    }
+    let mut buffer = [0u8; 8];
+    assert_eq!(lora.read_packet_into(&mut buffer).unwrap(), 8);
+    assert_eq!(&buffer, b"downlink");
+
+    // Nothing in either window
+    {
+        let mut state = state.borrow_mut();
+        state.after_mode(RadioMode::RxSingle, 5, Event::RxTimeout);
+        state.after_mode(RadioMode::RxSingle, 5, Event::RxTimeout);
+    }
+    let mut delay = MockDelay::default();
+    assert_eq!(lora.open_rx_windows(&mut delay, w1, Some(w2)).unwrap(), None);
+    assert!(delay.elapsed_ms >= 2000);
+    assert_eq!(lora.open_rx_windows(&mut delay, w1, None).unwrap(), None);
+}
+
+#[test]
+fn afc_follows_drifting_transmitter() {
+    let (mut lora, state) = radio(915);
+    lora.enable_afc(2);
+    {
+        // FreqError of 7630, 1000 Hz at 125 kHz
+        let mut state = state.borrow_mut();
+        state.set_reg(Register::RegFreqErrorMid, 0x1d);
+        state.set_reg(Register::RegFreqErrorLsb, 0xce);
+    }
+    lora.arm_rx().unwrap();
+    let mut delay = MockDelay::default();
+    let mut buffer = [0u8; 8];
+    for (expected, crc_error) in [(&b"one"[..], false), (b"bad", true), (b"two", false)] {
+        state.borrow_mut().after(0, Event::Packet { payload: expected.to_vec(), crc_error });
+        lora.poll_irq(Some(100), &mut delay).unwrap();
+        assert_eq!(lora.frequency, 915_000_000);
+        let size = lora.read_packet_into(&mut buffer).unwrap();
+        assert_eq!(&buffer[..size], expected);
+    }
+    // Corrected once the second good packet was read, restarting the receiver on the new frequency
+    assert_eq!(lora.frequency, 915_001_000);
+    let state = state.borrow();
+    let frf = frf_from_hz(915_001_000, FXOSC_HZ);
+    assert_eq!(state.reg(Register::RegFrfLsb), frf as u8);
+    assert_eq!(state.reg(Register::RegOpMode), 0x85);
+    assert_eq!(state.writes_to(Register::RegOpMode).last(), Some(&0x85));
+    assert_eq!(state.writes_to(Register::RegOpMode).iter().rev().nth(1), Some(&0x81));
+}
+
+#[test]
+fn afc_clamps_at_band_edges() {
+    let top_hz = hz_from_frf(FRF_MAX, FXOSC_HZ);
+    // Crossing to the HF port, and leaving the Frf register
+    for (start_hz, clamped_hz) in [(525_000_000, 525_000_000), (top_hz - 500, top_hz)] {
+        let (mut lora, state) = radio(915);
+        lora.set_frequency_hz(start_hz).unwrap();
+        lora.enable_afc(1);
+        {
+            // FreqError of 7630, 1000 Hz at 125 kHz
+            let mut state = state.borrow_mut();
+            state.set_reg(Register::RegFreqErrorMid, 0x1d);
+            state.set_reg(Register::RegFreqErrorLsb, 0xce);
+        }
+        lora.arm_rx().unwrap();
+        let mut buffer = [0u8; 8];
+        for _ in 0..2 {
+            state.borrow_mut().after(0, packet(b"edge"));
+            lora.poll_irq(Some(100), &mut MockDelay::default()).unwrap();
+            assert_eq!(lora.read_packet_into(&mut buffer).unwrap(), 4);
+            assert_eq!(lora.frequency, clamped_hz);
+            assert!(matches!(lora.read_packet_into(&mut buffer), Err(Error::NoPacket)));
+        }
+        assert_eq!(lora.band(), Band::from_hz(start_hz));
+        assert_eq!(state.borrow().reg(Register::RegOpMode) & 0x07, 0x05);
+    }
+
+    // A retune failing on the bus neither fails the read nor stays due
+    let (mut lora, state) = radio(915);
+    lora.enable_afc(1);
+    {
+        let mut state = state.borrow_mut();
+        state.set_reg(Register::RegFreqErrorMid, 0x1d);
+        state.set_reg(Register::RegFreqErrorLsb, 0xce);
+    }
+    lora.arm_rx().unwrap();
+    state.borrow_mut().after(0, packet(b"one"));
+    lora.poll_irq(Some(100), &mut MockDelay::default()).unwrap();
+    let mut buffer = [0u8; 8];
+    // Five operations read the packet, the sixth starts the retune
+    let spi_ops = state.borrow().spi_ops;
+    state.borrow_mut().fail_spi_op = Some(spi_ops + 6);
+    assert_eq!(lora.read_packet_into(&mut buffer).unwrap(), 3);
+    assert_eq!(&buffer[..3], b"one");
+    // The retune stopped at its first operation, standby
+    assert_eq!(state.borrow().spi_ops, spi_ops + 6);
+    state.borrow_mut().fail_spi_op = None;
+    assert!(lora.take_afc_error());
+    assert!(!lora.take_afc_error());
+    assert!(matches!(lora.read_packet_into(&mut buffer), Err(Error::NoPacket)));
+    assert_eq!(lora.frequency, 915_000_000);
+
+    // Failing after standby, on the Frf write, the receiver is restarted on the old frequency
+    state.borrow_mut().after(0, packet(b"two"));
+    lora.poll_irq(Some(100), &mut MockDelay::default()).unwrap();
+    let spi_ops = state.borrow().spi_ops;
+    state.borrow_mut().fail_spi_op = Some(spi_ops + 7);
+    assert_eq!(lora.read_packet_into(&mut buffer).unwrap(), 3);
+    state.borrow_mut().fail_spi_op = None;
+    assert!(lora.take_afc_error());
+    assert_eq!(lora.frequency, 915_000_000);
+    assert!(state.borrow().writes_to(Register::RegOpMode).ends_with(&[0x81, 0x85]));
+    assert!(matches!(lora.mode, RadioMode::RxContinuous));
+}
+
+#[test]
+fn scripted_reception_end_to_end() {
+    let (mut lora, state) = radio(915);
+    state.borrow_mut().set_reg(Register::RegFifoRxBaseAddr, 0x80);
+    {
+        let mut state = state.borrow_mut();
+        state.after_mode(RadioMode::RxContinuous, 10, Event::Packet { payload: b"bad".to_vec(), crc_error: true });
+        state.after(10, packet(b"good"));
+    }
+    let mut delay = MockDelay::default();
+    let mut buffer = [0u8; 8];
+    assert_eq!(lora.receive(RxMode::Continuous, Some(100), &mut delay).unwrap(), 3);
+    let (_, info) = lora.read_packet_with_frame_info(&mut buffer).unwrap();
+    assert!(info.crc_error);
+    assert_eq!(&buffer[..3], b"bad");
+    assert_eq!(lora.receive(RxMode::Continuous, Some(100), &mut delay).unwrap(), 4);
+    assert_eq!(lora.read_packet_into(&mut buffer).unwrap(), 4);
+    assert_eq!(&buffer[..4], b"good");
+    assert_eq!(state.borrow().reg(Register::RegFifoRxCurrentAddr), 0x83);
+    assert_eq!(lora.take_rx_counters().unwrap(), (2, 1));
+
+    // RxSingle times out and returns to standby on its own
+    state.borrow_mut().after_mode(RadioMode::RxSingle, 3, Event::RxTimeout);
+    assert!(matches!(lora.receive(RxMode::Single, None, &mut delay), Err(Error::Timeout)));
+    assert_eq!(state.borrow().reg(Register::RegOpMode) & 0x07, 0x01);
+}
+
+#[test]
+fn dedup_filter_drops_recent_ids() {
+    let mut filter = DedupFilter::<2>::new(leading_u16);
+    assert!(!filter.is_duplicate(b"\x00\x01a"));
+    assert!(filter.is_duplicate(b"\x00\x01b"));
+    // Packets without an ID always pass
+    assert!(!filter.is_duplicate(b"x"));
+    assert!(!filter.is_duplicate(b"x"));
+    assert!(!filter.is_duplicate(b"\x00\x02"));
+    // The oldest ID is dropped once full
+    assert!(!filter.is_duplicate(b"\x00\x03"));
+    assert!(!filter.is_duplicate(b"\x00\x01"));
+    assert!(filter.is_duplicate(b"\x00\x03"));
+    assert_eq!(filter.len(), 2);
+
+    let (mut lora, state) = radio(915);
+    filter.clear();
+    lora.arm_rx().unwrap();
+    let mut buffer = [0u8; 8];
+    state.borrow_mut().after(0, packet(b"\x12\x34hi"));
+    assert_eq!(lora.receive_dedup_into(&mut buffer, &mut filter).unwrap(), Some(4));
+    state.borrow_mut().after(0, packet(b"\x12\x34hi"));
+    assert_eq!(lora.receive_dedup_into(&mut buffer, &mut filter).unwrap(), None);
+
+    // A corrupted packet is returned, its ID not remembered
+    state.borrow_mut().after(0, Event::Packet { payload: b"\x56\x78".to_vec(), crc_error: true });
+    assert_eq!(lora.receive_dedup_into(&mut buffer, &mut filter).unwrap(), Some(2));
+    assert!(!filter.contains(0x5678));
+    #[cfg(feature = "heapless")]
+    {
+        state.borrow_mut().after(0, packet(b"\x56\x78ok"));
+        assert_eq!(lora.receive_dedup(&mut filter).unwrap().unwrap().as_slice(), b"\x56\x78ok");
+        state.borrow_mut().after(0, packet(b"\x56\x78ok"));
+        assert_eq!(lora.receive_dedup(&mut filter).unwrap(), None);
+    }
+}
+
+type MockResult = Result<(), Error<MockError, MockError, MockError>>;
+
+/// An operation of `spi_faults_keep_cleanup_contract`, given the mock state to script the radio.
+type SpiFaultOperation = fn(&mut MockLoRa, &Shared) -> MockResult;
+
+/// Makes each SPI operation of `operation` fail in turn, from the first one until `operation`
+/// succeeds, checking the cleanup contract after every failure: the error is returned, CS is
+/// deasserted and the cached state is the one of the registers actually written.
+fn inject_spi_faults(name: &str, operation: SpiFaultOperation) {
+    for n in 1.. {
+        let (mut lora, state) = radio(915);
+        let fail_at = state.borrow().spi_ops + n;
+        state.borrow_mut().fail_spi_op = Some(fail_at);
+        let result = operation(&mut lora, &state);
+        if state.borrow().spi_ops < fail_at {
+            result.unwrap();
+            return;
+        }
+        let context = std::format!("{}, SPI operation {} failing", name, n);
+        assert!(matches!(result, Err(Error::SPI(MockError))), "{}", context);
+        state.borrow_mut().fail_spi_op = None;
+        let state = state.borrow();
+        assert!(!state.cs_low, "{}", context);
+        // The mock completes transmissions instantly, and RxSingle and CAD return to standby
+        // on their own
+        let op_mode = state.reg(Register::RegOpMode) & 0x07;
+        match lora.mode {
+            RadioMode::Tx => {}
+            RadioMode::RxSingle | RadioMode::Cad if op_mode == RadioMode::Stdby as u8 => {}
+            mode => assert_eq!(mode as u8, op_mode, "{}", context),
+        }
+        let frf = lora.checked_frf(lora.frequency).unwrap();
+        assert_eq!(
+            frf.to_be_bytes()[1..],
+            [state.reg(Register::RegFrfMsb), state.reg(Register::RegFrfMid), state.reg(Register::RegFrfLsb)],
+            "{}",
+            context
+        );
+        let implicit = state.reg(Register::RegModemConfig1) & 0x01 != 0;
+        assert_eq!(lora.explicit_header, !implicit, "{}", context);
+        if let Some(written) = lora.header_written {
+            assert_eq!(written, !implicit, "{}", context);
+        }
+        if let Some(pa_config) = state.writes_to(Register::RegPaConfig).last() {
+            assert_eq!(lora.pa_output == PaOutput::PaBoost, pa_config & 0x80 != 0, "{}", context);
+        }
+        assert!(lora.paused_rx.is_none() && lora.listen.is_none(), "{}", context);
+        // A CRC error is only remembered once the flags of its packet were cleared
+        if lora.rx_crc_error {
+            assert!(state.writes_to(Register::RegIrqFlags).iter().any(|flags| flags & 0x20 != 0), "{}", context);
+        }
+        // A packet is counted towards the frequency correction whole or not at all
+        assert!(
+            lora.afc_packets < lora.afc_every_n_packets.max(1) && (lora.afc_packets > 0 || lora.afc_sum_hz == 0),
+            "{}",
+            context
+        );
+        drop(state);
+        assert!(lora.verify_config().is_ok(), "{}", context);
+    }
+}
+
+fn listen_config() -> ListenConfig {
+    let rx = ChannelConfig {
+        frequency_hz: 869_525_000,
+        spreading_factor: 12,
+        bandwidth: 125_000,
+        crc: false,
+        invert_iq: true,
+    };
+    ListenConfig { rx, tx: rx }
+}
+
+#[test]
+fn spi_faults_keep_cleanup_contract() {
+    let operations: &[(&str, SpiFaultOperation)] = &[
+        ("set_frequency", |lora, _| lora.set_frequency(868)),
+        ("set_frequency_hz", |lora, _| lora.set_frequency_hz(433_175_000)),
+        ("set_mode", |lora, _| lora.set_mode(RadioMode::Sleep)),
+        ("set_modem_preset", |lora, _| lora.set_modem_preset(ModemPreset::ArduinoLoRa)),
+        ("set_spreading_factor", |lora, _| lora.set_spreading_factor(12)),
+        ("set_signal_bandwidth", |lora, _| lora.set_signal_bandwidth(62_500)),
+        ("set_crc", |lora, _| lora.set_crc(true)),
+        ("set_preamble_length", |lora, _| lora.set_preamble_length(12)),
+        ("set_invert_iq", |lora, _| lora.set_invert_iq(true)),
+        ("set_invert_iq_rx", |lora, _| lora.set_invert_iq_rx(true)),
+        ("set_invert_iq_tx", |lora, _| lora.set_invert_iq_tx(true)),
+        ("set_tx_power", |lora, _| lora.set_tx_power(20)),
+        ("set_tx_power_dbm", |lora, _| lora.set_tx_power_dbm(14, PaSelection::Rfo)),
+        ("set_ocp", |lora, _| lora.set_ocp(120)),
+        ("set_pa_dac", |lora, _| {
+            lora.set_tx_power_dbm(17, PaSelection::PaBoost)?;
+            lora.set_ocp(240)?;
+            lora.set_pa_dac(PaDac::HighPower)
+        }),
+        ("set_rx_profile", |lora, _| lora.set_rx_profile(RxProfile::LowPower)),
+        ("transmit_payload", |lora, _| lora.transmit_payload(b"hello")),
+        ("receive", |lora, state| {
+            lora.enable_afc(2);
+            state.borrow_mut().after_mode(RadioMode::RxSingle, 2, packet(b"hello"));
+            let mut buffer = [0; 8];
+            lora.receive(RxMode::Single, Some(100), &mut MockDelay::default())?;
+            lora.read_packet_into(&mut buffer).map(|_| ())
+        }),
+        ("cad", |lora, state| {
+            state.borrow_mut().after_mode(RadioMode::Cad, 2, Event::CadDone { detected: true });
+            lora.cad(&mut MockDelay::default()).map(|_| ())
+        }),
+        ("arm_rx", |lora, _| lora.arm_rx()),
+        ("pause_rx", |lora, _| {
+            lora.arm_rx()?;
+            lora.pause_rx()
+        }),
+        ("resume_rx", |lora, _| {
+            lora.arm_rx()?;
+            lora.pause_rx()?;
+            lora.resume_rx()
+        }),
+        ("take_rx_counters", |lora, _| {
+            lora.arm_rx()?;
+            lora.take_rx_counters().map(|_| ())
+        }),
+        ("configure_lorawan_uplink", |lora, _| lora.configure_lorawan_uplink(868_100_000, 9, 125_000)),
+        ("continuous_listen", |lora, _| lora.continuous_listen(listen_config())),
+        ("interrupt_for_tx", |lora, _| {
+            lora.continuous_listen(listen_config())?;
+            let result = lora.interrupt_for_tx(b"hello", &mut MockDelay::default());
+            // The listen settings are kept on failure as well, to listen again
+            lora.listen = None;
+            result
+        }),
+        ("run_beacon", |lora, _| {
+            let mut beacons = 0;
+            lora.run_beacon(&mut MockDelay::default(), 100, |buffer| {
+                beacons += 1;
+                if beacons > 2 {
+                    return ControlFlow::Break(());
+                }
+                buffer[0] = beacons;
+                ControlFlow::Continue(1)
+            })
+        }),
+        ("open_rx_windows", |lora, state| {
+            let channel = listen_config().rx;
+            let w1 = RxWindow { offset_ms: 1000, duration_ms: 20, channel };
+            let w2 = RxWindow {
+                offset_ms: 2000,
+                duration_ms: 20,
+                channel: ChannelConfig { spreading_factor: 9, ..channel },
+            };
+            state.borrow_mut().after_mode(RadioMode::RxSingle, 5, Event::RxTimeout);
+            state.borrow_mut().after_mode(RadioMode::RxSingle, 5, packet(b"downlink"));
+            lora.open_rx_windows(&mut MockDelay::default(), w1, Some(w2)).map(|_| ())
+        }),
+    ];
+    for &(name, operation) in operations {
+        inject_spi_faults(name, operation);
+    }
+}
+
+#[test]
+fn spi_fault_keeps_packet_pending() {
+    for n in 1.. {
+        let (mut lora, state) = radio(915);
+        lora.arm_rx().unwrap();
+        state.borrow_mut().after(0, packet(b"kept"));
+        let fail_at = state.borrow().spi_ops + n;
+        state.borrow_mut().fail_spi_op = Some(fail_at);
+        let mut buffer = [0u8; 8];
+        let result = lora.read_packet_into(&mut buffer);
+        if state.borrow().spi_ops < fail_at {
+            assert_eq!(result.unwrap(), 4);
+            return;
+        }
+        assert!(matches!(result, Err(Error::SPI(MockError))), "SPI operation {}", n);
+        state.borrow_mut().fail_spi_op = None;
+        buffer = [0; 8];
+        assert_eq!(lora.read_packet_into(&mut buffer).unwrap(), 4, "SPI operation {}", n);
+        assert_eq!(&buffer[..4], b"kept");
+    }
+}
+
+#[test]
+fn set_mode_writes_header_mode_only_when_changed() {
+    let (mut lora, state) = radio(915);
+    lora.set_mode(RadioMode::RxContinuous).unwrap();
+    lora.set_mode(RadioMode::Stdby).unwrap();
+    assert_eq!(state.borrow().writes, [(0x01, 0x85), (0x01, 0x81)]);
+
+    state.borrow_mut().writes.clear();
+    lora.explicit_header = false;
+    lora.set_mode(RadioMode::Tx).unwrap();
+    lora.set_mode(RadioMode::Stdby).unwrap();
+    assert_eq!(state.borrow().writes, [(0x1d, 0x73), (0x01, 0x83), (0x01, 0x81)]);
+}
+
+#[test]
+fn tx_power_dbm_register_table() {
+    let selections = [
+        PaSelection::Rfo,
+        PaSelection::Auto { pa_boost_wired: false },
+        PaSelection::PaBoost,
+        PaSelection::Auto { pa_boost_wired: true },
+    ];
+    for selection in selections {
+        let pa_boost = matches!(selection, PaSelection::PaBoost | PaSelection::Auto { pa_boost_wired: true });
+        let (mut lora, state) = radio(915);
+        for dbm in -4..=20 {
+            // RegPaConfig, RegPaDac and RegOcp, MaxPower staying at the 7 set by init on PA_BOOST
+            let expected = match (pa_boost, dbm) {
+                (false, -4..=-1) => Some(((dbm + 4) as u8, 0x84, 0x2b)),
+                (false, 0..=14) => Some((0x70 | dbm as u8, 0x84, 0x2b)),
+                (true, 2..=17) => Some((0xf0 | (dbm - 2) as u8, 0x84, 0x2b)),
+                (true, 18..=20) => Some((0xf0 | (dbm - 5) as u8, 0x87, 0x31)),
+                _ => None,
+            };
+            state.borrow_mut().writes.clear();
+            let result = lora.set_tx_power_dbm(dbm, selection);
+            let context = std::format!("{:?} {} dBm", selection, dbm);
+            let state = state.borrow();
+            match expected {
+                Some(expected) => {
+                    result.unwrap();
+                    let actual = (
+                        state.reg(Register::RegPaConfig),
+                        state.reg(Register::RegPaDac),
+                        state.reg(Register::RegOcp),
+                    );
+                    assert_eq!(actual, expected, "{}", context);
+                }
+                None => {
+                    assert!(matches!(result, Err(Error::InvalidInput)), "{}", context);
+                    assert!(state.writes.is_empty(), "{}", context);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn pa_dac_high_power_conflicts() {
+    let (mut lora, state) = radio(915);
+    let conflict = |result: Result<(), Error<MockError, MockError, MockError>>| match result {
+        Err(Error::HighPowerConflict(setting)) => setting,
+        other => panic!("{:?}", other),
+    };
+    // RFO selected, then the over current protection at 100 mA
+    assert_eq!(conflict(lora.set_pa_dac(PaDac::HighPower)), PowerSetting::Rfo);
+    state.borrow_mut().set_reg(Register::RegPaConfig, 0x8f);
+    assert_eq!(conflict(lora.set_pa_dac(PaDac::HighPower)), PowerSetting::Ocp);
+    assert!(state.borrow().writes.is_empty());
+    // No limit with the over current protection off
+    state.borrow_mut().set_reg(Register::RegOcp, 0x1f);
+    lora.set_pa_dac(PaDac::HighPower).unwrap();
+    assert_eq!(lora.get_pa_dac().unwrap(), PaDac::HighPower);
+
+    lora.set_tx_power_dbm(20, PaSelection::PaBoost).unwrap();
+    assert_eq!(state.borrow().reg(Register::RegOcp), 0x31);
+    state.borrow_mut().writes.clear();
+    assert_eq!(conflict(lora.set_ocp(120)), PowerSetting::Ocp);
+    assert_eq!(conflict(lora.update_pa_config(false, 0x0f)), PowerSetting::Rfo);
+    assert!(state.borrow().writes.is_empty());
+    lora.set_ocp(240).unwrap();
+
+    // The power setters leave the high power mode before breaking its conditions
+    lora.set_tx_power_dbm(14, PaSelection::Rfo).unwrap();
+    assert_eq!(state.borrow().reg(Register::RegPaDac), 0x84);
+    lora.set_tx_power_dbm(20, PaSelection::PaBoost).unwrap();
+    lora.pa_output = PaOutput::Rfo;
+    lora.set_tx_power(10).unwrap();
+    assert_eq!(state.borrow().reg(Register::RegPaDac), 0x84);
+    assert_eq!(state.borrow().reg(Register::RegPaConfig) & 0x80, 0x00);
+}
+
+#[test]
+fn pll_timeout_retries_then_fails() {
+    let (mut lora, state) = radio(915);
+    let payload = *b"data";
+    // Locks on the second attempt, going through standby in between
+    state.borrow_mut().queue_reads(Register::RegHopChannel, &[0x80, 0x00]);
+    lora.transmit_payload(&payload).unwrap();
+    assert!(state.borrow().reads[&(Register::RegHopChannel as u8)].is_empty());
+    let modes: Vec<u8> = state.borrow().writes_to(Register::RegOpMode).iter().map(|mode| mode & 0x07).collect();
+    assert!(modes.ends_with(&[0x03, 0x01, 0x03]));
+
+    // Never locks, the radio is left in standby
+    let (mut lora, state) = radio(915);
+    state.borrow_mut().set_reg(Register::RegHopChannel, 0x80);
+    assert!(matches!(lora.transmit_payload(&payload), Err(Error::PllLockFailed)));
+    assert_eq!(state.borrow().reg(Register::RegOpMode) & 0x07, 0x01);
+    assert!(matches!(lora.mode, RadioMode::Stdby));
+    let mut delay = MockDelay::default();
+    assert!(matches!(
+        lora.receive(RxMode::Continuous, Some(10), &mut delay),
+        Err(Error::PllLockFailed)
+    ));
+    assert_eq!(state.borrow().reg(Register::RegOpMode) & 0x07, 0x01);
+    assert!(matches!(lora.mode, RadioMode::Stdby));
+}
+
+#[test]
+fn read_rx_stream_wraps_around_the_fifo() {
+    let (mut lora, state) = radio(915);
+    state.borrow_mut().set_reg(Register::RegFifoRxBaseAddr, 0xfa);
+    lora.arm_rx().unwrap();
+    {
+        let mut state = state.borrow_mut();
+        // Received from 0xfa to 0x05, the first 8 bytes having arrived at first
+        state.after(0, packet(b"hello, world"));
+        state.queue_reads(Register::RegIrqFlags, &[0x00, 0x00]);
+        state.queue_reads(Register::RegFifoRxByteAddr, &[0x02, 0x02, 0x02, 0x02]);
+    }
+    let mut buffer = [0; 16];
+    assert_eq!(lora.rx_bytes_available().unwrap(), 8);
+    assert_eq!(lora.read_rx_stream(&mut buffer[..5]).unwrap(), 5);
+    assert_eq!(&buffer[..5], b"hello");
+    assert_eq!(lora.rx_bytes_available().unwrap(), 3);
+    assert_eq!(lora.read_rx_stream(&mut buffer).unwrap(), 3);
+    assert_eq!(&buffer[..3], b", w");
+    assert!(state.borrow().reads.values().all(|reads| reads.is_empty()));
+    // The rest of the packet, then nothing until the next one
+    assert_eq!(lora.rx_bytes_available().unwrap(), 4);
+    assert_eq!(lora.read_rx_stream(&mut buffer).unwrap(), 4);
+    assert_eq!(&buffer[..4], b"orld");
+    assert_eq!(lora.read_rx_stream(&mut buffer).unwrap(), 0);
+    assert_eq!(lora.rx_bytes_available().unwrap(), 0);
+
+    // The packet can still be read whole, and the next one streams from where it ended
+    assert_eq!(lora.read_packet_into(&mut buffer).unwrap(), 12);
+    assert_eq!(&buffer[..12], b"hello, world");
+    state.borrow_mut().after(0, packet(b"again"));
+    assert_eq!(lora.rx_bytes_available().unwrap(), 5);
+    assert_eq!(lora.read_rx_stream(&mut buffer).unwrap(), 5);
+    assert_eq!(&buffer[..5], b"again");
+    assert_eq!(lora.read_rx_stream(&mut buffer).unwrap(), 0);
+}
+
+#[test]
+fn stream_receiver_reassembles_fragments() {
+    let (mut lora, state) = radio(915);
+    lora.arm_rx().unwrap();
+    let mut message = [0u8; 16];
+    let mut stream = StreamReceiver::new(&mut message, 4);
+    assert!(!stream.is_complete());
+    assert_eq!(stream.missing().count(), 0);
+    // Out of order, with a duplicate, the last fragment being shorter
+    let mut buffer = [0u8; 8];
+    for fragment in [&b"\x01\x03defg"[..], b"\x02\x03hi", b"\x01\x03defg", b"\x00\x03abc"] {
+        state.borrow_mut().after(0, packet(fragment));
+        let size = lora.read_packet_into(&mut buffer).unwrap();
+        let result = stream.feed(&buffer[..size]);
+        if fragment[0] == 0 {
+            // Too short for a fragment other than the last, nothing is kept
+            assert_eq!(result, Err(StreamError::InvalidLength));
+            assert_eq!(stream.missing().collect::<Vec<_>>(), [0]);
+        } else {
+            result.unwrap();
+        }
+    }
+    assert!(stream.data().is_none());
+    stream.feed(b"\x00\x03abcd").unwrap();
+    assert!(stream.is_complete());
+    assert_eq!(stream.data(), Some(&b"abcddefghi"[..]));
+
+    // End of stream, then the next message in the same buffer
+    stream.reset();
+    assert!(stream.data().is_none());
+    stream.feed(b"\x00\x01xy").unwrap();
+    assert_eq!(stream.data(), Some(&b"xy"[..]));
+}
+
+#[test]
+fn stream_receiver_rejects_bad_fragments() {
+    let mut message = [0u8; 8];
+    let mut stream = StreamReceiver::new(&mut message, 4);
+    assert_eq!(stream.feed(b"\x00"), Err(StreamError::TooShort));
+    assert_eq!(stream.feed(b"\x00\x00"), Err(StreamError::InvalidHeader));
+    assert_eq!(stream.feed(b"\x02\x02ab"), Err(StreamError::InvalidHeader));
+    assert_eq!(stream.feed(b"\x00\x02abcde"), Err(StreamError::InvalidLength));
+    assert_eq!(stream.feed(b"\x02\x03ab"), Err(StreamError::BufferTooSmall));
+    // None of these announced the total
+    assert_eq!(stream.missing().count(), 0);
+
+    stream.feed(b"\x00\x02abcd").unwrap();
+    assert_eq!(stream.feed(b"\x01\x03ef"), Err(StreamError::TotalMismatch));
+    assert_eq!(stream.missing().collect::<Vec<_>>(), [1]);
+    stream.feed(b"\x01\x02ef").unwrap();
+    assert_eq!(stream.data(), Some(&b"abcdef"[..]));
+}
+
+#[test]
+fn agc_config_packing() {
+    let (mut lora, state) = radio(915);
+    {
+        let mut state = state.borrow_mut();
+        // Bits outside the fields
+        state.set_reg(Register::RegAgcRef, 0xc0);
+        state.set_reg(Register::RegAgcThresh1, 0xe0);
+    }
+    let config = AgcConfig { reference_level: 0x2a, step1: 0x15, step2: 0x1, step3: 0xe, step4: 0xf, step5: 0x3 };
+    lora.set_agc_config(config).unwrap();
+    {
+        let state = state.borrow();
+        assert_eq!(state.reg(Register::RegAgcRef), 0xea);
+        assert_eq!(state.reg(Register::RegAgcThresh1), 0xf5);
+        assert_eq!(state.reg(Register::RegAgcThresh2), 0x1e);
+        assert_eq!(state.reg(Register::RegAgcThresh3), 0xf3);
+    }
+    assert_eq!(lora.get_agc_config().unwrap(), config);
+
+    // Fields too wide for their bits
+    state.borrow_mut().writes.clear();
+    for invalid in [
+        AgcConfig { reference_level: 0x40, ..config },
+        AgcConfig { step1: 0x20, ..config },
+        AgcConfig { step2: 0x10, ..config },
+        AgcConfig { step3: 0x10, ..config },
+        AgcConfig { step4: 0x10, ..config },
+        AgcConfig { step5: 0x10, ..config },
+    ] {
+        assert!(matches!(lora.set_agc_config(invalid), Err(Error::InvalidInput)), "{:?}", invalid);
+    }
+    assert!(state.borrow().writes.is_empty());
+}
+
+#[test]
+fn rx_profile_registers() {
+    let profiles = [
+        (RxProfile::MaxSensitivity, 0x3f, 0xff),
+        (RxProfile::LowPower, 0x7c, 0xfb),
+        (RxProfile::Custom { lna_gain: 6, lna_boost: true, agc_auto: false }, 0xdf, 0xfb),
+        (RxProfile::Custom { lna_gain: 2, lna_boost: false, agc_auto: true }, 0x5c, 0xff),
+    ];
+    for (profile, lna, modem_config_3) in profiles {
+        let (mut lora, state) = radio(915);
+        {
+            let mut state = state.borrow_mut();
+            // LnaBoostLf and the reserved bits, LowDataRateOptimize and the unused bits
+            state.set_reg(Register::RegLna, 0x1c);
+            state.set_reg(Register::RegModemConfig3, 0xfb);
+        }
+        lora.set_rx_profile(profile).unwrap();
+        let state = state.borrow();
+        assert_eq!(state.reg(Register::RegLna), lna, "{:?}", profile);
+        assert_eq!(state.reg(Register::RegModemConfig3), modem_config_3, "{:?}", profile);
+    }
+
+    let (mut lora, state) = radio(915);
+    state.borrow_mut().writes.clear();
+    for lna_gain in [0, 7] {
+        let profile = RxProfile::Custom { lna_gain, lna_boost: false, agc_auto: false };
+        assert!(matches!(lora.set_rx_profile(profile), Err(Error::InvalidInput)));
+    }
+    assert!(state.borrow().writes.is_empty());
+}
+
+#[test]
+fn pll_bandwidth_keeps_low_bits() {
+    let (mut lora, state) = radio(915);
+    // Reset value, bits 5-0 being reserved
+    state.borrow_mut().set_reg(Register::RegPll, 0xd0);
+    assert_eq!(lora.get_pll_bandwidth().unwrap(), PllBandwidth::Khz300);
+    for (bw, pll) in [
+        (PllBandwidth::Khz75, 0x10),
+        (PllBandwidth::Khz150, 0x50),
+        (PllBandwidth::Khz225, 0x90),
+        (PllBandwidth::Khz300, 0xd0),
+    ] {
+        lora.set_pll_bandwidth(bw).unwrap();
+        assert_eq!(state.borrow().reg(Register::RegPll), pll, "{:?}", bw);
+        assert_eq!(lora.get_pll_bandwidth().unwrap(), bw);
+    }
+    state.borrow_mut().set_reg(Register::RegPll, 0x2b);
+    lora.set_pll_bandwidth(PllBandwidth::Khz225).unwrap();
+    assert_eq!(state.borrow().reg(Register::RegPll), 0xab);
+
+    // RegPll is at 0x5c on the SX1272, 0x70 being unused
+    let state = reset_state();
+    state.borrow_mut().set_reg(Register::RegVersion, 0x22);
+    state.borrow_mut().set_reg(Register::RegPllSx1272, 0xd0);
+    let mut lora = LoRaBuilder::new(MockSpi(state.clone()), MockCs(state.clone()), MockReset, 915)
+        .build(&mut MockDelay::default())
+        .unwrap();
+    assert_eq!(lora.get_pll_bandwidth().unwrap(), PllBandwidth::Khz300);
+    lora.set_pll_bandwidth(PllBandwidth::Khz75).unwrap();
+    assert_eq!(state.borrow().reg(Register::RegPllSx1272), 0x10);
+    assert!(state.borrow().writes_to(Register::RegPll).is_empty());
+    lora.verify_config().unwrap();
+}
+
+#[test]
+fn hop_channel_bits_decode_independently() {
+    let (mut lora, state) = radio(915);
+    for bits in 0..=255u8 {
+        state.borrow_mut().set_reg(Register::RegHopChannel, bits);
+        // PllTimeout in bit 7, CrcOnPayload in bit 6, FhssPresentChannel in bits 5-0
+        assert_eq!(lora.get_pll_timeout().unwrap(), bits & 0x80 != 0, "{:#04x}", bits);
+        assert_eq!(lora.get_rx_header_info().unwrap().crc_on_payload, bits & 0x40 != 0, "{:#04x}", bits);
+        assert_eq!(lora.get_hop_channel().unwrap(), bits & 0x3f, "{:#04x}", bits);
+    }
+}
+
+#[test]
+fn payload_too_long_is_dropped() {
+    let (mut lora, state) = radio(915);
+    let mut buffer = [0u8; 16];
+    lora.set_max_payload_length(4).unwrap();
+    lora.arm_rx().unwrap();
+    state.borrow_mut().after(0, packet(b"too long"));
+    assert!(matches!(lora.read_packet_into(&mut buffer), Err(Error::PayloadTooLong)));
+    // The flags of the packet are cleared, so it isn't reported again
+    assert_eq!(state.borrow().reg(Register::RegIrqFlags), 0x00);
+    assert!(matches!(lora.read_packet_into(&mut buffer), Err(Error::NoPacket)));
+    state.borrow_mut().after(0, packet(b"ok"));
+    assert_eq!(lora.read_packet_into(&mut buffer).unwrap(), 2);
+    assert_eq!(&buffer[..2], b"ok");
+
+    // Same when waiting for the packet
+    let mut delay = MockDelay::default();
+    state.borrow_mut().after_mode(RadioMode::RxSingle, 2, packet(b"longer"));
+    assert!(matches!(lora.receive(RxMode::Single, Some(100), &mut delay), Err(Error::PayloadTooLong)));
+    assert_eq!(state.borrow().reg(Register::RegIrqFlags), 0x00);
+    // The radio left RxSingle with RxDone
+    assert_eq!(state.borrow().reg(Register::RegOpMode) & 0x07, 0x01);
+    assert!(matches!(lora.mode, RadioMode::Stdby));
+}
+
+#[test]
+fn rx_header_info_decoding() {
+    let (mut lora, state) = radio(915);
+    for coding_rate in 1..=4u8 {
+        for (modem_stat_low, hop_channel) in [(0x00, 0x40), (0x1f, 0xbf), (0x10, 0x00)] {
+            {
+                let mut state = state.borrow_mut();
+                // RxCodingRate in bits 7-5, the modem status bits below
+                state.set_reg(Register::RegModemStat, coding_rate << 5 | modem_stat_low);
+                state.set_reg(Register::RegHopChannel, hop_channel);
+                state.set_reg(Register::RegRxNbBytes, 0x2a);
+            }
+            assert_eq!(
+                lora.get_rx_header_info().unwrap(),
+                RxHeaderInfo {
+                    coding_rate: coding_rate + 4,
+                    crc_on_payload: hop_channel & 0x40 != 0,
+                    payload_length: 0x2a,
+                },
+                "4/{} {:#04x} {:#04x}",
+                coding_rate + 4,
+                modem_stat_low,
+                hop_channel
+            );
+        }
+    }
+}
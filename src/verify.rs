@@ -0,0 +1,124 @@
+//! Detection of configuration registers changed behind the driver's back, e.g. by an ESD event or
+//! a brown-out, see `LoRa::verify_config`, `LoRa::find_config_mismatches` and
+//! `LoRa::repair_config`.
+
+use crate::register::Register;
+
+/// Configuration registers whose last written value is remembered, with the mask of the bits
+/// compared. The LNA gain bits of RegLna read back the gain picked by the AGC, so they are not
+/// compared. Status registers, the FIFO and per-packet registers such as RegPayloadLength are not
+/// part of the configuration.
+const CONFIG_REGISTERS: [(Register, u8); 30] = [
+    (Register::RegFrfMsb, 0xff),
+    (Register::RegFrfMid, 0xff),
+    (Register::RegFrfLsb, 0xff),
+    (Register::RegPaConfig, 0xff),
+    (Register::RegPaRamp, 0xff),
+    (Register::RegOcp, 0xff),
+    (Register::RegLna, 0x1f),
+    (Register::RegFifoTxBaseAddr, 0xff),
+    (Register::RegFifoRxBaseAddr, 0xff),
+    (Register::RegModemConfig1, 0xff),
+    (Register::RegModemConfig2, 0xff),
+    (Register::RegSymbTimeoutLsb, 0xff),
+    (Register::RegPreambleMsb, 0xff),
+    (Register::RegPreambleLsb, 0xff),
+    (Register::RegMaxPayloadLength, 0xff),
+    (Register::RegHopPeriod, 0xff),
+    (Register::RegModemConfig3, 0xff),
+    (Register::RegIfFreq1, 0xff),
+    (Register::RegIfFreq2, 0xff),
+    (Register::RegDetectionOptimize, 0xff),
+    (Register::RegInvertiq, 0xff),
+    (Register::RegDetectionThreshold, 0xff),
+    (Register::RegSyncWord, 0xff),
+    (Register::RegInvertiq2, 0xff),
+    (Register::RegDioMapping1, 0xff),
+    (Register::RegDioMapping2, 0xff),
+    (Register::RegPaDac, 0xff),
+    (Register::RegPaDacSx1272, 0xff),
+    (Register::RegPllSx1272, 0xff),
+    (Register::RegPll, 0xff),
+];
+
+/// A configuration register whose value differs from the one last written.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RegisterMismatch {
+    /// Address of the register.
+    pub register: u8,
+    /// Value last written by the driver.
+    pub expected: u8,
+    /// Value read from the radio.
+    pub actual: u8,
+}
+
+/// The configuration registers found changed by `LoRa::find_config_mismatches`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ConfigMismatches {
+    mismatches: [RegisterMismatch; CONFIG_REGISTERS.len()],
+    len: usize,
+}
+
+impl ConfigMismatches {
+    pub(crate) fn new() -> Self {
+        ConfigMismatches {
+            mismatches: [RegisterMismatch { register: 0, expected: 0, actual: 0 }; CONFIG_REGISTERS.len()],
+            len: 0,
+        }
+    }
+
+    pub(crate) fn push(&mut self, mismatch: RegisterMismatch) {
+        self.mismatches[self.len] = mismatch;
+        self.len += 1;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &RegisterMismatch> {
+        self.mismatches[..self.len].iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// The values last written to the configuration registers.
+#[derive(Clone, Copy)]
+pub(crate) struct ConfigShadow {
+    values: [u8; CONFIG_REGISTERS.len()],
+    written: u32,
+}
+
+impl ConfigShadow {
+    pub(crate) const fn new() -> Self {
+        ConfigShadow {
+            values: [0; CONFIG_REGISTERS.len()],
+            written: 0,
+        }
+    }
+
+    /// Returns the configuration registers.
+    pub(crate) fn registers() -> impl Iterator<Item = Register> {
+        CONFIG_REGISTERS.iter().map(|&(reg, _)| reg)
+    }
+
+    /// Remembers a value written to `reg` if it is a configuration register.
+    pub(crate) fn record(&mut self, reg: Register, value: u8) {
+        if let Some(index) = CONFIG_REGISTERS.iter().position(|&(config, _)| config as u8 == reg as u8) {
+            self.values[index] = value;
+            self.written |= 1 << index;
+        }
+    }
+
+    /// Returns the configuration registers written so far, with their value and compared bits.
+    pub(crate) fn entries(&self) -> impl Iterator<Item = (Register, u8, u8)> + '_ {
+        CONFIG_REGISTERS
+            .iter()
+            .enumerate()
+            .filter(move |(index, _)| self.written & (1 << index) != 0)
+            .map(move |(index, &(reg, mask))| (reg, self.values[index], mask))
+    }
+}